@@ -3,46 +3,1115 @@
 //! [dependencies]
 //! fnv = "1.0.7"
 //! nohash-hasher = "0.2.0"
+//! unicode-normalization = "0.1.22"
 //! term_macros = { path = "../../shared/term_macros"  }
+//! parsers = { path = "../../experimental/parsers"  }
 //! ```
 use term_macros::*;
 //use genawaiter::stack::let_gen;
 //use genawaiter::yield_;
 use fnv::FnvHasher;
+use std::borrow::Cow;
 use std::hash::Hash;
 use std::hash::Hasher;
-use nohash_hasher::IntSet;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use nohash_hasher::{IntMap, IntSet};
+use term_macros::inputs::Source;
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+// by default only exact duplicates go. --fuzzy drops near-duplicates too: lines
+// whose shingles (runs of --shingle characters, or of words with --word_shingles) overlap by at least --threshold,
+// as a jaccard similarity. each line gets a minhash signature of --permutations hashes, cut into bands so that only
+// lines sharing a whole band get compared; one is a duplicate if its signature matches an earlier kept line's in
+// at least --threshold of them. --clusters writes every line, prefixed by the id of the kept line it duplicates
+// (or its own, if it was kept) and a tab, instead of dropping any.
+// --state seen.bin also drops lines seen by earlier runs (a comma-separated list takes in several, say from other
+// machines), and --update writes back to the first of them everything seen once the input runs out (so not when
+// serving or in --sync_mode, where it never does). a state file is a header saying how lines were keyed, since
+// hashes only match under the same key, then the sorted hashes.
+// --backend picks how the lines seen so far are remembered:
+//   hashset   their 64-bit hashes, in memory; two different lines can share one, and then the second is dropped
+//   bloom     a bloom filter taking --max_memory, so about --fp_rate of new lines are dropped as if seen, a rate
+//             that climbs once more lines have gone in than it was sized for (it says when)
+//   external  exact, in about --max_memory: every line's hash and position are sorted on disk in --spill_dir,
+//             lines sharing a hash are read back and compared byte for byte, and the input is read again to write
+//             the lines that are left in their original order. stdin and compressed inputs are copied there first.
 
 fn no_punctuation(w: &[u8]) -> Vec<u8> {
     w.iter().cloned().filter(|c| !c.is_ascii_punctuation()).collect()
 }
 
+/// Where full case folding goes further than lowercasing, by lowercase character, from CaseFolding.txt (apart from
+/// Cherokee, which casefold() maps by range).
+const FOLDS: &[(char, &str)] = &[
+    ('\u{b5}', "\u{3bc}"), ('\u{df}', "ss"), ('\u{149}', "\u{2bc}n"), ('\u{17f}', "s"), ('\u{1f0}', "j\u{30c}"),
+    ('\u{345}', "\u{3b9}"), ('\u{390}', "\u{3b9}\u{308}\u{301}"), ('\u{3b0}', "\u{3c5}\u{308}\u{301}"),
+    ('\u{3c2}', "\u{3c3}"), ('\u{3d0}', "\u{3b2}"), ('\u{3d1}', "\u{3b8}"), ('\u{3d5}', "\u{3c6}"),
+    ('\u{3d6}', "\u{3c0}"), ('\u{3f0}', "\u{3ba}"), ('\u{3f1}', "\u{3c1}"), ('\u{3f5}', "\u{3b5}"),
+    ('\u{587}', "\u{565}\u{582}"), ('\u{1c80}', "\u{432}"), ('\u{1c81}', "\u{434}"), ('\u{1c82}', "\u{43e}"),
+    ('\u{1c83}', "\u{441}"), ('\u{1c84}', "\u{442}"), ('\u{1c85}', "\u{442}"), ('\u{1c86}', "\u{44a}"),
+    ('\u{1c87}', "\u{463}"), ('\u{1c88}', "\u{a64b}"), ('\u{1e96}', "h\u{331}"), ('\u{1e97}', "t\u{308}"),
+    ('\u{1e98}', "w\u{30a}"), ('\u{1e99}', "y\u{30a}"), ('\u{1e9a}', "a\u{2be}"), ('\u{1e9b}', "\u{1e61}"),
+    ('\u{1f50}', "\u{3c5}\u{313}"), ('\u{1f52}', "\u{3c5}\u{313}\u{300}"), ('\u{1f54}', "\u{3c5}\u{313}\u{301}"),
+    ('\u{1f56}', "\u{3c5}\u{313}\u{342}"), ('\u{1f80}', "\u{1f00}\u{3b9}"), ('\u{1f81}', "\u{1f01}\u{3b9}"),
+    ('\u{1f82}', "\u{1f02}\u{3b9}"), ('\u{1f83}', "\u{1f03}\u{3b9}"), ('\u{1f84}', "\u{1f04}\u{3b9}"),
+    ('\u{1f85}', "\u{1f05}\u{3b9}"), ('\u{1f86}', "\u{1f06}\u{3b9}"), ('\u{1f87}', "\u{1f07}\u{3b9}"),
+    ('\u{1f90}', "\u{1f20}\u{3b9}"), ('\u{1f91}', "\u{1f21}\u{3b9}"), ('\u{1f92}', "\u{1f22}\u{3b9}"),
+    ('\u{1f93}', "\u{1f23}\u{3b9}"), ('\u{1f94}', "\u{1f24}\u{3b9}"), ('\u{1f95}', "\u{1f25}\u{3b9}"),
+    ('\u{1f96}', "\u{1f26}\u{3b9}"), ('\u{1f97}', "\u{1f27}\u{3b9}"), ('\u{1fa0}', "\u{1f60}\u{3b9}"),
+    ('\u{1fa1}', "\u{1f61}\u{3b9}"), ('\u{1fa2}', "\u{1f62}\u{3b9}"), ('\u{1fa3}', "\u{1f63}\u{3b9}"),
+    ('\u{1fa4}', "\u{1f64}\u{3b9}"), ('\u{1fa5}', "\u{1f65}\u{3b9}"), ('\u{1fa6}', "\u{1f66}\u{3b9}"),
+    ('\u{1fa7}', "\u{1f67}\u{3b9}"), ('\u{1fb2}', "\u{1f70}\u{3b9}"), ('\u{1fb3}', "\u{3b1}\u{3b9}"),
+    ('\u{1fb4}', "\u{3ac}\u{3b9}"), ('\u{1fb6}', "\u{3b1}\u{342}"), ('\u{1fb7}', "\u{3b1}\u{342}\u{3b9}"),
+    ('\u{1fbe}', "\u{3b9}"), ('\u{1fc2}', "\u{1f74}\u{3b9}"), ('\u{1fc3}', "\u{3b7}\u{3b9}"),
+    ('\u{1fc4}', "\u{3ae}\u{3b9}"), ('\u{1fc6}', "\u{3b7}\u{342}"), ('\u{1fc7}', "\u{3b7}\u{342}\u{3b9}"),
+    ('\u{1fd2}', "\u{3b9}\u{308}\u{300}"), ('\u{1fd3}', "\u{3b9}\u{308}\u{301}"), ('\u{1fd6}', "\u{3b9}\u{342}"),
+    ('\u{1fd7}', "\u{3b9}\u{308}\u{342}"), ('\u{1fe2}', "\u{3c5}\u{308}\u{300}"),
+    ('\u{1fe3}', "\u{3c5}\u{308}\u{301}"), ('\u{1fe4}', "\u{3c1}\u{313}"), ('\u{1fe6}', "\u{3c5}\u{342}"),
+    ('\u{1fe7}', "\u{3c5}\u{308}\u{342}"), ('\u{1ff2}', "\u{1f7c}\u{3b9}"), ('\u{1ff3}', "\u{3c9}\u{3b9}"),
+    ('\u{1ff4}', "\u{3ce}\u{3b9}"), ('\u{1ff6}', "\u{3c9}\u{342}"), ('\u{1ff7}', "\u{3c9}\u{342}\u{3b9}"),
+    ('\u{fb00}', "ff"), ('\u{fb01}', "fi"), ('\u{fb02}', "fl"), ('\u{fb03}', "ffi"), ('\u{fb04}', "ffl"),
+    ('\u{fb05}', "st"), ('\u{fb06}', "st"), ('\u{fb13}', "\u{574}\u{576}"), ('\u{fb14}', "\u{574}\u{565}"),
+    ('\u{fb15}', "\u{574}\u{56b}"), ('\u{fb16}', "\u{57e}\u{576}"), ('\u{fb17}', "\u{574}\u{56d}"),
+];
+
+/// Full case folding, so that "STRASSE" and "straße" or "FINE" and "ﬁne" match: lowercasing, then the folds that
+/// go further (ß to ss, ς to σ, ligatures split, iota subscripts spelled out, Cherokee to its capitals).
+fn casefold(text: &str) -> String {
+    let mut folded = String::with_capacity(text.len());
+    for c in text.to_lowercase().chars() {
+        match c as u32 {
+            // Cherokee's capitals were encoded first, so it folds to them
+            0xab70..=0xabbf => folded.push(char::from_u32(c as u32 - 0xab70 + 0x13a0).unwrap_or_else(|| c)),
+            0x13f8..=0x13fd => folded.push(char::from_u32(c as u32 - 8).unwrap_or_else(|| c)),
+            _ => match FOLDS.binary_search_by_key(&c, |(from, _)| *from) {
+                Ok(i) => folded.push_str(FOLDS[i].1),
+                Err(_) => folded.push(c),
+            },
+        }
+    }
+    folded
+}
+
+/// One step of --normalise, declared in the order they're applied in.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+enum Step {
+    Nfc,
+    Nfkc,
+    Nfd,
+    Nfkd,
+    Quotes,
+    Casefold,
+    Diacritics,
+    Punct,
+    Digits,
+    Space,
+}
+
+impl Step {
+    const NAMES: [(&'static str, Step); 10] = [
+        ("nfc", Step::Nfc),
+        ("nfkc", Step::Nfkc),
+        ("nfd", Step::Nfd),
+        ("nfkd", Step::Nfkd),
+        ("quotes", Step::Quotes),
+        ("casefold", Step::Casefold),
+        ("diacritics", Step::Diacritics),
+        ("punct", Step::Punct),
+        ("digits", Step::Digits),
+        ("space", Step::Space),
+    ];
+
+    fn name(&self) -> &'static str {
+        Step::NAMES.iter().find(|(_, step)| step == self).map(|(name, _)| *name).unwrap_or_else(|| "")
+    }
+
+    fn is_form(&self) -> bool {
+        *self <= Step::Nfkd
+    }
+
+    fn apply(&self, text: &str) -> String {
+        match self {
+            Step::Nfc => text.nfc().collect(),
+            Step::Nfkc => text.nfkc().collect(),
+            Step::Nfd => text.nfd().collect(),
+            Step::Nfkd => text.nfkd().collect(),
+            Step::Quotes => text
+                .chars()
+                .map(|c| match c {
+                    '\u{2018}' | '\u{2019}' | '\u{201a}' | '\u{201b}' | '\u{2032}' | '\u{2035}' | '\u{2039}' | '\u{203a}' => '\'',
+                    '\u{201c}' | '\u{201d}' | '\u{201e}' | '\u{201f}' | '\u{2033}' | '\u{2036}' | '\u{ab}' | '\u{bb}' => '"',
+                    c => c,
+                })
+                .collect(),
+            Step::Casefold => casefold(text),
+            Step::Diacritics => text.nfd().filter(|c| !is_combining_mark(*c)).nfc().collect(),
+            Step::Punct => text
+                .chars()
+                .filter(|c| c.is_alphanumeric() || c.is_whitespace() || is_combining_mark(*c))
+                .collect(),
+            Step::Digits => {
+                let mut masked = String::with_capacity(text.len());
+                for c in text.chars() {
+                    match c.is_numeric() {
+                        true if masked.ends_with('0') => {}
+                        true => masked.push('0'),
+                        false => masked.push(c),
+                    }
+                }
+                masked
+            }
+            Step::Space => text.split_whitespace().collect::<Vec<_>>().join(" "),
+        }
+    }
+}
+
+/// What a line is compared by: the line itself, or with --columns picked out of it, --no_punct's ascii
+/// punctuation taken out and the --normalise steps applied.
+struct Keyer {
+    no_punct: bool,
+    steps: Vec<Step>,
+    columns: Option<(String, parsers::ColumnSpecs)>,
+    sep: String,
+}
+
+impl Keyer {
+    fn new(no_punct: bool, normalise: Option<&str>, columns: Option<&str>, sep: &str) -> Result<Keyer, String> {
+        let mut steps = vec![];
+        for name in normalise.unwrap_or_else(|| "").split(',').map(str::trim).filter(|n| !n.is_empty()) {
+            let step = Step::NAMES.iter().find(|(n, _)| *n == name.to_lowercase()).map(|(_, step)| *step);
+            steps.push(step.ok_or_else(|| {
+                let names: Vec<&str> = Step::NAMES.iter().map(|(n, _)| *n).collect();
+                format!("--normalise takes {}, not {:?}", names.join(", "), name)
+            })?);
+        }
+        if let Some(pair) = steps.windows(2).find(|pair| pair[0] > pair[1]) {
+            let names: Vec<&str> = Step::NAMES.iter().map(|(n, _)| *n).collect();
+            return Err(format!(
+                "--normalise applies its steps in the order {}, so {} can't come after {}",
+                names.join(", "),
+                pair[1].name(),
+                pair[0].name()
+            ));
+        }
+        steps.dedup();
+        if steps.iter().filter(|step| step.is_form()).count() > 1 {
+            return Err("--normalise can only use one of nfc, nfkc, nfd and nfkd".to_string());
+        }
+        let columns = match columns {
+            Some(spec) => Some((spec.to_string(), parsers::parse_column_specs(spec).map_err(|e| format!("--columns: {}", e))?)),
+            None => None,
+        };
+        if let Some(parsers::ColumnSpec::Named(name)) = columns.iter().flat_map(|(_, specs)| specs).find(|s| matches!(s, parsers::ColumnSpec::Named(_))) {
+            return Err(format!("--columns can't use named columns like {:?}, there's no header", name));
+        }
+        Ok(Keyer { no_punct, steps, columns, sep: sep.to_string() })
+    }
+
+    /// How lines are keyed, as recorded in a state file: "exact" or "no_punct" as they always were, then whatever's
+    /// been added.
+    fn name(&self) -> String {
+        let mut name = match self.no_punct {
+            true => "no_punct".to_string(),
+            false => "exact".to_string(),
+        };
+        if !self.steps.is_empty() {
+            let steps: Vec<&str> = self.steps.iter().map(|step| step.name()).collect();
+            name = format!("{} normalise {}", name, steps.join(","));
+        }
+        if let Some((spec, _)) = &self.columns {
+            name = format!("{} columns {:?} split on {:?}", name, spec, self.sep);
+        }
+        name
+    }
+
+    /// Lines without the columns asked for are keyed by all of themselves. The line ending isn't part of the key,
+    /// so a shard's last line matches the same line anywhere else, whether or not it ended in a newline.
+    fn key<'a>(&self, line: &'a [u8]) -> Cow<'a, [u8]> {
+        let line = line.strip_suffix(b"\n").unwrap_or_else(|| line);
+        let line = line.strip_suffix(b"\r").unwrap_or_else(|| line);
+        if self.steps.is_empty() && self.columns.is_none() {
+            return match self.no_punct {
+                true => Cow::Owned(no_punctuation(line)),
+                false => Cow::Borrowed(line),
+            };
+        }
+        let line = String::from_utf8_lossy(line);
+        let mut key = match &self.columns {
+            Some((_, specs)) => {
+                let fields: Vec<&str> = line.split(self.sep.as_str()).collect();
+                parsers::try_apply(specs, &fields, None)
+                    .map(|picked| picked.join("\t"))
+                    .unwrap_or_else(|_| line.to_string())
+            }
+            None => line.to_string(),
+        };
+        if self.no_punct {
+            key = key.chars().filter(|c| !c.is_ascii_punctuation()).collect();
+        }
+        for step in &self.steps {
+            key = step.apply(&key);
+        }
+        Cow::Owned(key.into_bytes())
+    }
+}
+
 fn hash_str(s: &[u8]) -> u64 {
     let mut h = FnvHasher::with_key(0);
     s.hash(&mut h);
     h.finish()
 }
 
+/// splitmix64's finaliser, so one shingle hash gives a different, well-mixed value for each permutation.
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+
+struct MinHasher {
+    seeds: Vec<u64>,
+    shingle: usize,
+    words: bool,
+}
+
+impl MinHasher {
+    fn new(permutations: usize, shingle: usize, words: bool) -> MinHasher {
+        let seeds = (0..permutations as u64).map(|i| mix(i.wrapping_add(0x9e3779b97f4a7c15))).collect();
+        MinHasher { seeds, shingle: shingle.max(1), words }
+    }
+
+    /// Hashes of the line's shingles, with runs of whitespace counted as one space; a line shorter than one
+    /// shingle is a shingle by itself.
+    fn shingles(&self, line: &str) -> Vec<u64> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if self.words {
+            return words
+                .windows(self.shingle.min(words.len()).max(1))
+                .map(|w| hash_str(w.join(" ").as_bytes()))
+                .collect();
+        }
+        let chars: Vec<char> = words.join(" ").chars().collect();
+        chars
+            .windows(self.shingle.min(chars.len()).max(1))
+            .map(|w| hash_str(w.iter().collect::<String>().as_bytes()))
+            .collect()
+    }
+
+    fn signature(&self, line: &str) -> Vec<u32> {
+        let shingles = self.shingles(line);
+        self.seeds
+            .iter()
+            .map(|seed| shingles.iter().map(|h| mix(h ^ seed) as u32).min().unwrap_or_else(|| u32::MAX))
+            .collect()
+    }
+}
+
+/// The signatures of the lines kept so far, and for each band, which of them have which values in it.
+struct Lsh {
+    rows: usize,
+    threshold: f64,
+    buckets: Vec<IntMap<u64, Vec<u32>>>,
+    kept: Vec<Vec<u32>>,
+}
+
+impl Lsh {
+    /// Splits the permutations into bands of `rows`, picking the split whose s-curve rises closest below the
+    /// threshold, so that pairs about as similar as the threshold nearly always share a band.
+    fn new(permutations: usize, threshold: f64) -> Lsh {
+        let knee = |rows: usize| (1.0 / (permutations / rows) as f64).powf(1.0 / rows as f64);
+        let splits: Vec<usize> = (1..=permutations).filter(|rows| permutations % rows == 0).collect();
+        let rows = splits
+            .iter()
+            .rev()
+            .find(|rows| knee(**rows) <= threshold)
+            .cloned()
+            .unwrap_or_else(|| 1);
+        Lsh {
+            rows,
+            threshold,
+            buckets: (0..permutations / rows).map(|_| IntMap::default()).collect(),
+            kept: vec![],
+        }
+    }
+
+    fn band_keys<'a>(&self, signature: &'a [u32]) -> impl Iterator<Item = u64> + 'a {
+        signature.chunks(self.rows).map(|band| {
+            let mut h = FnvHasher::with_key(0);
+            band.hash(&mut h);
+            h.finish()
+        })
+    }
+
+    /// The first kept line this one is a near-duplicate of, if any.
+    fn find(&self, signature: &[u32]) -> Option<u32> {
+        self.band_keys(signature)
+            .zip(&self.buckets)
+            .filter_map(|(key, bucket)| bucket.get(&key))
+            .flatten()
+            .find(|id| {
+                let kept = &self.kept[**id as usize];
+                let agree = kept.iter().zip(signature).filter(|(a, b)| a == b).count();
+                agree as f64 / signature.len() as f64 >= self.threshold
+            })
+            .cloned()
+    }
+
+    fn insert(&mut self, signature: Vec<u32>) -> u32 {
+        let id = self.kept.len() as u32;
+        let keys: Vec<u64> = self.band_keys(&signature).collect();
+        for (key, bucket) in keys.into_iter().zip(self.buckets.iter_mut()) {
+            bucket.entry(key).or_default().push(id);
+        }
+        self.kept.push(signature);
+        id
+    }
+
+    /// The id of the kept line this one duplicates, or its own new id, and whether it was kept.
+    fn cluster(&mut self, signature: Vec<u32>) -> (u32, bool) {
+        match self.find(&signature) {
+            Some(id) => (id, false),
+            None => (self.insert(signature), true),
+        }
+    }
+}
+
+const STATE_MAGIC: &[u8; 4] = b"DDUP";
+const STATE_VERSION: u32 = 1;
+
+fn exit_with(e: String) -> ! {
+    eprintln!("{}", e);
+    std::process::exit(1)
+}
+
+/// Hashes from earlier runs, sorted so they can be searched without building a set, and those new in this one.
+struct Seen {
+    key: String,
+    earlier: Vec<u64>,
+    now: IntSet<u64>,
+}
+
+impl Seen {
+    fn new(key: String) -> Seen {
+        Seen { key, earlier: vec![], now: IntSet::default() }
+    }
+
+    /// Takes in every state file in `paths`, comma-separated; one that doesn't exist yet is only allowed if it's
+    /// going to be written.
+    fn load(paths: &str, key: String, update: bool) -> Result<Seen, String> {
+        let mut seen = Seen::new(key);
+        for (i, path) in paths.split(',').enumerate() {
+            let bytes = match std::fs::read(path) {
+                Ok(bytes) => bytes,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound && update && i == 0 => continue,
+                Err(e) => return Err(format!("couldn't read the state {}: {}", path, e)),
+            };
+            seen.read(&bytes).map_err(|e| format!("{} isn't a usable state: {}", path, e))?;
+        }
+        seen.earlier.sort_unstable();
+        seen.earlier.dedup();
+        Ok(seen)
+    }
+
+    fn read(&mut self, bytes: &[u8]) -> Result<(), String> {
+        let u32_at = |at: usize| bytes.get(at..at + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+        if bytes.get(..4) != Some(&STATE_MAGIC[..]) {
+            return Err("it's not a deduplicate state".to_string());
+        }
+        match u32_at(4) {
+            Some(STATE_VERSION) => {}
+            other => return Err(format!("it's version {:?}, this reads {}", other, STATE_VERSION)),
+        }
+        let key_len = u32_at(8).ok_or_else(|| "it's cut short".to_string())? as usize;
+        let key = bytes.get(12..12 + key_len).ok_or_else(|| "it's cut short".to_string())?;
+        if key != self.key.as_bytes() {
+            return Err(format!(
+                "its lines were keyed {:?}, these are keyed {:?}",
+                String::from_utf8_lossy(key),
+                self.key
+            ));
+        }
+        let hashes = &bytes[12 + key_len..];
+        if hashes.len() % 8 != 0 {
+            return Err("it's cut short".to_string());
+        }
+        self.earlier.extend(hashes.chunks(8).map(|b| {
+            u64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]])
+        }));
+        Ok(())
+    }
+
+    /// Whether the hash is new, noting it if so.
+    fn insert(&mut self, hash: u64) -> bool {
+        self.earlier.binary_search(&hash).is_err() && self.now.insert(hash)
+    }
+
+    /// Writes every hash seen, replacing `path` only once the whole state is written.
+    fn save(&self, path: &str) -> Result<(), String> {
+        let mut all: Vec<u64> = self.earlier.iter().chain(&self.now).cloned().collect();
+        all.sort_unstable();
+        all.dedup();
+        let partial = format!("{}.partial", path);
+        let write = || -> std::io::Result<()> {
+            let mut w = std::io::BufWriter::new(std::fs::File::create(&partial)?);
+            w.write_all(STATE_MAGIC)?;
+            w.write_all(&STATE_VERSION.to_le_bytes())?;
+            w.write_all(&(self.key.len() as u32).to_le_bytes())?;
+            w.write_all(self.key.as_bytes())?;
+            for hash in &all {
+                w.write_all(&hash.to_le_bytes())?;
+            }
+            w.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+            std::fs::rename(&partial, path)
+        };
+        write().map_err(|e| format!("couldn't write the state {}: {}", path, e))
+    }
+}
+
+/// "512M", "2G", "64k" or a plain number of bytes.
+fn parse_size(size: &str) -> Result<usize, String> {
+    let size = size.trim();
+    let (number, unit) = match size.char_indices().find(|(_, c)| c.is_alphabetic()) {
+        Some((i, _)) => size.split_at(i),
+        None => (size, ""),
+    };
+    let multiplier: usize = match unit.to_lowercase().trim_end_matches('b') {
+        "" => 1,
+        "k" => 1 << 10,
+        "m" => 1 << 20,
+        "g" => 1 << 30,
+        "t" => 1 << 40,
+        _ => return Err(format!("{:?} isn't a size like 512M or 2G", size)),
+    };
+    number
+        .trim()
+        .parse::<f64>()
+        .map(|n| (n * multiplier as f64) as usize)
+        .map_err(|_| format!("{:?} isn't a size like 512M or 2G", size))
+}
+
+/// Bits set by each line's hash, at positions spread by double hashing.
+struct Bloom {
+    bits: Vec<u64>,
+    hashes: u64,
+    capacity: u64,
+    added: u64,
+    fp_rate: f64,
+}
+
+impl Bloom {
+    /// As many bits as fit in `bytes`, and as many hashes per line as give `fp_rate` when it's as full as it should get.
+    fn new(bytes: usize, fp_rate: f64) -> Result<Bloom, String> {
+        if !(fp_rate > 0.0 && fp_rate < 1.0) {
+            return Err(format!("--fp_rate needs to be between 0 and 1, not {}", fp_rate));
+        }
+        let words = (bytes / 8).max(1);
+        let bits = (words * 64) as f64;
+        Ok(Bloom {
+            bits: vec![0; words],
+            hashes: (-fp_rate.log2()).round().max(1.0) as u64,
+            capacity: (bits * std::f64::consts::LN_2.powi(2) / -fp_rate.ln()) as u64,
+            added: 0,
+            fp_rate,
+        })
+    }
+
+    /// Whether the hash is (probably) new, setting its bits if so.
+    fn insert(&mut self, hash: u64) -> bool {
+        let bits = self.bits.len() as u64 * 64;
+        let step = mix(hash) | 1;
+        let mut new = false;
+        for i in 0..self.hashes {
+            let bit = hash.wrapping_add(i.wrapping_mul(step)) % bits;
+            let (word, mask) = ((bit / 64) as usize, 1 << (bit % 64));
+            new |= self.bits[word] & mask == 0;
+            self.bits[word] |= mask;
+        }
+        if new {
+            self.added += 1;
+            if self.added == self.capacity + 1 {
+                eprintln!(
+                    "the bloom filter has taken in more than the {} lines it holds at a {} false positive rate; \
+                     from here on more new lines will be dropped by mistake (give it more --max_memory)",
+                    self.capacity, self.fp_rate
+                );
+            }
+        }
+        new
+    }
+}
+
+/// A file in --spill_dir, removed when it's dropped.
+struct Spill {
+    path: PathBuf,
+}
+
+impl Spill {
+    fn new(dir: &Path, name: &str) -> Spill {
+        Spill {
+            path: dir.join(format!("dedup_{}_{}.spill", std::process::id(), name)),
+        }
+    }
+
+    fn create(&self) -> Result<BufWriter<File>, String> {
+        File::create(&self.path)
+            .map(BufWriter::new)
+            .map_err(|e| format!("couldn't write {}: {}", self.path.display(), e))
+    }
+
+    fn open(&self) -> Result<BufReader<File>, String> {
+        File::open(&self.path)
+            .map(BufReader::new)
+            .map_err(|e| format!("couldn't read {}: {}", self.path.display(), e))
+    }
+}
+
+impl Drop for Spill {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+fn io_error(e: std::io::Error) -> String {
+    format!("deduplicate --backend external: {}", e)
+}
+
+/// Something ExternalSort can spill: written and read back as SIZE little-endian bytes.
+trait Record: Ord + Copy {
+    const SIZE: usize;
+    fn write(&self, output: &mut impl Write) -> std::io::Result<()>;
+    fn read(bytes: &[u8]) -> Self;
+}
+
+fn u64_at(bytes: &[u8], at: usize) -> u64 {
+    let mut b = [0; 8];
+    b.copy_from_slice(&bytes[at..at + 8]);
+    u64::from_le_bytes(b)
+}
+
+impl Record for u64 {
+    const SIZE: usize = 8;
+    fn write(&self, output: &mut impl Write) -> std::io::Result<()> {
+        output.write_all(&self.to_le_bytes())
+    }
+    fn read(bytes: &[u8]) -> u64 {
+        u64_at(bytes, 0)
+    }
+}
+
+/// A line's hash and where to find it again, ordered by hash and then by where it came in the input.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct Entry {
+    hash: u64,
+    index: u64,
+    source: u64,
+    offset: u64,
+    len: u64,
+}
+
+impl Record for Entry {
+    const SIZE: usize = 40;
+    fn write(&self, output: &mut impl Write) -> std::io::Result<()> {
+        [self.hash, self.index, self.source, self.offset, self.len]
+            .iter()
+            .try_for_each(|n| output.write_all(&n.to_le_bytes()))
+    }
+    fn read(bytes: &[u8]) -> Entry {
+        Entry {
+            hash: u64_at(bytes, 0),
+            index: u64_at(bytes, 8),
+            source: u64_at(bytes, 16),
+            offset: u64_at(bytes, 24),
+            len: u64_at(bytes, 32),
+        }
+    }
+}
+
+/// How many runs are merged at once, and so how many spill files are open at a time.
+const FAN_IN: usize = 64;
+
+/// Sorts more records than fit in memory: each `memory` bytes' worth is sorted and spilled as a run, and the runs
+/// are merged as they're read back, first FAN_IN at a time into longer runs if there are more than that.
+struct ExternalSort<T: Record> {
+    dir: PathBuf,
+    name: &'static str,
+    capacity: usize,
+    buffer: Vec<T>,
+    runs: Vec<Spill>,
+    spilled: usize,
+}
+
+impl<T: Record> ExternalSort<T> {
+    fn new(dir: &Path, name: &'static str, memory: usize) -> ExternalSort<T> {
+        ExternalSort {
+            dir: dir.to_path_buf(),
+            name,
+            capacity: (memory / std::mem::size_of::<T>()).max(1),
+            buffer: vec![],
+            runs: vec![],
+            spilled: 0,
+        }
+    }
+
+    fn next_run(&mut self) -> Spill {
+        self.spilled += 1;
+        Spill::new(&self.dir, &format!("{}{}", self.name, self.spilled - 1))
+    }
+
+    fn push(&mut self, record: T) -> Result<(), String> {
+        self.buffer.push(record);
+        match self.buffer.len() >= self.capacity {
+            true => self.spill(),
+            false => Ok(()),
+        }
+    }
+
+    fn spill(&mut self) -> Result<(), String> {
+        self.buffer.sort_unstable();
+        let run = self.next_run();
+        let mut output = run.create()?;
+        self.buffer.iter().try_for_each(|r| r.write(&mut output)).map_err(io_error)?;
+        output.flush().map_err(io_error)?;
+        self.buffer.clear();
+        self.runs.push(run);
+        Ok(())
+    }
+
+    /// Every record pushed, in order.
+    fn finish(mut self) -> Result<Merged<T>, String> {
+        self.spill()?;
+        self.buffer = vec![];
+        while self.runs.len() > FAN_IN {
+            let runs: Vec<Spill> = self.runs.drain(..FAN_IN).collect();
+            let run = self.next_run();
+            let mut output = run.create()?;
+            for record in Merged::<T>::new(runs)? {
+                record?.write(&mut output).map_err(io_error)?;
+            }
+            output.flush().map_err(io_error)?;
+            self.runs.push(run);
+        }
+        Merged::new(std::mem::take(&mut self.runs))
+    }
+}
+
+struct Merged<T: Record> {
+    heap: BinaryHeap<Reverse<(T, usize)>>,
+    readers: Vec<BufReader<File>>,
+    _runs: Vec<Spill>,
+}
+
+impl<T: Record> Merged<T> {
+    fn new(runs: Vec<Spill>) -> Result<Merged<T>, String> {
+        let mut merged = Merged {
+            heap: BinaryHeap::new(),
+            readers: runs.iter().map(|run| run.open()).collect::<Result<_, _>>()?,
+            _runs: runs,
+        };
+        for run in 0..merged.readers.len() {
+            merged.refill(run)?;
+        }
+        Ok(merged)
+    }
+
+    /// Takes the next record of a run into the heap, if it has one left.
+    fn refill(&mut self, run: usize) -> Result<(), String> {
+        let mut bytes = vec![0; T::SIZE];
+        match self.readers[run].read_exact(&mut bytes) {
+            Ok(()) => self.heap.push(Reverse((T::read(&bytes), run))),
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {}
+            Err(e) => return Err(io_error(e)),
+        }
+        Ok(())
+    }
+}
+
+impl<T: Record> Iterator for Merged<T> {
+    type Item = Result<T, String>;
+
+    fn next(&mut self) -> Option<Result<T, String>> {
+        let Reverse((record, run)) = self.heap.pop()?;
+        Some(self.refill(run).map(|_| record))
+    }
+}
+
+/// Whether a source can be read again at the same offsets: a file that isn't compressed.
+fn seekable(source: &Source) -> bool {
+    let mut header = vec![];
+    source.name != "-"
+        && File::open(&source.name)
+            .and_then(|f| f.take(term_macros::compression::HEADER_LEN as u64).read_to_end(&mut header))
+            .map(|_| term_macros::compression::Compression::detect(&header) == term_macros::compression::Compression::None)
+            .unwrap_or_else(|_| false)
+}
+
+/// --backend external: see the top of the file. `hash` is hash_str, unless a test needs lines to collide.
+fn external(
+    sources: &[Source],
+    keyer: &Keyer,
+    hash: fn(&[u8]) -> u64,
+    memory: usize,
+    spill_dir: &Path,
+    output: &mut impl Write,
+) -> Result<(), String> {
+    // 1: every line's hash and position, with the inputs that can't be read at an offset copied
+    let mut entries = ExternalSort::<Entry>::new(spill_dir, "entries", memory);
+    let mut readable: Vec<(PathBuf, Option<Spill>)> = vec![];
+    let mut index = 0;
+    for (s, source) in sources.iter().enumerate() {
+        let copy = match seekable(source) {
+            true => None,
+            false => Some(Spill::new(spill_dir, &format!("input{}", s))),
+        };
+        let mut copy_output = copy.as_ref().map(|c| c.create()).transpose()?;
+        let mut reader = BufReader::new(source.open().map_err(|e| format!("couldn't read {}: {}", source.name, e))?);
+        let (mut line, mut offset) = (vec![], 0);
+        while reader.read_until(b'\n', &mut line).map_err(io_error)? > 0 {
+            if let Some(copy_output) = copy_output.as_mut() {
+                copy_output.write_all(&line).map_err(io_error)?;
+            }
+            let content = line.strip_suffix(b"\n").unwrap_or_else(|| &line);
+            let hash = hash(&keyer.key(content));
+            entries.push(Entry { hash, index, source: s as u64, offset, len: content.len() as u64 })?;
+            offset += line.len() as u64;
+            index += 1;
+            line.clear();
+        }
+        if let Some(mut copy_output) = copy_output {
+            copy_output.flush().map_err(io_error)?;
+        }
+        let path = copy.as_ref().map(|c| c.path.clone()).unwrap_or_else(|| PathBuf::from(&source.name));
+        readable.push((path, copy));
+    }
+
+    // 2: lines sharing a hash read back and compared, so only true duplicates are dropped
+    let files: Vec<File> = readable
+        .iter()
+        .map(|(path, _)| File::open(path).map_err(|e| format!("couldn't read {}: {}", path.display(), e)))
+        .collect::<Result<_, _>>()?;
+    let read_key = |entry: &Entry| -> Result<Vec<u8>, String> {
+        let mut file = &files[entry.source as usize];
+        let mut bytes = vec![0; entry.len as usize];
+        file.seek(SeekFrom::Start(entry.offset)).map_err(io_error)?;
+        file.read_exact(&mut bytes).map_err(io_error)?;
+        Ok(keyer.key(&bytes).into_owned())
+    };
+    let mut dropped = ExternalSort::<u64>::new(spill_dir, "dropped", memory);
+    // the distinct lines with the current hash: the first is only read if another line shares its hash
+    let mut distinct: Vec<(Entry, Option<Vec<u8>>)> = vec![];
+    for entry in entries.finish()? {
+        let entry = entry?;
+        if distinct.first().map(|(first, _)| first.hash) != Some(entry.hash) {
+            distinct = vec![(entry, None)];
+            continue;
+        }
+        let this = read_key(&entry)?;
+        let mut duplicate = false;
+        for (earlier, earlier_key) in distinct.iter_mut() {
+            if earlier_key.is_none() {
+                *earlier_key = Some(read_key(earlier)?);
+            }
+            if earlier_key.as_deref() == Some(&this[..]) {
+                duplicate = true;
+                break;
+            }
+        }
+        match duplicate {
+            true => dropped.push(entry.index)?,
+            false => distinct.push((entry, Some(this))),
+        }
+    }
+    drop(files);
+
+    // 3: the input again, in order, without the dropped lines
+    let mut dropped = dropped.finish()?.peekable();
+    let with_source = term_macros::inputs::with_source();
+    let mut index = 0;
+    for ((path, _), source) in readable.iter().zip(sources) {
+        let mut reader = BufReader::new(File::open(path).map_err(|e| format!("couldn't read {}: {}", path.display(), e))?);
+        let (mut line, mut line_number) = (vec![], 0);
+        while reader.read_until(b'\n', &mut line).map_err(io_error)? > 0 {
+            line_number += 1;
+            if !line.ends_with(b"\n") {
+                line.push(b'\n');
+            }
+            match dropped.peek() {
+                Some(Ok(next)) if *next == index => {
+                    dropped.next();
+                }
+                Some(Err(_)) => return Err(dropped.next().unwrap().unwrap_err()),
+                _ => {
+                    let written = match with_source {
+                        true => term_macros::inputs::write_tagged(output, &source.name, line_number, &line),
+                        false => output.write_all(&line),
+                    };
+                    // whatever's reading has stopped, as when filter_in! can't write
+                    if written.is_err() {
+                        return Ok(());
+                    }
+                }
+            }
+            index += 1;
+            line.clear();
+        }
+    }
+    let _ = output.flush();
+    Ok(())
+}
+
 fn main() {
     tool! {
         args:
             - no_punct;
+            - fuzzy;
+            - clusters;
+            - threshold: f64 = 0.8;
+            - shingle: usize = 5;
+            - word_shingles;
+            - permutations: usize = 128;
+            - state: Option<String> = None;
+            - update;
+            - backend: String = "hashset".to_string();
+            - max_memory: String = "1G".to_string();
+            - fp_rate: f64 = 0.001;
+            - spill_dir: Option<String> = None;
+            - normalise: Option<String> = None;
+            - columns: Option<String> = None;
+            - sep: String = "\t".to_string();
         ;
         body: || {
-            let mut already_seen = IntSet::default();
-            let hashfn: &dyn Fn(&[u8]) -> u64 = match no_punct {
-                true => &|line: &[u8]| hash_str(&no_punctuation(line)),
-                false => &|line: &[u8] | hash_str(line)
-            };
-            filter_in!(|line: &[u8]| {
-                let hashnum = hashfn(line);
-                if already_seen.contains(&hashnum) {
-                    false
+            if update && state.is_none() {
+                exit_with("--update needs a --state to write to".to_string());
+            }
+            // serving and --sync_mode never run out of input, so the state would never be written
+            if update && (term_macros::server::serving() || find_arg::<bool>("--sync_mode").unwrap_or_else(|| false)) {
+                exit_with("--update writes the state once the input runs out, so it can't be used with --serve or --sync_mode".to_string());
+            }
+            if fuzzy && state.is_some() {
+                exit_with("--state only keeps the hashes of exact keys, so it can't be used with --fuzzy".to_string());
+            }
+            if backend != "hashset" && (fuzzy || state.is_some()) {
+                exit_with(format!("--fuzzy and --state keep their own sets, so they can't be used with --backend {}", backend));
+            }
+            let keyer = Keyer::new(no_punct, normalise.as_deref(), columns.as_deref(), &sep).unwrap_or_else(|e| exit_with(e));
+            let memory = parse_size(&max_memory).unwrap_or_else(|e| exit_with(format!("--max_memory: {}", e)));
+            if fuzzy {
+                let minhasher = MinHasher::new(permutations.max(1), shingle, word_shingles);
+                let mut lsh = Lsh::new(permutations.max(1), threshold);
+                let signature = |line: &[u8]| minhasher.signature(&String::from_utf8_lossy(&keyer.key(line)));
+                if clusters {
+                    readin!(wtr, |line: &[u8]| {
+                        let (id, _) = lsh.cluster(signature(line));
+                        let _ = write!(wtr, "{}\t", id);
+                        let _ = wtr.write_all(line);
+                    });
                 } else {
-                    already_seen.insert(hashnum);
-                    true
+                    filter_in!(|line: &[u8]| lsh.cluster(signature(line)).1);
+                }
+                return;
+            }
+            match backend.as_str() {
+                "hashset" => {}
+                "bloom" => {
+                    let mut bloom = Bloom::new(memory, fp_rate).unwrap_or_else(|e| exit_with(e));
+                    filter_in!(|line: &[u8]| bloom.insert(hash_str(&keyer.key(line))));
+                    return;
                 }
-            });
+                "external" => {
+                    let spill_dir = spill_dir.map(PathBuf::from).unwrap_or_else(std::env::temp_dir);
+                    let mut output = BufWriter::new(term_macros::compression::stdout());
+                    external(&term_macros::inputs::sources_or_exit(), &keyer, hash_str, memory, &spill_dir, &mut output)
+                        .unwrap_or_else(|e| exit_with(e));
+                    return;
+                }
+                other => exit_with(format!("--backend needs to be hashset, bloom or external, not {:?}", other)),
+            }
+            let mut already_seen = match &state {
+                Some(paths) => Seen::load(paths, keyer.name(), update).unwrap_or_else(|e| exit_with(e)),
+                None => Seen::new(keyer.name()),
+            };
+            filter_in!(|line: &[u8]| already_seen.insert(hash_str(&keyer.key(line))));
+            if let (true, Some(paths)) = (update, &state) {
+                let path = paths.split(',').next().unwrap_or_else(|| paths);
+                already_seen.save(path).unwrap_or_else(|e| exit_with(e));
+            }
+        }
+    }
+}
+
+#[test]
+fn test_lsh() {
+    // the most rows per band whose s-curve still rises below the threshold
+    assert_eq!(Lsh::new(128, 0.8).rows, 8);
+    assert_eq!(Lsh::new(128, 0.8).buckets.len(), 16);
+    assert_eq!(Lsh::new(128, 0.5).rows, 4);
+    assert_eq!(Lsh::new(128, 0.99).rows, 64);
+    assert_eq!(Lsh::new(128, 0.0).rows, 1);
+
+    let minhasher = MinHasher::new(128, 5, false);
+    assert_eq!(minhasher.shingles("a  b"), minhasher.shingles("a b"));
+    assert_eq!(minhasher.shingles("abc").len(), 1);
+    assert_eq!(MinHasher::new(128, 2, true).shingles("one two three").len(), 2);
+
+    let mut lsh = Lsh::new(128, 0.8);
+    let lines = [
+        "the quick brown fox jumps over the lazy dog by the river",
+        "an entirely different sentence about the weather in spring",
+        "the quick brown fox jumps over the lazy dog by the river!",
+        "an entirely different sentence about the weather in spring.",
+        "the quick brown fox jumps over the lazy dog by the river",
+    ];
+    let clusters: Vec<(u32, bool)> = lines.iter().map(|line| lsh.cluster(minhasher.signature(line))).collect();
+    assert_eq!(clusters, vec![(0, true), (1, true), (0, false), (1, false), (0, false)]);
+}
+
+#[test]
+fn test_seen() {
+    let dir = std::env::temp_dir().join(format!("dedup_test_seen_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("seen.bin").to_string_lossy().to_string();
+
+    let mut seen = Seen::load(&path, "exact".to_string(), true).unwrap();
+    assert!(seen.insert(1) && seen.insert(2) && !seen.insert(1));
+    seen.save(&path).unwrap();
+    assert!(!Path::new(&format!("{}.partial", path)).exists());
+
+    let mut again = Seen::load(&path, "exact".to_string(), false).unwrap();
+    assert_eq!(again.earlier, vec![1, 2]);
+    assert!(!again.insert(2) && again.insert(3));
+
+    let e = Seen::load(&path, "no_punct".to_string(), false).err().unwrap();
+    assert!(e.contains("keyed \"exact\", these are keyed \"no_punct\""), "{}", e);
+    let bytes = std::fs::read(&path).unwrap();
+    for cut in [bytes.len() - 3, 10, 14] {
+        assert_eq!(Seen::new("exact".to_string()).read(&bytes[..cut]), Err("it's cut short".to_string()));
+    }
+    assert!(Seen::new("exact".to_string()).read(b"not a state").is_err());
+    assert!(Seen::load(&path.replace("seen", "missing"), "exact".to_string(), false).is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_parse_size() {
+    assert_eq!(parse_size("512M"), Ok(512 << 20));
+    assert_eq!(parse_size("2G"), Ok(2 << 30));
+    assert_eq!(parse_size(" 64kb "), Ok(64 << 10));
+    assert_eq!(parse_size("1.5k"), Ok(1536));
+    assert_eq!(parse_size("100"), Ok(100));
+    assert!(parse_size("12 parsecs").is_err());
+    assert!(parse_size("M").is_err());
+}
+
+#[test]
+fn test_bloom() {
+    assert!(Bloom::new(1 << 10, 0.0).is_err());
+    assert!(Bloom::new(1 << 10, 1.0).is_err());
+    let mut bloom = Bloom::new(1 << 16, 0.01).unwrap();
+    assert_eq!(bloom.hashes, 7);
+    // m ln2^2 / ln(1/p) for m = 2^19 bits: about 55 thousand lines
+    assert!((bloom.capacity as i64 - 54_698).abs() < 10, "{}", bloom.capacity);
+    let capacity = bloom.capacity;
+    (0..capacity).for_each(|i| {
+        bloom.insert(mix(i));
+    });
+    assert!((0..capacity).all(|i| !bloom.insert(mix(i))));
+    assert!(bloom.added <= capacity);
+    // lines never seen, when it's as full as it's sized for: about --fp_rate of them come back as seen (a little
+    // more by the end, since each new one fills it further)
+    let false_positives = (capacity..capacity + 5000).filter(|i| !bloom.insert(mix(*i))).count();
+    assert!(false_positives > 25 && false_positives < 125, "{}", false_positives);
+}
+
+#[test]
+fn test_external() {
+    let dir = std::env::temp_dir().join(format!("dedup_test_external_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    // more runs than FAN_IN, so some are merged before the end
+    let mut sort = ExternalSort::<u64>::new(&dir, "test", 8 * 3);
+    let numbers: Vec<u64> = (0..1000).map(|i| mix(i) % 500).collect();
+    numbers.iter().try_for_each(|n| sort.push(*n)).unwrap();
+    assert!(sort.runs.len() > FAN_IN);
+    let sorted: Vec<u64> = sort.finish().unwrap().map(Result::unwrap).collect();
+    let mut expected = numbers.clone();
+    expected.sort_unstable();
+    assert_eq!(sorted, expected);
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+
+    let path = dir.join("input.txt");
+    std::fs::write(&path, "b\na\nb\nc\na\nd").unwrap();
+    let sources = term_macros::inputs::expand(&[path.to_string_lossy().to_string()]).unwrap();
+    let keyer = Keyer::new(false, None, None, "\t").unwrap();
+    for hash in [hash_str as fn(&[u8]) -> u64, |_: &[u8]| 0] {
+        // with every line hashed the same, only comparing the lines themselves tells them apart
+        let mut output = vec![];
+        external(&sources, &keyer, hash, 64, &dir, &mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "b\na\nc\nd\n");
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_steps() {
+    let apply = |step: Step, text: &str| step.apply(text);
+    assert_eq!(apply(Step::Nfc, "cafe\u{301}"), "café");
+    assert_eq!(apply(Step::Nfd, "café"), "cafe\u{301}");
+    assert_eq!(apply(Step::Nfkc, "\u{ff21}\u{ff42}c"), "Abc");
+    assert_eq!(apply(Step::Nfkd, "\u{ff21}é"), "Ae\u{301}");
+    assert_eq!(apply(Step::Quotes, "\u{201c}it\u{2019}s\u{201d} \u{ab}so\u{bb}"), "\"it's\" \"so\"");
+    assert_eq!(apply(Step::Casefold, "STRASSE"), apply(Step::Casefold, "straße"));
+    assert_eq!(apply(Step::Casefold, "ΣΊΣΥΦΟΣ \u{fb01}NE \u{13a0}\u{ab71}"), "σίσυφοσ fine \u{13a0}\u{13a1}");
+    assert_eq!(apply(Step::Diacritics, "Müller's crème café"), "Muller's creme cafe");
+    assert_eq!(apply(Step::Punct, "«Hi», she said — ok?!"), "Hi she said  ok");
+    assert_eq!(apply(Step::Digits, "call 555-1234 or ٣٤"), "call 0-0 or 0");
+    assert_eq!(apply(Step::Space, "  a \t b\u{a0} c  "), "a b c");
+    assert!(FOLDS.windows(2).all(|pair| pair[0].0 < pair[1].0));
+}
+
+#[test]
+fn test_keyer() {
+    let keyer = Keyer::new(false, Some("nfkc, casefold,space"), None, "\t").unwrap();
+    assert_eq!(keyer.name(), "exact normalise nfkc,casefold,space");
+    assert_eq!(keyer.key(b"Hello   \xef\xbc\xb7orld\n"), keyer.key(b"hello world"));
+    let e = Keyer::new(false, Some("space,casefold"), None, "\t").err().unwrap();
+    assert!(e.contains("casefold can't come after space"), "{}", e);
+    assert!(Keyer::new(false, Some("nfc,nfkc"), None, "\t").is_err());
+    assert!(Keyer::new(false, Some("lowercase"), None, "\t").is_err());
+
+    let keyer = Keyer::new(true, Some("casefold"), Some("2,0"), "\t").unwrap();
+    assert_eq!(keyer.name(), "no_punct normalise casefold columns \"2,0\" split on \"\\t\"");
+    assert_eq!(&*keyer.key(b"A\tignored\tB!\n"), b"b\ta");
+    assert_eq!(keyer.key(b"a\tother\tb"), keyer.key(b"A\tignored\tB!\n"));
+    // too few columns: keyed by the whole line
+    assert_eq!(&*keyer.key(b"Only, one"), b"only one");
+    let keyer = Keyer::new(false, None, Some("1"), ",").unwrap();
+    assert_eq!(&*keyer.key(b"x,y,z"), b"y");
+    assert!(Keyer::new(false, None, Some("name"), "\t").is_err());
+}
+
+#[test]
+fn test_state_line_endings() {
+    let dir = std::env::temp_dir().join(format!("dedup_test_endings_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("seen.bin").to_string_lossy().to_string();
+    for normalise in [None, Some("space")] {
+        let keyer = Keyer::new(false, normalise, None, "\t").unwrap();
+        // one shard ending without a newline, then another run with the same lines ended every way
+        let mut first = Seen::load(&path, keyer.name(), true).unwrap();
+        for line in [&b"x\n"[..], b"y\r\n", b"z"] {
+            assert!(first.insert(hash_str(&keyer.key(line))));
+        }
+        first.save(&path).unwrap();
+        let mut second = Seen::load(&path, keyer.name(), false).unwrap();
+        for line in [&b"z\n"[..], b"x", b"y\n", b"z\r\n"] {
+            assert!(!second.insert(hash_str(&keyer.key(line))), "{:?} {:?}", normalise, line);
         }
+        std::fs::remove_file(&path).unwrap();
     }
-}
\ No newline at end of file
+    std::fs::remove_dir_all(&dir).unwrap();
+}
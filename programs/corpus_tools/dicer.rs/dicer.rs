@@ -2,20 +2,47 @@
 //! ```cargo
 //! [dependencies]
 //! term_macros = { path = "../../shared/term_macros"  }
+//! parsers = { path = "../../experimental/parsers"  }
 //! ```
 
 use term_macros::*;
 
+/// --order used to be a list of column numbers, like `[2,0,1]`, which still means the spec "2 0 1".
+fn legacy_order(order: &str) -> Option<Result<String, String>> {
+    let order = order.trim();
+    if !(order.starts_with('[') && order.ends_with(']')) {
+        return None;
+    }
+    Some(
+        term_macros::parse_args::Args::parse_arg::<Vec<usize>>(order)
+            .map(|columns| columns.iter().map(|c| c.to_string()).collect::<Vec<_>>().join(" "))
+            .ok_or_else(|| format!("--order {:?} needs to be a list of column numbers, or a column spec like \"2 0 1\"", order)),
+    )
+}
+
 fn main() {
     tool! {
         args:
-            - order: Vec<usize>;
+            - order: String;
             - sep: String = "\t".to_string();
             - newsep: String = "\t".to_string();
             - error_on_invalid_utf8;
         ;
 
         body: || {
+            // e.g. "2 ..1 4..", "-1 ..-1", "!3", "0 '|' 1": see experimental/parsers for the whole grammar
+            let order = legacy_order(&order).unwrap_or_else(|| Ok(order.clone())).unwrap_or_else(|e| {
+                eprintln!("{}", e);
+                std::process::exit(1)
+            });
+            let order = parsers::parse_column_specs(&order).unwrap_or_else(|e| {
+                eprintln!("{}", e);
+                std::process::exit(1)
+            });
+            if let Some(parsers::ColumnSpec::Named(name)) = order.iter().find(|s| matches!(s, parsers::ColumnSpec::Named(_))) {
+                eprintln!("--order can't use named columns like {:?}, there's no header", name);
+                std::process::exit(1)
+            }
 
             readin!(wtr, |line: &[u8]| {
                 let line = std::str::from_utf8(line);
@@ -28,30 +55,20 @@ fn main() {
                     }
                 }
                 let line = line.unwrap();
-                let line = &line[0..line.len() - 1];
+                let line = line.strip_suffix('\n').unwrap_or_else(|| line);
                 let parts: Vec<&str> = line.split(&sep).collect();
-                let mut new_parts = Vec::with_capacity(order.len() * 2);
-                for idx in order.iter() {
-                    let part = parts.get(*idx);
-                    if part.is_none() {
+                let new_parts = match parsers::try_apply(&order, &parts, None) {
+                    Ok(new_parts) => new_parts,
+                    Err(_) => {
                         let _ = wtr.write_all(b"\n");
                         return;
                     }
-                    let part = part.unwrap();
-                    new_parts.push(*part);
-                    new_parts.push(&newsep);
                 };
-                let _ = new_parts.pop();
-                for part in new_parts.iter() {
-                    let r = wtr.write_all(part.as_bytes());
-                    if r.is_err() {
-                        panic!("Unable to write")
-                    }
-                }
-                if !new_parts.iter().last().unwrap().contains("\n") {
-                    let _ = wtr.write_all(b"\n");
+                let r = wtr.write_all(new_parts.join(&newsep).as_bytes());
+                if r.is_err() {
+                    panic!("Unable to write")
                 }
-                new_parts.clear();
+                let _ = wtr.write_all(b"\n");
             });
         }
 
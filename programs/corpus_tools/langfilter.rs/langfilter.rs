@@ -1,15 +1,15 @@
 //!
 //! ```cargo
 //! [dependencies]
-//! lz4_flex = { version = "0.9.0", default-features = false }
 //! rayon = "1.5.3"
 //! memmap = "0.7.0"
 //! term_macros = { path = "../../shared/term_macros"  }
+//! scorers = { path = "../../shared/scorers" }
 //! ```
 
-use lz4_flex::compress_prepend_size;
 use memmap::MmapOptions;
 use rayon::prelude::*;
+use scorers::compression::{joint_ratio, Codec};
 use std::io::{Error, ErrorKind};
 use std::{collections::HashMap, path::PathBuf};
 use term_macros::*;
@@ -85,14 +85,8 @@ fn compression_ratio(
     compr_f1_len: usize,
     compr_f2_len: usize,
 ) -> Result<f64, Error> {
-    if f1.len() + f2.len() == 0 {
-        return Err(Error::new(ErrorKind::InvalidData, "File is empty"));
-    }
-    let compressed_together =
-        compress_prepend_size(&f1.iter().chain(f2.iter()).copied().collect::<Vec<u8>>());
-
-    let actual = (compressed_together.len()) as f64 / (compr_f1_len + compr_f2_len) as f64;
-    Ok(actual)
+    joint_ratio(&Codec::Lz4, f1, f2, compr_f1_len, compr_f2_len)
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "File is empty"))
 }
 
 /// Returns a list of all files found in the top level of a directory (and not within any subdirectories). Ignores folders.
@@ -152,7 +146,7 @@ fn get_lengths(
         .map(|(fname, file)| {
             // Safety: no
             let mmap = unsafe { MmapOptions::new().map(&file).unwrap() };
-            let byte_length = compress_prepend_size(&mmap[..]).len();
+            let byte_length = Codec::Lz4.compressed_len(&mmap[..]);
             (fname.to_string_lossy().to_string(), (mmap, byte_length))
         })
         .collect()
@@ -163,7 +157,7 @@ pub fn get_likelihood_of_lang<'a>(
     input_bytes: &[u8],
     average_length: f64,
 ) -> Vec<(&'a str, f64)> {
-    let compressed_length = compress_prepend_size(input_bytes).len();
+    let compressed_length = Codec::Lz4.compressed_len(input_bytes);
     let compression_ratios = lengths
         .par_iter()
         .map(|(lang, (f1, compr_f1_len))| {
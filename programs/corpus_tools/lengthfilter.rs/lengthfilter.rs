@@ -18,9 +18,23 @@ fn main() {
         ;
 
         body: || {
-            filter_in!(|line: &[u8]| {
+            par_filter_in!(|line: &[u8]| {
                 let word_count = line.split(|c| c == &b' ').count();
-                !(line.len() > max_chars || line.len() < min_chars || word_count > max_words || word_count < min_words)
+                let dropped_by = if line.len() > max_chars {
+                    Some("max_chars")
+                } else if line.len() < min_chars {
+                    Some("min_chars")
+                } else if word_count > max_words {
+                    Some("max_words")
+                } else if word_count < min_words {
+                    Some("min_words")
+                } else {
+                    None
+                };
+                if let Some(filter) = dropped_by {
+                    term_macros::progress::dropped_by(filter);
+                }
+                dropped_by.is_none()
             });
         }
 
@@ -5,19 +5,40 @@ use itertools::Itertools;
 fn main() {
 
     tool! {
-        args: 
+        args:
             - min_char_length: usize = 3;
-            - col: usize = 0;
-            - sep: String = "\t".to_string();
+            // deprecated, from before --fields: the column to check, and what columns are split on
+            - col: Option<usize> = None;
+            - sep: Option<String> = None;
         ;
 
         body: || {
-            filter_in!(|line: &[u8]| {
-                std::str::from_utf8(line).ok().map(|ln| ln.split(&sep).nth(col)).flatten().map(|ln| ln.to_lowercase()).map(|ln| {
-                    let words: Vec<_> = ln.unicode_words().filter(|w| w.len() >= min_char_length).collect();
-                    words.iter().unique().count() - words.len() == 0
-                }).unwrap_or_else(|| false)
-            });
+            if col.is_some() || sep.is_some() {
+                eprintln!("--col and --sep are deprecated: use --fields (and --record_format csv for commas) instead");
+            }
+            if col.is_some() && matches!(find_layered_arg::<String>("--fields"), Ok(Some(_))) {
+                eprintln!("--col and --fields both pick the column to check, so give only --fields");
+                std::process::exit(1);
+            }
+            let no_repeats = |field: &str| {
+                let ln = field.to_lowercase();
+                let words: Vec<_> = ln.unicode_words().filter(|w| w.len() >= min_char_length).collect();
+                words.iter().unique().count() == words.len()
+            };
+            match sep.filter(|sep| sep != "\t") {
+                // any other separator splits lines as it always did, since records only split tsv on tabs
+                Some(sep) => {
+                    let col = col.unwrap_or_else(|| 0);
+                    filter_in!(|line: &[u8]| {
+                        std::str::from_utf8(line).ok().and_then(|ln| ln.split(&sep).nth(col)).map(no_repeats).unwrap_or_else(|| false)
+                    });
+                }
+                None => {
+                    filter_records!(&col.unwrap_or_else(|| 0).to_string(), |fields: &[&str]| {
+                        fields.iter().all(|field| no_repeats(field))
+                    });
+                }
+            }
         }
     }
 }
@@ -26,20 +26,12 @@ fn main() {
                 set.insert(w);
             })
     };
-    filter_in!(|line: &[u8]| {
-        let line = std::str::from_utf8(line);
-        if line.is_err() {
+    filter_records!("0 1", |fields: &[&str]| {
+        if fields.len() < 2 {
             return false;
         }
-        let line = line.unwrap();
-        let mut split = line.split(|c| c == '\t');
-        let part1 = split.next();
-        let part2 = split.next();
-        if part2.is_none() {
-            return false;
-        }
-        into_set(part1.unwrap(), &mut set1);
-        into_set(part2.unwrap(), &mut set2);
+        into_set(fields[0], &mut set1);
+        into_set(fields[1], &mut set2);
         let passed_check = set1.intersection(&set2).next().is_none();
         set1.clear();
         set2.clear();
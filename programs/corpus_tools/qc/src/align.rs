@@ -0,0 +1,137 @@
+use crate::structs::*;
+use fnv::FnvHasher;
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+// alignment learns which words translate which from the pairs themselves: ibm model 1, trained both ways with a few
+// rounds of em over the first `sample` pairs. it scores a pair by how much of it lines up, i.e. the share of each
+// side's words that some word on the other side translates to with at least `threshold` probability, taking the
+// lower of the two sides. pairs that aren't translations of each other score near 0, and partly translated ones
+// score about as much as was translated.
+
+pub const SAMPLE: usize = 100_000;
+pub const THRESHOLD: f64 = 0.1;
+const ITERATIONS: usize = 5;
+// the empty word, which words with no translation on the other side are aligned to
+const NULL: u64 = 0;
+
+/// (from, to) -> the chance `to` is the translation of `from`
+type Table = HashMap<(u64, u64), f32>;
+
+fn hash_word(word: &str) -> u64 {
+    let mut h = FnvHasher::with_key(0);
+    word.to_lowercase().hash(&mut h);
+    h.finish().max(NULL + 1)
+}
+
+fn hashed(side: &Side) -> Vec<u64> {
+    side.words.iter().map(|w| hash_word(w)).collect()
+}
+
+fn add_counts(mut a: HashMap<(u64, u64), f64>, b: HashMap<(u64, u64), f64>) -> HashMap<(u64, u64), f64> {
+    b.into_iter().for_each(|(k, v)| *a.entry(k).or_insert(0.0) += v);
+    a
+}
+
+/// One direction of model 1.
+fn train(pairs: &[(Vec<u64>, Vec<u64>)], iterations: usize) -> Table {
+    let mut table = Table::new();
+    for iteration in 0..iterations {
+        let counts = pairs
+            .par_chunks(1024)
+            .map(|chunk| {
+                let mut counts = HashMap::new();
+                for (from, to) in chunk {
+                    // everything starts out equally likely
+                    let chance = |f: &u64, t: &u64| match iteration {
+                        0 => 1.0,
+                        _ => table.get(&(*f, *t)).copied().unwrap_or_else(|| 0.0) as f64,
+                    };
+                    for t in to {
+                        let total: f64 = from.iter().chain([&NULL]).map(|f| chance(f, t)).sum();
+                        if total <= 0.0 {
+                            continue;
+                        }
+                        for f in from.iter().chain([&NULL]) {
+                            *counts.entry((*f, *t)).or_insert(0.0) += chance(f, t) / total;
+                        }
+                    }
+                }
+                counts
+            })
+            .reduce(HashMap::new, add_counts);
+        let mut totals: HashMap<u64, f64> = HashMap::new();
+        counts
+            .iter()
+            .for_each(|((f, _), count)| *totals.entry(*f).or_insert(0.0) += count);
+        table = counts
+            .into_iter()
+            .map(|((f, t), count)| ((f, t), (count / totals[&f]) as f32))
+            .collect();
+    }
+    table
+}
+
+pub struct Alignment {
+    forward: Table,
+    backward: Table,
+    threshold: f64,
+}
+
+impl Alignment {
+    pub fn from_txs(txs: &[Translation], threshold: f64) -> Alignment {
+        let pairs: Vec<_> = txs
+            .par_iter()
+            .map(|tx| (hashed(&tx.sides.0), hashed(&tx.sides.1)))
+            .collect();
+        let reversed: Vec<_> = pairs.iter().map(|(a, b)| (b.clone(), a.clone())).collect();
+        Alignment {
+            forward: train(&pairs, ITERATIONS),
+            backward: train(&reversed, ITERATIONS),
+            threshold,
+        }
+    }
+
+    /// The share of `to` that some word of `from` translates to.
+    fn coverage(&self, table: &Table, from: &[u64], to: &[u64]) -> f64 {
+        let aligned = to
+            .iter()
+            .filter(|t| {
+                from.iter()
+                    .any(|f| table.get(&(*f, **t)).map(|p| *p as f64 >= self.threshold).unwrap_or_else(|| false))
+            })
+            .count();
+        aligned as f64 / to.len() as f64
+    }
+}
+
+impl ScoreBoth for Alignment {
+    fn score_both(&self, tx: &Translation) -> f64 {
+        let (source, target) = (hashed(&tx.sides.0), hashed(&tx.sides.1));
+        if source.is_empty() || target.is_empty() {
+            return 0.0;
+        }
+        self.coverage(&self.forward, &source, &target)
+            .min(self.coverage(&self.backward, &target, &source))
+    }
+}
+
+#[test]
+fn test_alignment() {
+    let source = ["one", "two", "three", "four", "five", "six", "seven", "eight"];
+    let target = ["eins", "zwei", "drei", "vier", "funf", "sechs", "sieben", "acht"];
+    let txs: Vec<Translation> = (0..400)
+        .map(|i: usize| {
+            let words: Vec<usize> = (0..(i % 4) + 2).map(|j| (i * 3 + j * 5) % source.len()).collect();
+            let side = |vocab: &[&str]| words.iter().map(|w| vocab[*w]).collect::<Vec<_>>().join(" ");
+            Translation::new(&side(&source), &side(&target), i as u64)
+        })
+        .collect();
+    let alignment = Alignment::from_txs(&txs, THRESHOLD);
+    let score = |a: &str, b: &str| alignment.score_both(&Translation::new(a, b, 0));
+    assert_eq!(score("two three eight", "acht zwei drei"), 1.0);
+    assert_eq!(score("two three", "sechs sieben"), 0.0);
+    assert_eq!(score("one two five six", "eins zwei five six"), 0.5);
+    assert_eq!(score("", "eins"), 0.0);
+}
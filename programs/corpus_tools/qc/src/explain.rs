@@ -0,0 +1,109 @@
+use crate::fusion::*;
+use crate::sort::ranked;
+use crate::structs::*;
+use std::io::Write;
+use term_macros::records::{Format, RecordWriter};
+
+// --explain writes every pair rather than only the kept ones: the kept pairs best first, then those past --k_top,
+// then those a cutoff dropped, each with a column per scorer for its raw score and one for its rank among all the
+// pairs (1 is the best), then the fused score and why the pair was dropped, if it was:
+//   src  tgt  redundancy  redundancy_rank  ...  score  dropped
+// dropped is empty for a kept pair, k_top for one past the --k_top share, or the name of the scorer whose cutoff
+// it missed (and then it has no fused score). --explain_format picks tsv (the default), csv or jsonl;
+// tsv and csv start with a header line.
+
+pub fn columns(stages: &[Stage]) -> Vec<String> {
+    let mut names = vec!["src".to_string(), "tgt".to_string()];
+    for stage in stages {
+        names.push(stage.name.clone());
+        names.push(format!("{}_rank", stage.name));
+    }
+    names.push("score".to_string());
+    names.push("dropped".to_string());
+    names
+}
+
+fn format_score(score: f64) -> String {
+    format!("{:.6}", score)
+}
+
+pub fn explain<W: Write>(
+    stages: &[Stage],
+    fusion: Fusion,
+    txs: &[Translation],
+    k_top: f64,
+    output: W,
+    format: Format,
+) -> std::io::Result<()> {
+    let raw = score_all(stages, txs);
+    // the same order Stage::sort would give, without scoring everything again
+    let stage_ranks: Vec<Vec<usize>> = raw.iter().map(|scores| ranks(scores)).collect();
+    let fused = fuse(stages, fusion, &raw, txs.len());
+    let order = ranked(&fused);
+    let kept = (order.len() as f64 * k_top).floor() as usize;
+    let cut = (0..txs.len()).filter(|i| fused[*i].is_none());
+
+    let mut writer = RecordWriter::new(output, format);
+    match format {
+        Format::Jsonl => writer.names = Some(columns(stages)),
+        _ => writer.write_record(&columns(stages))?,
+    }
+    for (position, i) in order.iter().copied().chain(cut).enumerate() {
+        let tx = &txs[i];
+        let mut fields = vec![tx.sides.0.content.to_string(), tx.sides.1.content.to_string()];
+        for (scores, ranks) in raw.iter().zip(&stage_ranks) {
+            fields.push(format_score(scores[i]));
+            fields.push((ranks[i] + 1).to_string());
+        }
+        fields.push(fused[i].map(format_score).unwrap_or_default());
+        fields.push(match fused[i] {
+            Some(_) if position < kept => String::new(),
+            Some(_) => "k_top".to_string(),
+            None => stages
+                .iter()
+                .zip(&raw)
+                .find(|(stage, scores)| !stage.passes(scores[i]))
+                .map(|(stage, _)| stage.name.clone())
+                .unwrap_or_default(),
+        });
+        writer.write_record(&fields)?;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_explain() {
+    let txs = vec![
+        Translation::new("Good sentence here.", "Gute Satz hier.", 0),
+        Translation::new("no caps", "keine", 1),
+        Translation::new("Another one!", "Noch einer!", 2),
+    ];
+    let mut cut = StageSpec::new("well_formed");
+    cut.apply = Apply::Target;
+    cut.min = Some(0.5);
+    let stages = vec![
+        Stage::build(&StageSpec::new("nonalphabetic"), &Corpus::Pairs(&txs), 0).unwrap(),
+        Stage::build(&cut, &Corpus::Pairs(&txs), 0).unwrap(),
+    ];
+    let mut output = vec![];
+    explain(&stages, Fusion::Rank, &txs, 0.5, &mut output, Format::Tsv).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    let lines: Vec<Vec<&str>> = output.lines().map(|l| l.split('\t').collect()).collect();
+    assert_eq!(
+        lines[0],
+        vec!["src", "tgt", "nonalphabetic", "nonalphabetic_rank", "well_formed_target", "well_formed_target_rank", "score", "dropped"]
+    );
+    assert_eq!(lines.len(), 4);
+    assert_eq!((lines[1][0], lines[1][7]), ("Good sentence here.", ""));
+    assert_eq!((lines[2][0], lines[2][7]), ("Another one!", "k_top"));
+    assert_eq!((lines[3][0], lines[3][6], lines[3][7]), ("no caps", "", "well_formed_target"));
+    assert_eq!(lines[3][4], "0.000000");
+    // the ranks are the positions Stage::sort gives
+    for (s, stage) in stages.iter().enumerate() {
+        let positions = stage.sort(&txs);
+        for line in &lines[1..] {
+            let tx = txs.iter().find(|tx| &*tx.sides.0.content == line[0]).unwrap();
+            assert_eq!(line[3 + 2 * s], (positions[&tx.id] + 1).to_string());
+        }
+    }
+}
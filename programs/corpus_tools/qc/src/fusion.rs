@@ -0,0 +1,426 @@
+use crate::align;
+use crate::structs::*;
+use rayon::prelude::*;
+use scorers::compression::{Codec, CompressedVsAverage, CompressedVsReference};
+use std::sync::Arc;
+
+// a --pipeline file lists the scorers to run, one per line, each with optional settings:
+//   # scorer          settings
+//   redundancy
+//   out_of_freq       weight=2 cutoff=10000
+//   well_formed       weight=0.5 side=target min=0.5
+//   length_difference max=0.8
+//   alignment         weight=2 min=0.3 threshold=0.2
+//   perplexity        side=target model=de.lm
+// scorer names are qc's flag names, or the line scorers sort.rs shares with it (uppercased, noisy, word_length_variety). weight scales the scorer's part of the fused score (default 1).
+// side is source, target or both (the default); scorers that compare the two sides only take both.
+// alignment also takes threshold= (how likely a word's translation has to be to count, default 0.1) and
+// sample= (how many of the first pairs it learns its word translations from, default 100000).
+// perplexity needs model= (a language model file written by ngrams --model), and is usually given once per side,
+// each with a model of that side's language.
+// compressed_vs_average (how far a side's compressibility is from the average over the first sample= pairs) and
+// compressed_vs_reference (how little a side has in common with the text in reference=<file>) take codec=lz4|gzip|zstd
+// (default lz4); compressed_vs_reference with codec=zstd can also take dictionary=true, to learn a dictionary from
+// the whole reference rather than compress alongside its end.
+// min and max are hard cutoffs on the raw score: a pair outside them is dropped whatever its other scores.
+// --fusion picks how the scores are combined: rank (weighted sum of rank positions, what qc always did),
+// zscore or minmax (weighted sum of each scorer's normalised scores).
+
+pub const SCORERS: &[&str] = &[
+    "redundancy",
+    "translated_partial",
+    "nonalphabetic",
+    "penalise_capitals",
+    "well_formed",
+    "out_of_freq",
+    "unicode_range",
+    "length_difference",
+    "alignment",
+    "perplexity",
+    "compressed_vs_average",
+    "compressed_vs_reference",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Apply {
+    Source,
+    Target,
+    Both,
+}
+
+impl Apply {
+    pub fn from_name(name: &str) -> Result<Apply, String> {
+        match name {
+            "source" | "src" => Ok(Apply::Source),
+            "target" | "tgt" => Ok(Apply::Target),
+            "both" => Ok(Apply::Both),
+            _ => Err(format!("side needs to be source, target or both, not {:?}", name)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fusion {
+    Rank,
+    ZScore,
+    MinMax,
+}
+
+impl Fusion {
+    pub fn from_name(name: &str) -> Result<Fusion, String> {
+        match name.to_lowercase().as_str() {
+            "rank" => Ok(Fusion::Rank),
+            "zscore" | "z-score" => Ok(Fusion::ZScore),
+            "minmax" | "min-max" => Ok(Fusion::MinMax),
+            _ => Err(format!("--fusion needs to be rank, zscore or minmax, not {:?}", name)),
+        }
+    }
+}
+
+/// One line of a pipeline file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StageSpec {
+    pub scorer: String,
+    pub weight: f64,
+    pub apply: Apply,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    /// out_of_freq's vocabulary size, instead of --cutoff
+    pub cutoff: Option<usize>,
+    /// alignment's settings, instead of the defaults in align
+    pub threshold: Option<f64>,
+    pub sample: Option<usize>,
+    /// perplexity's language model file
+    pub model: Option<String>,
+    /// compressed_vs_average's and compressed_vs_reference's settings
+    pub codec: Option<String>,
+    pub reference: Option<String>,
+    pub dictionary: bool,
+}
+
+impl StageSpec {
+    pub fn new(scorer: &str) -> StageSpec {
+        StageSpec {
+            scorer: scorer.to_string(),
+            weight: 1.0,
+            apply: Apply::Both,
+            min: None,
+            max: None,
+            cutoff: None,
+            threshold: None,
+            sample: None,
+            model: None,
+            codec: None,
+            reference: None,
+            dictionary: false,
+        }
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(key: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("{} needs a number, not {:?}", key, value))
+}
+
+pub fn parse_pipeline(contents: &str) -> Result<Vec<StageSpec>, String> {
+    contents
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.split('#').next().unwrap_or_else(|| "").trim()))
+        .filter(|(_, line)| !line.is_empty())
+        .map(|(n, line)| {
+            let mut parts = line.split_whitespace();
+            let scorer = parts.next().unwrap_or_else(|| "");
+            if !SCORERS.contains(&scorer) && !scorers::NAMES.contains(&scorer) {
+                return Err(format!(
+                    "line {}: there's no scorer {:?}, it needs to be one of {}, {}",
+                    n,
+                    scorer,
+                    SCORERS.join(", "),
+                    scorers::NAMES.join(", ")
+                ));
+            }
+            let mut spec = StageSpec::new(scorer);
+            for setting in parts {
+                let (key, value) = setting
+                    .split_once('=')
+                    .ok_or_else(|| format!("line {}: {:?} needs to be key=value", n, setting))?;
+                let set = match key {
+                    "weight" => parse_number(key, value).map(|w| spec.weight = w),
+                    "side" => Apply::from_name(value).map(|a| spec.apply = a),
+                    "min" => parse_number(key, value).map(|m| spec.min = Some(m)),
+                    "max" => parse_number(key, value).map(|m| spec.max = Some(m)),
+                    "cutoff" if scorer == "out_of_freq" => parse_number(key, value).map(|c| spec.cutoff = Some(c)),
+                    "threshold" if scorer == "alignment" => parse_number(key, value).map(|t| spec.threshold = Some(t)),
+                    "sample" if scorer == "alignment" || scorer == "compressed_vs_average" => parse_number(key, value).map(|s| spec.sample = Some(s)),
+                    "model" if scorer == "perplexity" => {
+                        spec.model = Some(value.to_string());
+                        Ok(())
+                    }
+                    "codec" if scorer.starts_with("compressed_vs_") => Codec::from_name(value).map(|_| spec.codec = Some(value.to_string())),
+                    "reference" if scorer == "compressed_vs_reference" => {
+                        spec.reference = Some(value.to_string());
+                        Ok(())
+                    }
+                    "dictionary" if scorer == "compressed_vs_reference" => value
+                        .parse()
+                        .map(|d| spec.dictionary = d)
+                        .map_err(|_| format!("dictionary needs true or false, not {:?}", value)),
+                    _ => Err(format!("{} isn't a setting {} has", key, scorer)),
+                };
+                set.map_err(|e| format!("line {}: {}", n, e))?;
+            }
+            Ok(spec)
+        })
+        .collect()
+}
+
+/// The stages qc's flags turn on, as it ran them before pipeline files: every weight 1, on both sides.
+pub fn from_flags(flags: &[(&str, bool)]) -> Vec<StageSpec> {
+    flags
+        .iter()
+        .filter(|(_, on)| *on)
+        .map(|(scorer, _)| StageSpec::new(scorer))
+        .collect()
+}
+
+/// Where out_of_freq, unicode_range, alignment and compressed_vs_average learn about the corpus from.
+pub enum Corpus<'a> {
+    Pairs(&'a Vec<Translation>),
+    Stats(&'a CorpusStats),
+}
+
+impl Corpus<'_> {
+    /// Up to the first `size` pairs, for the scorers that learn from a sample.
+    fn sample(&self, size: usize) -> &[Translation] {
+        match self {
+            Corpus::Pairs(txs) => &txs[..txs.len().min(size)],
+            Corpus::Stats(stats) => &stats.sample[..stats.sample.len().min(size)],
+        }
+    }
+}
+
+pub struct Stage {
+    /// the scorer's name, with the side if it's only applied to one
+    pub name: String,
+    scorer: Box<dyn ScoreBoth + Send + Sync>,
+    pub weight: f64,
+    pub apply: Apply,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+}
+
+impl Stage {
+    /// Builds the scorer, which for out_of_freq and unicode_range means a pass over all the pairs, or their stats,
+    /// and for alignment learning its word translations from the first of them.
+    pub fn build(spec: &StageSpec, corpus: &Corpus, cutoff: usize) -> Result<Stage, String> {
+        let cutoff = spec.cutoff.unwrap_or_else(|| cutoff);
+        let scorer: Box<dyn ScoreBoth + Send + Sync> = match spec.scorer.as_str() {
+            "redundancy" => Box::new(Redundant),
+            "translated_partial" => Box::new(Untranslated),
+            "nonalphabetic" => Box::new(NonAlphabetic),
+            "penalise_capitals" => Box::new(Capitals),
+            "well_formed" => Box::new(WellFormed),
+            "out_of_freq" => Box::new(match corpus {
+                Corpus::Pairs(txs) => OutOfFrequency::from_txs(txs, cutoff),
+                Corpus::Stats(stats) => OutOfFrequency::from_counts(&stats.words, cutoff),
+            }),
+            "unicode_range" => Box::new(match corpus {
+                Corpus::Pairs(txs) => CharRange::from_txs(txs),
+                Corpus::Stats(stats) => CharRange::from_sums(&stats.ranges),
+            }),
+            "length_difference" => Box::new(LengthDifference),
+            "alignment" => Box::new(align::Alignment::from_txs(
+                corpus.sample(spec.sample.unwrap_or_else(|| align::SAMPLE)),
+                spec.threshold.unwrap_or_else(|| align::THRESHOLD),
+            )),
+            "perplexity" => {
+                let path = spec
+                    .model
+                    .as_ref()
+                    .ok_or_else(|| "perplexity needs model=<file>, a language model from ngrams --model".to_string())?;
+                Box::new(Line(Arc::new(scorers::Perplexity::load(path, 1.0)?)))
+            }
+            "compressed_vs_average" => {
+                let codec = Codec::from_name(spec.codec.as_deref().unwrap_or_else(|| "lz4"))?;
+                let lines: Vec<&str> = corpus
+                    .sample(spec.sample.unwrap_or_else(|| align::SAMPLE))
+                    .iter()
+                    .flat_map(|tx| match spec.apply {
+                        Apply::Source => vec![&*tx.sides.0.content],
+                        Apply::Target => vec![&*tx.sides.1.content],
+                        Apply::Both => vec![&*tx.sides.0.content, &*tx.sides.1.content],
+                    })
+                    .collect();
+                Box::new(Line(Arc::new(CompressedVsAverage::from_lines(codec, &lines, 1.0))))
+            }
+            "compressed_vs_reference" => {
+                let codec = Codec::from_name(spec.codec.as_deref().unwrap_or_else(|| "lz4"))?;
+                let path = spec
+                    .reference
+                    .as_ref()
+                    .ok_or_else(|| "compressed_vs_reference needs reference=<file>".to_string())?;
+                Box::new(Line(Arc::new(CompressedVsReference::load(path, codec, spec.dictionary, 1.0)?)))
+            }
+            name if scorers::NAMES.contains(&name) => Box::new(Line(scorers::by_name(name, 1.0).unwrap())),
+            other => return Err(format!("there's no scorer {:?}", other)),
+        };
+        if spec.apply != Apply::Both && !scorer.per_side() {
+            return Err(format!("{} compares the two sides, so it can only be applied to both", spec.scorer));
+        }
+        let name = match spec.apply {
+            Apply::Both => spec.scorer.clone(),
+            Apply::Source => format!("{}_source", spec.scorer),
+            Apply::Target => format!("{}_target", spec.scorer),
+        };
+        Ok(Stage {
+            name,
+            scorer,
+            weight: spec.weight,
+            apply: spec.apply,
+            min: spec.min,
+            max: spec.max,
+        })
+    }
+
+    pub fn passes(&self, score: f64) -> bool {
+        self.min.map(|min| score >= min).unwrap_or_else(|| true) && self.max.map(|max| score <= max).unwrap_or_else(|| true)
+    }
+}
+
+/// Where each score comes when they're sorted best first.
+pub fn ranks(scores: &[f64]) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..scores.len()).collect();
+    order.sort_by(|a, b| worst_first(scores[*b], scores[*a]));
+    let mut ranks = vec![0; scores.len()];
+    order.iter().enumerate().for_each(|(rank, i)| ranks[*i] = rank);
+    ranks
+}
+
+/// One scorer's scores over the pairs that made every cutoff, put on a common scale where higher is better.
+pub fn normalise(scores: &[f64], fusion: Fusion) -> Vec<f64> {
+    let finite: Vec<f64> = scores.iter().copied().filter(|s| s.is_finite()).collect();
+    let normalised: Vec<f64> = match fusion {
+        // the best score is rank 0, so a rank counts against a pair
+        Fusion::Rank => return ranks(scores).into_iter().map(|rank| -(rank as f64)).collect(),
+        Fusion::ZScore => {
+            let n = finite.len().max(1) as f64;
+            let mean = finite.iter().sum::<f64>() / n;
+            let sd = (finite.iter().map(|s| (s - mean).powi(2)).sum::<f64>() / n).sqrt();
+            scores
+                .iter()
+                .map(|s| if sd > 0.0 { (s - mean) / sd } else { s - mean })
+                .collect()
+        }
+        Fusion::MinMax => {
+            let min = finite.iter().copied().fold(f64::INFINITY, f64::min);
+            let max = finite.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+            scores
+                .iter()
+                .map(|s| if max > min { (s - min) / (max - min) } else { 0.0 })
+                .collect()
+        }
+    };
+    // infinities are as good (or bad) as the best (or worst) real score, and what isn't a number is the worst
+    let (lowest, highest) = normalised.iter().filter(|s| s.is_finite()).fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), s| (lo.min(*s), hi.max(*s)));
+    let (lowest, highest) = if lowest.is_finite() { (lowest, highest) } else { (0.0, 0.0) };
+    normalised
+        .into_iter()
+        .map(|s| match s {
+            s if s.is_finite() => s,
+            s if s == f64::INFINITY => highest,
+            _ => lowest,
+        })
+        .collect()
+}
+
+impl Sorter for Stage {
+    fn scores(&self, txs: &[Translation]) -> Vec<f64> {
+        txs.par_iter()
+            .map(|tx| match self.apply {
+                Apply::Both => self.scorer.score_both(tx),
+                Apply::Source => self.scorer.score_side(&tx.sides.0),
+                Apply::Target => self.scorer.score_side(&tx.sides.1),
+            })
+            .collect()
+    }
+}
+
+/// Every pair's raw score from each stage, stage by stage.
+pub fn score_all(stages: &[Stage], txs: &[Translation]) -> Vec<Vec<f64>> {
+    stages.par_iter().map(|stage| stage.scores(txs)).collect()
+}
+
+/// The fused score of each pair, higher is better, or None for a pair a stage's cutoff dropped.
+pub fn fuse(stages: &[Stage], fusion: Fusion, raw: &[Vec<f64>], len: usize) -> Vec<Option<f64>> {
+    let kept: Vec<usize> = (0..len)
+        .filter(|i| stages.iter().zip(raw).all(|(stage, scores)| stage.passes(scores[*i])))
+        .collect();
+    let mut fused = vec![None; len];
+    kept.iter().for_each(|i| fused[*i] = Some(0.0));
+    for (stage, scores) in stages.iter().zip(raw) {
+        let kept_scores: Vec<f64> = kept.iter().map(|i| scores[*i]).collect();
+        let normalised = normalise(&kept_scores, fusion);
+        kept.iter().zip(normalised).for_each(|(i, s)| {
+            fused[*i] = fused[*i].map(|total| total + stage.weight * s);
+        });
+    }
+    fused
+}
+
+#[test]
+fn test_parse_pipeline() {
+    let specs = parse_pipeline(
+        "# comment\nredundancy\n\nout_of_freq weight=2 cutoff=100  # trailing\nwell_formed side=target min=0.5 max=1\n",
+    )
+    .unwrap();
+    assert_eq!(specs.len(), 3);
+    assert_eq!(specs[0], StageSpec::new("redundancy"));
+    assert_eq!((specs[1].weight, specs[1].cutoff), (2.0, Some(100)));
+    assert_eq!(
+        (specs[2].apply, specs[2].min, specs[2].max),
+        (Apply::Target, Some(0.5), Some(1.0))
+    );
+    assert!(parse_pipeline("redundant").is_err());
+    assert!(parse_pipeline("redundancy cutoff=3").is_err());
+    assert!(parse_pipeline("redundancy weight=heavy").is_err());
+    assert!(parse_pipeline("redundancy side=left").is_err());
+    let lm = parse_pipeline("perplexity side=source model=en.lm").unwrap();
+    assert_eq!((lm[0].apply, lm[0].model.as_deref()), (Apply::Source, Some("en.lm")));
+    assert!(Stage::build(&StageSpec::new("perplexity"), &Corpus::Pairs(&vec![]), 0).is_err());
+    let noisy = parse_pipeline("noisy side=target").unwrap();
+    assert_eq!(Stage::build(&noisy[0], &Corpus::Pairs(&vec![]), 0).unwrap().name, "noisy_target");
+    let compressed = parse_pipeline("compressed_vs_reference codec=zstd reference=ref.txt dictionary=true").unwrap();
+    assert_eq!((compressed[0].codec.as_deref(), compressed[0].dictionary), (Some("zstd"), true));
+    assert!(parse_pipeline("compressed_vs_average codec=brotli").is_err());
+    assert!(parse_pipeline("compressed_vs_average reference=ref.txt").is_err());
+}
+
+#[test]
+fn test_fuse() {
+    let raw = vec![vec![0.9, 0.1, 0.5, f64::NAN], vec![10.0, 30.0, 20.0, 40.0]];
+    let stages: Vec<Stage> = ["nonalphabetic", "penalise_capitals"]
+        .iter()
+        .map(|name| Stage::build(&StageSpec::new(name), &Corpus::Pairs(&vec![]), 0).unwrap())
+        .collect();
+    assert_eq!(normalise(&raw[0], Fusion::Rank), vec![0.0, -2.0, -1.0, -3.0]);
+    assert_eq!(normalise(&raw[0], Fusion::MinMax), vec![1.0, 0.0, 0.5, 0.0]);
+    let z = normalise(&raw[1], Fusion::ZScore);
+    assert!((z.iter().sum::<f64>()).abs() < 1e-9 && z[3] > z[1]);
+    assert_eq!(
+        fuse(&stages, Fusion::Rank, &raw, 4),
+        vec![Some(-3.0), Some(-3.0), Some(-3.0), Some(-3.0)]
+    );
+
+    let mut cut = StageSpec::new("nonalphabetic");
+    cut.min = Some(0.2);
+    let stages = vec![Stage::build(&cut, &Corpus::Pairs(&vec![]), 0).unwrap()];
+    assert_eq!(
+        fuse(&stages, Fusion::MinMax, &raw[..1], 4),
+        vec![Some(1.0), None, Some(0.0), None]
+    );
+    let mut side = StageSpec::new("length_difference");
+    side.apply = Apply::Source;
+    assert!(Stage::build(&side, &Corpus::Pairs(&vec![]), 0).is_err());
+}
@@ -1,19 +1,22 @@
 use term_macros::*;
+mod align;
+mod explain;
+mod fusion;
 mod sort;
+mod stream;
 mod structs;
 mod types;
 use rayon::prelude::*;
+use fusion::*;
 use sort::*;
 use structs::*;
 use types::*;
 use std::io::Write;
 use std::io::Read;
-macro_rules! push {
-    ($boolean:ident ? $struc:expr => $vec:ident) => {
-        if ($boolean) {
-            $vec.push(Box::from($struc));
-        }
-    };
+
+fn exit_with(e: String) -> ! {
+    eprintln!("{}", e);
+    std::process::exit(1)
 }
 
 fn main() {
@@ -29,12 +32,71 @@ fn main() {
             - unicode_range: bool = false;
             //- ideal_length: f64 = 4.0;
             - length_difference: bool = false;
+            - alignment: bool = false;
+            - source_lm: Option<String> = None;
+            - target_lm: Option<String> = None;
             - k_top: f64 = 0.75;
+            - pipeline: Option<String> = None;
+            - fusion: String = "rank".to_string();
+            - explain: bool = false;
+            - explain_format: String = "tsv".to_string();
+            - streaming: bool = false;
+            - spill_dir: Option<String> = None;
         ;
 
         body: || {
+            let fusion = Fusion::from_name(&fusion).unwrap_or_else(|e| exit_with(e));
+            let explain_format = term_macros::records::Format::from_name(&explain_format)
+                .unwrap_or_else(|_| exit_with(format!("--explain_format needs one of tsv, csv, jsonl, not {:?}", explain_format)));
+            let specs = match &pipeline {
+                Some(path) => std::fs::read_to_string(path)
+                    .map_err(|e| format!("couldn't read --pipeline {}: {}", path, e))
+                    .and_then(|contents| parse_pipeline(&contents).map_err(|e| format!("{}: {}", path, e)))
+                    .unwrap_or_else(|e| exit_with(e)),
+                None => {
+                    let mut specs = from_flags(&[
+                        ("redundancy", redundancy),
+                        ("translated_partial", translated_partial),
+                        ("nonalphabetic", nonalphabetic),
+                        ("penalise_capitals", penalise_capitals),
+                        ("well_formed", well_formed),
+                        ("out_of_freq", out_of_freq),
+                        ("unicode_range", unicode_range),
+                        ("length_difference", length_difference),
+                        ("alignment", alignment),
+                    ]);
+                    // --source_lm and --target_lm score each side against a language model of its language
+                    for (path, apply) in [(&source_lm, Apply::Source), (&target_lm, Apply::Target)] {
+                        if let Some(path) = path {
+                            let mut spec = StageSpec::new("perplexity");
+                            spec.apply = apply;
+                            spec.model = Some(path.clone());
+                            specs.push(spec);
+                        }
+                    }
+                    specs
+                }
+            };
+
+            if streaming {
+                if explain {
+                    exit_with("--explain needs every pair in memory, so it can't be used with --streaming".to_string());
+                }
+                let spill_dir = spill_dir.map(std::path::PathBuf::from).unwrap_or_else(std::env::temp_dir);
+                let stdout = std::io::stdout();
+                let mut output = std::io::BufWriter::new(stdout.lock());
+                stream::run(&specs, fusion, cutoff, k_top, term_macros::inputs::sources_or_exit(), &spill_dir, &mut output)
+                    .unwrap_or_else(|e| exit_with(e));
+                return;
+            }
+
             let mut data = String::new();
-            std::io::stdin().read_to_string(&mut data).unwrap();
+            for source in term_macros::inputs::sources_or_exit() {
+                source
+                    .open()
+                    .and_then(|mut input| input.read_to_string(&mut data))
+                    .unwrap_or_else(|e| exit_with(format!("Could not read {}: {}", source.name, e)));
+            }
 
             let lines: Vec<_> = data.par_split(|u| u == '\n')
                 .collect();
@@ -48,25 +110,23 @@ fn main() {
                 })
                 .collect();
 
-            let mut scorers: Vec<Box<dyn Sorter + Send + Sync>> = vec![];
-
-            push!(redundancy ? Redundant => scorers);
-            push!(translated_partial ? Untranslated => scorers);
-            push!(nonalphabetic ? NonAlphabetic => scorers);
-            push!(penalise_capitals ? Capitals => scorers);
-            push!(well_formed ? WellFormed => scorers);
-            push!(out_of_freq ? OutOfFrequency::from_txs(&txs, cutoff) => scorers);
-            push!(unicode_range ? CharRange::from_txs(&txs) => scorers);
-
-            //let use_ideal_length = true;
-            //push!(use_ideal_length ? IdealLength(ideal_length) => scorers);
-            push!(length_difference ? LengthDifference => scorers);
-
-            let sorted_txs = sort(scorers, &mut txs);
+            let stages: Vec<Stage> = specs
+                .iter()
+                .map(|spec| Stage::build(spec, &Corpus::Pairs(&txs), cutoff))
+                .collect::<Result<_, _>>()
+                .unwrap_or_else(|e| exit_with(e));
 
             let stdout = std::io::stdout();
             let mut lock = stdout.lock();
 
+            if explain {
+                explain::explain(&stages, fusion, &txs, k_top, &mut lock, explain_format).unwrap();
+                lock.flush().unwrap();
+                return;
+            }
+
+            let sorted_txs = sort(&stages, fusion, &mut txs);
+
             sorted_txs.iter().take((sorted_txs.len() as f64 * k_top).floor() as usize).for_each(|tx| {
                 lock.write_all(&format!("{}\t{}\n", tx.sides.0.content, tx.sides.1.content).as_bytes()).unwrap();
             });
@@ -1,17 +1,23 @@
-use crate::{Sorter, Translation};
+use crate::fusion::{fuse, score_all, Fusion, Stage};
+use crate::Translation;
 use rayon::prelude::*;
 
-pub fn sort<'a>(
-    sorters: Vec<Box<dyn Sorter + Send + Sync>>,
-    txs: &'a mut Vec<Translation>,
-) -> &'a [Translation] {
-    let sorts: Vec<_> = sorters.par_iter().map(|s| s.sort(txs.as_slice())).collect();
-    let txs_slice = &mut txs[..];
-    txs_slice.par_sort_by_cached_key(|tx| {
-        sorts
-            .iter()
-            .map(|map| *map.get(&tx.id).unwrap())
-            .sum::<usize>()
+/// The pairs that made every cutoff, by index, best fused score first.
+pub fn ranked(fused: &[Option<f64>]) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..fused.len()).filter(|i| fused[*i].is_some()).collect();
+    order.par_sort_by(|a, b| {
+        fused[*b]
+            .partial_cmp(&fused[*a])
+            .unwrap_or_else(|| std::cmp::Ordering::Equal)
     });
-    txs_slice
+    order
+}
+
+/// Drops the pairs a cutoff rules out and orders the rest by fused score, best first.
+pub fn sort<'a>(stages: &[Stage], fusion: Fusion, txs: &'a mut Vec<Translation>) -> &'a [Translation] {
+    let raw = score_all(stages, txs);
+    let fused = fuse(stages, fusion, &raw, txs.len());
+    let mut unsorted: Vec<Option<Translation>> = txs.drain(..).map(Some).collect();
+    txs.extend(ranked(&fused).into_iter().filter_map(|i| unsorted[i].take()));
+    txs.as_slice()
 }
@@ -0,0 +1,443 @@
+use crate::align;
+use crate::fusion::*;
+use crate::structs::*;
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use term_macros::inputs::Source;
+
+// --streaming scores corpora too big to hold in memory, in passes over the input rather than all at once:
+//  1. count the words and codepoint ranges out_of_freq and unicode_range need, and keep the first pairs for
+//     alignment and compressed_vs_average to learn from (stdin is copied into --spill_dir on the way, so it can
+//     be read again)
+//  2. score the pairs a chunk at a time, spilling the raw scores to --spill_dir, and keep each scorer's
+//     count, mean, min and max over the pairs that made every cutoff
+//  3. fuse the spilled scores, spilling those too, and find the fused score the --k_top share is at or above
+//  4. write the pairs at or above it, in input order rather than best first.
+// only the word counts, per-scorer stats and histograms stay in memory. zscore and minmax fusion keep the same pairs
+// qc would without --streaming; rank fusion ranks by histogram, so it keeps nearly but not exactly the same ones.
+
+const CHUNK_LINES: usize = 1 << 16;
+const BINS: usize = 1 << 16;
+
+/// A file in --spill_dir, removed when it's dropped.
+struct Spill {
+    path: PathBuf,
+}
+
+impl Spill {
+    fn new(dir: &Path, name: &str) -> Spill {
+        Spill {
+            path: dir.join(format!("qc_{}_{}.spill", std::process::id(), name)),
+        }
+    }
+
+    fn create(&self) -> Result<BufWriter<File>, String> {
+        File::create(&self.path)
+            .map(BufWriter::new)
+            .map_err(|e| format!("couldn't write {}: {}", self.path.display(), e))
+    }
+
+    fn open(&self) -> Result<BufReader<File>, String> {
+        File::open(&self.path)
+            .map(BufReader::new)
+            .map_err(|e| format!("couldn't read {}: {}", self.path.display(), e))
+    }
+}
+
+impl Drop for Spill {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+fn io_error(e: std::io::Error) -> String {
+    format!("qc --streaming: {}", e)
+}
+
+fn write_f64(output: &mut impl Write, value: f64) -> Result<(), String> {
+    output.write_all(&value.to_le_bytes()).map_err(io_error)
+}
+
+fn read_f64(input: &mut impl Read) -> Result<f64, String> {
+    let mut bytes = [0; 8];
+    input.read_exact(&mut bytes).map_err(io_error)?;
+    Ok(f64::from_le_bytes(bytes))
+}
+
+/// A line's pair, by the same rule as without --streaming: it needs a tab and more than 5 bytes.
+fn pair(line: &str, id: u64) -> Option<Translation> {
+    if !(line.contains('\t') && line.len() > 5) {
+        return None;
+    }
+    let mut parts = line.split('\t');
+    Some(Translation::new(parts.next().unwrap(), parts.next().unwrap(), id))
+}
+
+/// Reads every line of the sources, a chunk at a time, each without its newline.
+fn for_chunks(sources: &[Source], mut process: impl FnMut(&[String]) -> Result<(), String>) -> Result<(), String> {
+    let mut chunk = Vec::with_capacity(CHUNK_LINES);
+    for source in sources {
+        let mut reader = BufReader::new(source.open().map_err(|e| format!("couldn't read {}: {}", source.name, e))?);
+        let mut line = vec![];
+        while reader.read_until(b'\n', &mut line).map_err(io_error)? > 0 {
+            if line.ends_with(b"\n") {
+                line.pop();
+            }
+            chunk.push(String::from_utf8_lossy(&line).to_string());
+            line.clear();
+            if chunk.len() == CHUNK_LINES {
+                process(&chunk)?;
+                chunk.clear();
+            }
+        }
+    }
+    if !chunk.is_empty() {
+        process(&chunk)?;
+    }
+    Ok(())
+}
+
+fn pairs(chunk: &[String], first_id: u64) -> Vec<Translation> {
+    use rayon::prelude::*;
+    chunk
+        .par_iter()
+        .enumerate()
+        .filter_map(|(i, line)| pair(line, first_id + i as u64))
+        .collect()
+}
+
+/// One scorer's scores over the pairs that made every cutoff, as far as normalising them needs.
+#[derive(Clone)]
+struct Summary {
+    finite: usize,
+    sum: f64,
+    squares: f64,
+    min: f64,
+    max: f64,
+    // for rank fusion: how many finite scores fall in each of BINS between min and max, and how many are +inf
+    bins: Vec<usize>,
+    infinite: usize,
+}
+
+impl Summary {
+    fn new() -> Summary {
+        Summary {
+            finite: 0,
+            sum: 0.0,
+            squares: 0.0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+            bins: vec![],
+            infinite: 0,
+        }
+    }
+
+    fn mean(&self) -> f64 {
+        self.sum / self.finite.max(1) as f64
+    }
+
+    fn sd(&self) -> f64 {
+        (self.squares / self.finite.max(1) as f64).sqrt()
+    }
+
+    fn bin(&self, score: f64) -> usize {
+        bin(score, self.min, self.max)
+    }
+
+    /// Roughly how many pairs score better, as normalise's ranks have it.
+    fn rank(&self, score: f64) -> f64 {
+        let finite: usize = self.bins.iter().sum();
+        if score == f64::INFINITY {
+            return self.infinite as f64 / 2.0;
+        }
+        if !score.is_finite() {
+            return (self.infinite + finite) as f64;
+        }
+        let b = self.bin(score);
+        (self.infinite + self.bins[b + 1..].iter().sum::<usize>()) as f64 + self.bins[b] as f64 / 2.0
+    }
+
+    /// The same as normalise gives for one score, from the summary rather than all the scores.
+    fn normalise(&self, score: f64, fusion: Fusion) -> f64 {
+        let scale = |s: f64| match fusion {
+            Fusion::Rank => -self.rank(s),
+            Fusion::ZScore => match self.sd() > 0.0 {
+                true => (s - self.mean()) / self.sd(),
+                false => s - self.mean(),
+            },
+            Fusion::MinMax => match self.max > self.min {
+                true => (s - self.min) / (self.max - self.min),
+                false => 0.0,
+            },
+        };
+        match score {
+            s if s.is_finite() || fusion == Fusion::Rank => scale(s),
+            _ if self.finite == 0 => 0.0,
+            s if s == f64::INFINITY => scale(self.max),
+            _ => scale(self.min),
+        }
+    }
+}
+
+fn bin(score: f64, min: f64, max: f64) -> usize {
+    match max > min {
+        true => (((score - min) / (max - min)) * (BINS - 1) as f64) as usize,
+        false => 0,
+    }
+}
+
+fn passes(stages: &[Stage], scores: &[f64]) -> bool {
+    stages.iter().zip(scores).all(|(stage, score)| stage.passes(*score))
+}
+
+/// Goes over the spilled raw scores, a pair at a time.
+fn for_spilled(spill: &Spill, stages: usize, pairs: usize, mut process: impl FnMut(&[f64])) -> Result<(), String> {
+    let mut input = spill.open()?;
+    let mut scores = vec![0.0; stages];
+    for _ in 0..pairs {
+        for score in scores.iter_mut() {
+            *score = read_f64(&mut input)?;
+        }
+        process(&scores);
+    }
+    Ok(())
+}
+
+pub fn run(
+    specs: &[StageSpec],
+    fusion: Fusion,
+    cutoff: usize,
+    k_top: f64,
+    sources: Vec<Source>,
+    spill_dir: &Path,
+    output: &mut impl Write,
+) -> Result<(), String> {
+    // 1: corpus stats, and stdin put somewhere it can be read from again
+    let words = specs.iter().any(|s| s.scorer == "out_of_freq");
+    let ranges = specs.iter().any(|s| s.scorer == "unicode_range");
+    let sample = specs
+        .iter()
+        .filter(|s| s.scorer == "alignment" || s.scorer == "compressed_vs_average")
+        .map(|s| s.sample.unwrap_or_else(|| align::SAMPLE))
+        .max()
+        .unwrap_or_else(|| 0);
+    let stdin_copy = Spill::new(spill_dir, "stdin");
+    let reads_stdin = sources.iter().any(|s| s.name == "-");
+    if reads_stdin && sources.len() > 1 {
+        return Err("qc --streaming reads stdin or --input files, not both".to_string());
+    }
+    let mut copy = match reads_stdin {
+        true => Some(stdin_copy.create()?),
+        false => None,
+    };
+    let mut stats = CorpusStats::default();
+    let mut line_id = 0;
+    for_chunks(&sources, |chunk| {
+        if let Some(copy) = copy.as_mut() {
+            chunk
+                .iter()
+                .try_for_each(|line| writeln!(copy, "{}", line))
+                .map_err(io_error)?;
+        }
+        if words || ranges || stats.sample.len() < sample {
+            pairs(chunk, line_id).iter().for_each(|tx| stats.add(tx, words, ranges, sample));
+        }
+        line_id += chunk.len() as u64;
+        Ok(())
+    })?;
+    let sources = match copy.take() {
+        Some(mut copy) => {
+            copy.flush().map_err(io_error)?;
+            term_macros::inputs::expand(&[stdin_copy.path.display().to_string()])?
+        }
+        None => sources,
+    };
+    let stages: Vec<Stage> = specs
+        .iter()
+        .map(|spec| Stage::build(spec, &Corpus::Stats(&stats), cutoff))
+        .collect::<Result<_, _>>()?;
+    drop(stats);
+
+    // 2: raw scores, to disk, and what fusing them needs to know
+    let raw_spill = Spill::new(spill_dir, "scores");
+    let mut raw_output = raw_spill.create()?;
+    let mut summaries = vec![Summary::new(); stages.len()];
+    let mut pair_count = 0;
+    let mut line_id = 0;
+    for_chunks(&sources, |chunk| {
+        let txs = pairs(chunk, line_id);
+        line_id += chunk.len() as u64;
+        let raw: Vec<Vec<f64>> = stages.iter().map(|stage| stage.scores(&txs)).collect();
+        for i in 0..txs.len() {
+            let scores: Vec<f64> = raw.iter().map(|s| s[i]).collect();
+            scores.iter().try_for_each(|s| write_f64(&mut raw_output, *s))?;
+            if passes(&stages, &scores) {
+                for (summary, score) in summaries.iter_mut().zip(&scores) {
+                    if score.is_finite() {
+                        summary.finite += 1;
+                        summary.sum += score;
+                        summary.min = summary.min.min(*score);
+                        summary.max = summary.max.max(*score);
+                    }
+                }
+            }
+        }
+        pair_count += txs.len();
+        Ok(())
+    })?;
+    raw_output.flush().map_err(io_error)?;
+    drop(raw_output);
+
+    // 3: the spread or histogram of each scorer's scores, then the fused scores
+    if fusion != Fusion::MinMax {
+        summaries.iter_mut().for_each(|summary| summary.bins = vec![0; BINS]);
+        for_spilled(&raw_spill, stages.len(), pair_count, |scores| {
+            if !passes(&stages, scores) {
+                return;
+            }
+            for (summary, score) in summaries.iter_mut().zip(scores) {
+                match score {
+                    s if s.is_finite() => {
+                        summary.squares += (s - summary.mean()).powi(2);
+                        let b = summary.bin(*s);
+                        summary.bins[b] += 1;
+                    }
+                    s if *s == f64::INFINITY => summary.infinite += 1,
+                    _ => {}
+                }
+            }
+        })?;
+    }
+    let fused_spill = Spill::new(spill_dir, "fused");
+    let mut fused_output = fused_spill.create()?;
+    let (mut kept, mut min, mut max) = (0, f64::INFINITY, f64::NEG_INFINITY);
+    let mut result = Ok(());
+    for_spilled(&raw_spill, stages.len(), pair_count, |scores| {
+        let fused = match passes(&stages, scores) {
+            true => stages
+                .iter()
+                .zip(scores)
+                .zip(&summaries)
+                .map(|((stage, score), summary)| stage.weight * summary.normalise(*score, fusion))
+                .sum::<f64>(),
+            false => f64::NAN,
+        };
+        if !fused.is_nan() {
+            kept += 1;
+            min = min.min(fused);
+            max = max.max(fused);
+        }
+        if result.is_ok() {
+            result = write_f64(&mut fused_output, fused);
+        }
+    })?;
+    result?;
+    fused_output.flush().map_err(io_error)?;
+    drop(fused_output);
+    drop(raw_spill);
+
+    // the fused score the best k_top share is at or above: first which bin it's in, then where in the bin
+    // a --k_top above 1 keeps everything, as take() does without --streaming
+    let wanted = ((kept as f64 * k_top).floor() as usize).min(kept);
+    let read_fused = |process: &mut dyn FnMut(f64)| -> Result<(), String> {
+        let mut input = fused_spill.open()?;
+        for _ in 0..pair_count {
+            process(read_f64(&mut input)?);
+        }
+        Ok(())
+    };
+    let mut threshold = (f64::INFINITY, 0);
+    if wanted > 0 {
+        let mut bins = vec![0; BINS];
+        read_fused(&mut |fused| {
+            if !fused.is_nan() {
+                bins[bin(fused, min, max)] += 1;
+            }
+        })?;
+        let mut above = 0;
+        let mut b = BINS - 1;
+        while above + bins[b] < wanted {
+            above += bins[b];
+            b -= 1;
+        }
+        let mut in_bin = Vec::with_capacity(bins[b]);
+        read_fused(&mut |fused| {
+            if !fused.is_nan() && bin(fused, min, max) == b {
+                in_bin.push(fused);
+            }
+        })?;
+        in_bin.sort_by(|a, b| b.partial_cmp(a).unwrap_or_else(|| std::cmp::Ordering::Equal));
+        let score = in_bin[wanted - above - 1];
+        let better = above + in_bin.iter().filter(|s| **s > score).count();
+        // ties at the threshold are kept in input order, as the stable sort without --streaming keeps them
+        threshold = (score, wanted - better);
+    }
+
+    // 4: the pairs that made it
+    let (score, mut ties) = threshold;
+    let mut fused_input = fused_spill.open()?;
+    let mut line_id = 0;
+    for_chunks(&sources, |chunk| {
+        let txs = pairs(chunk, line_id);
+        line_id += chunk.len() as u64;
+        for tx in txs {
+            let fused = read_f64(&mut fused_input)?;
+            let keep = fused > score || (fused == score && ties > 0);
+            if fused == score && keep {
+                ties -= 1;
+            }
+            if keep {
+                writeln!(output, "{}\t{}", tx.sides.0.content, tx.sides.1.content).map_err(io_error)?;
+            }
+        }
+        Ok(())
+    })?;
+    output.flush().map_err(io_error)
+}
+
+#[test]
+fn test_streaming_matches_in_memory() {
+    let dir = std::env::temp_dir().join(format!("qc_stream_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let words = ["the", "cat", "Der", "Hund", "sat", "lief", "on", "weg", "mat", "THE", "far"];
+    let text: String = (0..500)
+        .map(|i: usize| {
+            let side = |n: usize| (0..(n % 9) + 1).map(|j| words[(i * 7 + j * n) % words.len()]).collect::<Vec<_>>().join(" ");
+            format!("{}{}\t{}\n", side(i), [".", "", ""][i % 3], side(i / 2 + 3))
+        })
+        .collect();
+    let path = dir.join("pairs.tsv");
+    std::fs::write(&path, &text).unwrap();
+    let specs = parse_pipeline("redundancy weight=2\nwell_formed side=source\nout_of_freq cutoff=100\nunicode_range\nlength_difference max=0.5\n").unwrap();
+
+    for (fusion, k_top) in [(Fusion::ZScore, 0.5), (Fusion::MinMax, 0.5), (Fusion::ZScore, 2.0)] {
+        let mut txs: Vec<Translation> = text
+            .split('\n')
+            .enumerate()
+            .filter_map(|(i, line)| pair(line, i as u64))
+            .collect();
+        let stages: Vec<Stage> = specs
+            .iter()
+            .map(|spec| Stage::build(spec, &Corpus::Pairs(&txs), 5000).unwrap())
+            .collect();
+        let sorted = crate::sort::sort(&stages, fusion, &mut txs);
+        let mut expected: Vec<String> = sorted
+            .iter()
+            .take((sorted.len() as f64 * k_top).floor() as usize)
+            .map(|tx| format!("{}\t{}", tx.sides.0.content, tx.sides.1.content))
+            .collect();
+
+        let mut output = vec![];
+        let sources = term_macros::inputs::expand(&[path.display().to_string()]).unwrap();
+        run(&specs, fusion, 5000, k_top, sources, &dir, &mut output).unwrap();
+        let mut streamed: Vec<String> = String::from_utf8(output).unwrap().lines().map(|l| l.to_string()).collect();
+        expected.sort();
+        streamed.sort();
+        assert_eq!(streamed, expected, "{:?} {}", fusion, k_top);
+    }
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -40,6 +40,13 @@ pub trait ScoreOne {
 
 pub trait ScoreBoth {
     fn score_both(&self, tx: &Translation) -> f64;
+    /// Whether this scores each side on its own (score_both being the sum), so it can be applied to one side.
+    fn per_side(&self) -> bool {
+        false
+    }
+    fn score_side(&self, _side: &Side) -> f64 {
+        f64::NAN
+    }
 }
 
 impl<T> ScoreBoth for T
@@ -49,25 +56,29 @@ where
     fn score_both(&self, tx: &Translation) -> f64 {
         self.score_one(&tx.sides.0) + self.score_one(&tx.sides.1)
     }
+    fn per_side(&self) -> bool {
+        true
+    }
+    fn score_side(&self, side: &Side) -> f64 {
+        self.score_one(side)
+    }
 }
 
-pub trait Sorter {
-    fn sort(&self, txs: &[Translation]) -> SortPositions;
+// scores that aren't numbers (e.g. from an empty side) count as the worst
+pub fn worst_first(a: f64, b: f64) -> std::cmp::Ordering {
+    match (a.is_nan(), b.is_nan()) {
+        (false, false) => a.partial_cmp(&b).unwrap_or_else(|| std::cmp::Ordering::Equal),
+        (a, b) => b.cmp(&a),
+    }
 }
 
-impl<T> Sorter for T
-where
-    T: ScoreBoth + Send + Sync,
-{
+pub trait Sorter {
+    /// The raw score of every pair, in order.
+    fn scores(&self, txs: &[Translation]) -> Vec<f64>;
+
     fn sort(&self, txs: &[Translation]) -> SortPositions {
-        let mut scored_txs: Vec<_> = txs
-            .iter()
-            .map(|tx| (tx, self.score_both(&tx)))
-            .collect();
-        scored_txs.sort_by(|a, b| {
-            b.1.partial_cmp(&a.1)
-                .unwrap_or_else(|| std::cmp::Ordering::Equal)
-        });
+        let mut scored_txs: Vec<_> = txs.iter().zip(self.scores(txs)).collect();
+        scored_txs.sort_by(|a, b| worst_first(b.1, a.1));
         scored_txs
             .into_iter()
             .enumerate()
@@ -76,6 +87,15 @@ where
     }
 }
 
+impl<T> Sorter for T
+where
+    T: ScoreBoth + Send + Sync,
+{
+    fn scores(&self, txs: &[Translation]) -> Vec<f64> {
+        txs.iter().map(|tx| self.score_both(tx)).collect()
+    }
+}
+
 // 1 is good, 0 is bad
 
 pub struct Redundant;
@@ -101,27 +121,32 @@ pub struct OutOfFrequency {
     freqs: HashSet<Arc<str>>,
 }
 
+/// Adds a side's words to the counts OutOfFrequency is built from.
+pub fn count_words(map: &mut HashMap<Arc<str>, usize>, side: &Side) {
+    side.words.iter().for_each(|w| {
+        let prev = map.get(w).map(|i| *i).unwrap_or_else(|| 0);
+        map.insert(Arc::from(w.to_lowercase().as_str()), prev + 1);
+    });
+}
+
 impl OutOfFrequency {
     pub fn from_txs(txs: &Vec<Translation>, cutoff: usize) -> OutOfFrequency {
         let mut map = HashMap::new();
         txs.iter().for_each(|tx| {
-            tx.sides.0.words.iter().for_each(|w| {
-                let prev = map.get(w).map(|i| *i).unwrap_or_else(|| 0);
-                map.insert(Arc::from(w.to_lowercase().as_str()), prev + 1);
-            });
-            tx.sides.1.words.iter().for_each(|w| {
-                let prev = map.get(w).map(|i| *i).unwrap_or_else(|| 0);
-                map.insert(Arc::from(w.to_lowercase().as_str()), prev + 1);
-            });
+            count_words(&mut map, &tx.sides.0);
+            count_words(&mut map, &tx.sides.1);
         });
+        OutOfFrequency::from_counts(&map, cutoff)
+    }
 
-        let mut freqs: Vec<_> = map.into_iter().collect();
+    pub fn from_counts(map: &HashMap<Arc<str>, usize>, cutoff: usize) -> OutOfFrequency {
+        let mut freqs: Vec<_> = map.iter().collect();
         freqs.sort_by(|a, b| {
             b.1.partial_cmp(&a.1)
                 .unwrap_or_else(|| std::cmp::Ordering::Equal)
         });
         OutOfFrequency {
-            freqs: freqs.into_iter().take(cutoff).map(|(w, _)| w).collect(),
+            freqs: freqs.into_iter().take(cutoff).map(|(w, _)| w.clone()).collect(),
         }
     }
 }
@@ -207,6 +232,56 @@ impl CharRange {
             side_2: CharRange::calc_range(txs, 1),
         }
     }
+    pub fn from_sums(sums: &RangeSums) -> CharRange {
+        let average = |(max, min): (usize, usize)| (max / sums.pairs.max(1), min / sums.pairs.max(1));
+        CharRange {
+            side_1: average(sums.sides[0]),
+            side_2: average(sums.sides[1]),
+        }
+    }
+}
+
+/// What CharRange::calc_range adds up, a pair at a time: each side's highest and lowest codepoints, summed.
+#[derive(Default, Clone)]
+pub struct RangeSums {
+    sides: [(usize, usize); 2],
+    pairs: usize,
+}
+
+impl RangeSums {
+    pub fn add(&mut self, tx: &Translation) {
+        self.pairs += 1;
+        for (sum, side) in self.sides.iter_mut().zip([&tx.sides.0, &tx.sides.1]) {
+            let chars = get_codepoints(&side.content);
+            if let (Some(max), Some(min)) = (chars.iter().max(), chars.iter().min()) {
+                *sum = (sum.0 + max, sum.1 + min);
+            }
+        }
+    }
+}
+
+/// The corpus statistics OutOfFrequency, CharRange, Alignment and CompressedVsAverage need, gathered in a pass over the input when it isn't all in memory.
+#[derive(Default)]
+pub struct CorpusStats {
+    pub words: HashMap<Arc<str>, usize>,
+    pub ranges: RangeSums,
+    /// the first pairs, for Alignment and CompressedVsAverage to learn from
+    pub sample: Vec<Translation>,
+}
+
+impl CorpusStats {
+    pub fn add(&mut self, tx: &Translation, words: bool, ranges: bool, sample: usize) {
+        if self.sample.len() < sample {
+            self.sample.push(Translation::new(&tx.sides.0.content, &tx.sides.1.content, tx.id));
+        }
+        if words {
+            count_words(&mut self.words, &tx.sides.0);
+            count_words(&mut self.words, &tx.sides.1);
+        }
+        if ranges {
+            self.ranges.add(tx);
+        }
+    }
 }
 
 impl ScoreBoth for CharRange {
@@ -252,6 +327,19 @@ impl ScoreOne for IdealLength {
     }
 }
 
+/// One of the scorers crate's line scorers (the ones sort.rs uses), run on a side at a time.
+pub struct Line(pub scorers::SharedScorer);
+
+impl ScoreOne for Line {
+    fn score_one(&self, tx: &Side) -> f64 {
+        let score = self.0.score(&tx.content);
+        match self.0.polarity() {
+            scorers::Polarity::HigherBetter => score,
+            scorers::Polarity::LowerBetter => -score,
+        }
+    }
+}
+
 pub struct LengthDifference;
 
 impl ScoreBoth for LengthDifference {
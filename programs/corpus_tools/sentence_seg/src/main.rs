@@ -1,16 +1,39 @@
+use segmenter::punkt::Learned;
+use segmenter::Segmenter;
 use term_macros::*;
-use unicode_segmentation::UnicodeSegmentation;
+
+// one sentence per line, by the segmenter in shared/segmenter rather than unicode's sentence boundaries, which
+// split after every abbreviation: --lang picks its abbreviation list, --model adds a model from sentences --train.
+
+fn exit_with(e: String) -> ! {
+    eprintln!("{}", e);
+    std::process::exit(1)
+}
 
 fn main() {
-    readin!(writer, |lns: &[u8]| {
+    tool! {
+        args:
+            - lang: String = "en".to_string();
+            - model: Option<String> = None;
+        ;
+
+        body: || {
+            let mut segmenter = Segmenter::new(&lang).unwrap_or_else(|e| exit_with(e));
+            if let Some(path) = &model {
+                segmenter = segmenter.learn(&Learned::load(path).unwrap_or_else(|e| exit_with(e)));
+            }
+
+            readin!(writer, |lns: &[u8]| {
 
-        let _ = std::str::from_utf8(lns)
+                let _ = std::str::from_utf8(lns)
 
-            .map(|lns| {
-                lns.unicode_sentences().for_each(|line| {
-                    writer.write(line.replace("\\n", " ").replace("\n", " ").replace("\\\"", "").as_bytes()).unwrap();
-                    writer.write("\n".as_bytes()).unwrap();
-                });
+                    .map(|lns| {
+                        segmenter.split(lns).into_iter().for_each(|line| {
+                            writer.write_all(line.replace("\\n", " ").replace("\n", " ").replace("\\\"", "").as_bytes()).unwrap();
+                            writer.write_all("\n".as_bytes()).unwrap();
+                        });
+                    });
             });
-    });
+        }
+    };
 }
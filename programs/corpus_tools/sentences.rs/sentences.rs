@@ -2,15 +2,27 @@
 //! ```cargo
 //! [dependencies]
 //! term_macros = { path = "../../shared/term_macros"  }
+//! segmenter = { path = "../../shared/segmenter"  }
 //! ```
-use std::collections::HashSet;
+use segmenter::punkt::{Learned, Trainer};
+use segmenter::Segmenter;
+use std::io::BufRead;
 use term_macros::*;
 //use std::iter::FromIterator;
 //use std::sync::Arc;
 
-fn main() {
-    let delims = "..۔܁܂።᙮᠃᠉。..꓿꘎｡?¿;՞؟፧⁇⁈⁉≟⍰⸮꘏!¡՜߹႟᥄‼؟⁈܀፨";
+// one sentence per output line, split by the segmenter in shared/segmenter: --lang picks its abbreviation list,
+// --model adds what `sentences --train model.txt` learnt from a corpus, and --add, --remove and --only_use change
+// which characters end sentences. ; still ends one here, as it always has, though the segmenter leaves it out for
+// sentence_seg; like any terminal now, it doesn't before a lowercase word. ¿ and ¡ start sentences, so they no
+// longer end them too.
+
+fn exit_with(e: String) -> ! {
+    eprintln!("{}", e);
+    std::process::exit(1)
+}
 
+fn main() {
     tool! {
         args:
             - add: String = String::new();
@@ -19,31 +31,41 @@ fn main() {
             - reject_uncapitalized;
             - reject_unpunctuated;
             - min_length: usize = 1;
+            - lang: String = "en".to_string();
+            - model: Option<String> = None;
+            - train: Option<String> = None;
         ;
 
         body: || {
 
-            let mut set = HashSet::new();
-
-            match only_use.len() > 0 {
-                false => {
-                    delims.chars().for_each(|c| {set.insert(c);});
-                    add.chars().for_each(|c| {set.insert(c);});
-                    remove.chars().for_each(|c| {set.remove(&c);});
+            if let Some(path) = &train {
+                let mut trainer = Trainer::default();
+                for source in term_macros::inputs::sources_or_exit() {
+                    let reader = std::io::BufReader::new(source.open_or_panic());
+                    reader.lines().map_while(Result::ok).for_each(|line| trainer.add(&line));
                 }
-                true => only_use.chars().for_each(|c| {set.insert(c);})
+                trainer.finish().save(path).unwrap_or_else(|e| exit_with(e));
+                return;
+            }
+
+            let mut segmenter = Segmenter::new(&lang).unwrap_or_else(|e| exit_with(e));
+            segmenter = match only_use.is_empty() {
+                true => segmenter.add_terminals(";").add_terminals(&add).remove_terminals(&remove),
+                false => segmenter.only_terminals(&only_use),
             };
+            if let Some(path) = &model {
+                segmenter = segmenter.learn(&Learned::load(path).unwrap_or_else(|e| exit_with(e)));
+            }
 
-            readin!(writer, |lns: &[u8]| {
+            par_readin!(writer, |lns: &[u8]| {
                 let lns = std::str::from_utf8(lns);
                 if lns.is_err() {
                     return;
                 }
                 let lns = lns.unwrap();
-                lns
-                .split_inclusive(
-                    |c| set.contains(&c)
-                )
+                segmenter
+                .split(lns)
+                .into_iter()
                 .for_each(|line| {
                     if line.len() > min_length &&
                     (!reject_uncapitalized || line.chars().filter(|c| c.is_alphabetic()).next().map(|c| c.is_uppercase()).unwrap_or_else(|| false)) && (!reject_unpunctuated || line.chars().last().map(|c| !c.is_alphanumeric()).unwrap_or_else(|| false)) && !line.contains(" .") {
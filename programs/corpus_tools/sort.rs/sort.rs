@@ -2,6 +2,7 @@
 //! ```cargo
 //! [dependencies]
 //! term_macros = { path = "../../shared/term_macros"  }
+//! scorers = { path = "../../shared/scorers" }
 //! ```
 
 #![allow(dead_code)]
@@ -12,24 +13,102 @@
 fn main() {
 
     // ideally it should be flexible enough to just take regex, compile or reject them, and then perform calculations based on using them as filters.
-    // seems like fundamental compositional pattern is: divide into units (bytes, chars, words), measure properties of each, normalise to btwn 0 and 1
     // awk?
+    // three modes: by default keep lines whose average score is over --min; --stats prints each scorer's
+    // distribution over the input instead; --filter noisy:20,uppercased:5 drops about the worst 20% of lines
+    // by Noisy and the worst 5% by Uppercased, reading the whole input first to find where those cutoffs fall.
+    // --filter times its scorers on the first --warmup lines and runs the cheapest per line turned down first;
+    // --verbose says which order that was.
+    // --reference scores lines by how little they share with a file of text like what's wanted, compressed with
+    // --codec (and with --dictionary, a zstd dictionary learnt from it); --against_average by how far their
+    // compressibility is from the input's average, which only --stats and --filter read the input first to find.
 
     tool! {
 
-        args: 
+        args:
             - min: f64 = 0.94;
             - variety_filter: bool = true;
+            - lm: Option<String> = None;
+            - stats: bool = false;
+            - bins: usize = 10;
+            - filter: Option<String> = None;
+            - warmup: usize = 1000;
+            - verbose: bool = false;
+            - reference: Option<String> = None;
+            - codec: String = "lz4".to_string();
+            - dictionary: bool = false;
+            - against_average: bool = false;
         ;
 
         body: || {
-            let mut scorer = SoftScorer::default();
-            scorer.add_scorer(Uppercased { weight: 1.0 });
-            scorer.add_scorer(Noisy { weight: 1.0 });
+            let mut active: Vec<SharedScorer> = vec![Arc::new(Uppercased::new(1.0)), Arc::new(Noisy::new(1.0))];
             if variety_filter {
-                scorer.add_scorer(WordLengthVariety { weight: 1.0 });
+                active.push(Arc::new(WordLengthVariety::new(1.0)));
+            }
+            // a language model from ngrams --model, so lines that don't read like its language score low
+            if let Some(path) = &lm {
+                active.push(Arc::new(Perplexity::load(path, 1.0).unwrap_or_else(|e| exit_with(e))));
+            }
+
+            if let Some(path) = &reference {
+                let codec = Codec::from_name(&codec).unwrap_or_else(|e| exit_with(e));
+                let scorer = CompressedVsReference::load(path, codec, dictionary, 1.0).unwrap_or_else(|e| exit_with(e));
+                active.push(Arc::new(scorer));
+            }
+
+            if stats || filter.is_some() {
+                let lines = read_lines();
+                if against_average {
+                    let codec = Codec::from_name(&codec).unwrap_or_else(|e| exit_with(e));
+                    active.push(Arc::new(CompressedVsAverage::from_lines(codec, &lines, 1.0)));
+                }
+                if stats {
+                    let stdout = std::io::stdout();
+                    let mut lock = stdout.lock();
+                    for scorer in &active {
+                        let distribution = Distribution::new(lines.iter().map(|line| scorer.score(line)).collect());
+                        let _ = write_stats(&mut lock, scorer.name(), scorer.polarity(), &distribution, bins);
+                    }
+                    return;
+                }
+                let percents = parse_filter(filter.as_deref().unwrap_or_else(|| "")).unwrap_or_else(|e| exit_with(e));
+                let mut hard = HardScorer::default();
+                for (name, percent) in percents {
+                    let scorer = active.iter().find(|s| is_named(s.as_ref(), &name)).unwrap_or_else(|| {
+                        let names: Vec<&str> = active.iter().map(|s| s.name()).collect();
+                        exit_with(format!("--filter can use {}, not {:?}", names.join(", "), name))
+                    });
+                    let distribution = Distribution::new(lines.iter().map(|line| scorer.score(line)).collect());
+                    if let Some(cutoff) = distribution.cutoff(percent, scorer.polarity()) {
+                        hard.add_shared(scorer.clone(), cutoff);
+                    }
+                }
+                let calibrations = hard.calibrate(&lines[..lines.len().min(warmup)]);
+                if verbose {
+                    eprintln!(
+                        "--filter order, from {} warm-up lines (about {:.0}ns a line):",
+                        lines.len().min(warmup),
+                        expected_nanos(&calibrations)
+                    );
+                    for (i, c) in calibrations.iter().enumerate() {
+                        eprintln!("  {}. {} ({:.0}ns a line, turns down {:.1}%)", i + 1, c.name, c.nanos, c.rejected * 100.0);
+                    }
+                }
+                let mut writer = std::io::BufWriter::new(std::io::stdout());
+                for line in lines.iter().filter(|line| hard.score(line)) {
+                    if writeln!(writer, "{}", line).is_err() {
+                        break;
+                    }
+                }
+                let _ = writer.flush();
+                return;
             }
 
+            if against_average {
+                exit_with("--against_average needs --stats or --filter, which read the input before scoring it".to_string());
+            }
+            let mut scorer = SoftScorer::default();
+            active.into_iter().for_each(|s| scorer.add_shared(s));
 
             filter_in!(|line: &[u8]| {
                 let score = scorer.score(std::str::from_utf8(line).unwrap());
@@ -43,173 +122,41 @@ fn main() {
 }
 
 use log::*;
+use scorers::compression::{Codec, CompressedVsAverage, CompressedVsReference};
+use scorers::stats::{parse_filter, write_stats, Distribution};
+use scorers::*;
 use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 use std::iter::FromIterator;
 use std::io::prelude::*;
 use core::fmt::Debug;
 use term_macros::*;
-// info on top 20%
-// so two modes: provide statistics, and actually do the filtering
 // could use a chunked sorting approach
 
-enum Polarity {
-    HigherBetter,
-    LowerBetter
-}
-
-trait Scorer: Debug {
-    fn polarity(&self) -> Polarity;
-    fn score(&self, sentence: &str) -> f64;
-    fn name(&self) -> &str;
+fn exit_with(e: String) -> ! {
+    eprintln!("{}", e);
+    std::process::exit(1)
 }
-// initialisation needs to be handled case-by-case. scorerfactory?
-/*
-
-fn uppercased(&self) -> Policy<f64>;
-fn long(&self) -> Policy<f64>;
-fn noisy(&self) -> Policy<f64>;
-fn gzip_against_average(&self) -> Policy<f64>; // how gzippable is it by itself compared to the average?
-fn gzip_against_reference(&self) -> Policy<f64>; // how gzippable is it compared to the reference corpus? <-  this is more powerful than the threshold based language detector and more likely to yield good results because it will take relatives into account.
-fn infrequent(&self) -> Policy<f64>;
-fn weird_whitespace(&self) -> Policy<f64>;
-fn irregular_charset
-
-longest word?
-
-*/
 
-macro_rules! scorer {
-    ($name:ident { $($prop:ident: $typ:ty),* }, $polar:expr, $($fnbody:tt)+) => {
-        #[derive(Debug)]
-        struct $name {
-            $($prop: $typ),*
-        }
-        impl $name {
-            fn new($($prop: $typ),*) -> Self {
-                Self {
-                    $($prop),*
+/// Every line of the input, for the modes that need to see all of it before writing any. A line that isn't utf-8
+/// is read lossily rather than ending the input there.
+fn read_lines() -> Vec<String> {
+    let mut lines = vec![];
+    for source in term_macros::inputs::sources_or_exit() {
+        let mut reader = std::io::BufReader::new(source.open_or_panic());
+        let mut line = vec![];
+        loop {
+            match reader.read_until(b'\n', &mut line) {
+                Ok(0) => break,
+                Ok(_) => {
+                    let text = line.strip_suffix(b"\n").unwrap_or_else(|| &line);
+                    let text = text.strip_suffix(b"\r").unwrap_or_else(|| text);
+                    lines.push(String::from_utf8_lossy(text).into_owned());
+                    line.clear();
                 }
+                Err(e) => exit_with(format!("Could not read {}: {}", source.name, e)),
             }
         }
-        impl Scorer for $name {
-            fn name(&self) -> &str {
-                stringify!($name)
-            }
-            fn polarity(&self) -> Polarity {
-                $polar
-            }
-            $(
-                    $fnbody
-            )+
-        }
-    }
-}
-
-scorer! {
-    Uppercased {
-        weight: f64
-    },
-    Polarity::HigherBetter,
-    fn score(&self, sentence: &str) -> f64 {
-        (1.0 / sentence.chars().filter(|c| c.is_uppercase()).count() as f64) * self.weight
-    }
-}
-
-scorer! {
-    Noisy {
-        weight: f64
-    },
-    Polarity::HigherBetter,
-    fn score(&self, sentence: &str) -> f64 {
-        (sentence.chars().filter(|c| c.is_whitespace() || c.is_alphabetic()).count() as f64 / sentence.chars().count() as f64) * self.weight
     }
+    lines
 }
-
-scorer! {
-    WordLengthVariety {
-        weight: f64
-    },
-    Polarity::HigherBetter,
-    fn score(&self, sentence: &str) -> f64 {
-        let mut word_lengths = sentence.split(" ").map(|l| l.len()).collect::<Vec<_>>();
-        word_lengths.sort();
-        let diff = (*word_lengths.iter().next().unwrap() as f64 - *word_lengths.iter().rev().next().unwrap() as f64).abs();
-        1.0 - (1.0 / (1.0 + diff))
-    }
-}
-
-// todo: penalty for upper-case chars in the middle of words. would penalise swahili though?
-// contextual meanings: the f64 returned can be an "ideal" number, or a cutoff. enum?
-// they should return floats between 0 and 1. boolean?
-// activate vs inactive, on vs off, off -> discard, irrelevant.
-
-// several policies: lazily apply hard binary cutoff ranges, or score all of them and sort all of them
-// possibly time each of them to see which one is the most computationally expensive, and apply the cheap ones first?
-// weights?
-trait Metascorer<T> {
-    fn score(&self, sentence: &str) -> T;
-}
-
-struct HardScorer {
-    scorers: HashMap<String, Arc<dyn Scorer + Send + Sync>>,
-    cutoffs: HashMap<String, f64>
-}
-
-impl HardScorer {
-    fn add_scorer<S: Scorer + Send + Sync + 'static>(&mut self, scorer: S, cutoff: f64) {
-        self.cutoffs.insert(scorer.name().into(), cutoff);
-        self.scorers.insert(scorer.name().into(), Arc::from(scorer));
-    }
-}
-
-impl Metascorer<bool> for HardScorer {
-
-    fn score(&self, sentence: &str) -> bool {
-        let iter = self.scorers.values().map(|s| {
-            let polarity = s.polarity();
-            let score = s.score(sentence);
-            let cutoff = self.cutoffs.get(s.name()).unwrap();
-            match polarity {
-                Polarity::HigherBetter => score > *cutoff,
-                Polarity::LowerBetter => score < *cutoff,
-            } 
-        });
-        for boolean in iter {
-            match boolean {
-                false => return false,
-                true => continue
-            }
-        }
-        true
-    }
-}
-
-#[derive(Default, Debug)]
-struct SoftScorer {
-    scorers: HashMap<String, Arc<dyn Scorer + Send + Sync>>
-}
-
-
-impl SoftScorer {
-    fn add_scorer<S: Scorer + Send + Sync + 'static>(&mut self, scorer: S) {
-        self.scorers.insert(scorer.name().into(), Arc::from(scorer));
-    }
-}
-
-impl Metascorer<f64> for SoftScorer {
-    fn score(&self, sentence: &str) -> f64 {
-        self.scorers.values().map(|s| {
-            let polarity = s.polarity();
-            let score = s.score(sentence);
-            //println!("Scored {} as {}", sentence, score);
-            match polarity {
-                Polarity::HigherBetter => score,
-                Polarity::LowerBetter => score * -1.0,
-            } 
-        }).sum::<f64>() / self.scorers.len() as f64
-    }
-}
-
-// since this is working within a synchronous loop, we can afford internal mutability
-// takes a scorer and derives a final score, or a boolean?
\ No newline at end of file
@@ -1,26 +1,20 @@
 use kmedoids;
-use lz4_flex::compress_prepend_size;
+use scorers::compression::{joint_ratio, Codec};
 use rayon::prelude::*;
 use std::collections::HashMap;
 use std::io::Read;
 use term_macros::*;
-/// Takes two file objects, f1 and f2. Reads in the first 10kb of the contents of f1 and f2 as a &[u8]. Trims the buffer if the length is less than 10kb for either. Measures the total size of both of these slices combined. Compresses the slices together via compress_prepend_size. Returns the ratio of the uncompressed to the compressed size.
+/// Takes two file objects, f1 and f2. Reads in the first 10kb of the contents of f1 and f2 as a &[u8]. Trims the buffer if the length is less than 10kb for either. Measures the total size of both of these slices combined. Compresses the slices together with lz4. Returns how much bigger that is than compressing each alone (1.0 when either is empty).
 fn compression_ratio(
     f1: &[u8],
     f2: &[u8],
     compr_f1_len: usize,
     compr_f2_len: usize,
 ) -> Result<f64, std::io::Error> {
-    let together = f1.into_iter().chain(f2).copied().collect::<Vec<u8>>();
-    let total_length = together.len();
-    if total_length == 0 || f1.len() == 0 || f2.len() == 0 {
+    if f1.len() == 0 || f2.len() == 0 {
         return Ok(1.0);
     }
-    let compressed_together = compress_prepend_size(&together);
-
-    let expected = (compr_f1_len + compr_f2_len) as f64 / (f1.len() + f2.len()) as f64;
-    let actual = (compressed_together.len()) as f64 / (f1.len() + f2.len()) as f64;
-    Ok(actual / expected)
+    Ok(joint_ratio(&Codec::Lz4, f1, f2, compr_f1_len, compr_f2_len).unwrap_or_else(|| 1.0))
 }
 /// Returns a list of all files found in the top level of a directory (and not within any subdirectories). Ignores folders.
 fn get_files(path: &std::path::Path) -> Result<Vec<std::path::PathBuf>, std::io::Error> {
@@ -59,8 +53,7 @@ fn get_file_combinations(
     let file_slice_compressed_lengths = file_slices
         .par_iter()
         .map(|file_slice| {
-            let compressed_file_slice = compress_prepend_size(&file_slice);
-            compressed_file_slice.len()
+            Codec::Lz4.compressed_len(&file_slice)
         })
         .collect::<Vec<_>>();
 
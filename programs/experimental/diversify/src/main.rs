@@ -1,21 +1,14 @@
 use std::io::{Error, ErrorKind};
-use lz4_flex::compress_prepend_size;
+use scorers::compression::{joint_ratio, Codec};
 use term_macros::*;
 use memmap::MmapOptions;
 use rayon::prelude::*;
 
 fn compression_ratio<'a>(f1: &'a [u8]) -> impl Fn(&[u8]) -> Result<f64, Error> + 'a {
-    let compr_f1_len = compress_prepend_size(f1).len();
+    let compr_f1_len = Codec::Lz4.compressed_len(f1);
     move |f2: &[u8]| {
-        if f1.len() + f2.len() == 0 {
-            return Err(Error::new(ErrorKind::InvalidData, "Both slices are empty"));
-        };
-
-        let compr_f2_len = compress_prepend_size(f2).len();
-        let compressed_together =
-            compress_prepend_size(&f1.iter().chain(f2.iter()).copied().collect::<Vec<u8>>());
-
-        Ok((compressed_together.len()) as f64 / (compr_f1_len + compr_f2_len) as f64)
+        joint_ratio(&Codec::Lz4, f1, f2, compr_f1_len, Codec::Lz4.compressed_len(f2))
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Both slices are empty"))
     }
 }
 
@@ -0,0 +1,263 @@
+use std::collections::HashMap;
+use std::io::{Read, Write};
+
+// a small n-gram language model, over characters or words, for telling whether a line looks like the language it
+// was trained on. ngrams are stored as a 64-bit hash of what came before and the unit's id, with their counts, and
+// probabilities are witten-bell interpolated down to a uniform guess over the vocabulary (plus one slot for anything
+// never seen), so every line gets a real perplexity, however strange it is.
+// score() puts that perplexity between 0 and 1 as its inverse, the average chance the model gave each unit: near 1
+// for a line it could predict perfectly, falling towards 0 for garbled text, boilerplate it never saw and other scripts.
+// pruning keeps the probabilities summing to one by handing what the dropped ngrams were seen to their contexts'
+// backoff weight, so it goes to the shorter ngrams instead of vanishing.
+// models are saved as: b"NGLM", version, unit, order, then for each order its contexts and ngrams, little-endian.
+
+const MAGIC: &[u8; 4] = b"NGLM";
+const VERSION: u8 = 2;
+// stand-ins for the start and end of a line, which no hashed unit can be
+const START: u64 = 0;
+const END: u64 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Unit {
+    Chars,
+    Words,
+}
+
+impl Unit {
+    pub fn from_name(name: &str) -> Result<Unit, String> {
+        match name {
+            "chars" | "char" => Ok(Unit::Chars),
+            "words" | "word" => Ok(Unit::Words),
+            _ => Err(format!("unit needs to be chars or words, not {:?}", name)),
+        }
+    }
+}
+
+/// FNV-1a, spelled out so saved models don't depend on std's hasher staying the same.
+fn hash_bytes(hash: u64, bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .fold(hash, |h, b| (h ^ *b as u64).wrapping_mul(0x100000001b3))
+}
+
+const SEED: u64 = 0xcbf29ce484222325;
+
+fn hash_ids(ids: &[u64]) -> u64 {
+    ids.iter().fold(SEED, |h, id| hash_bytes(h, &id.to_le_bytes()))
+}
+
+fn read_u64<R: Read>(r: &mut R) -> std::io::Result<u64> {
+    let mut bytes = [0; 8];
+    r.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_u32<R: Read>(r: &mut R) -> std::io::Result<u32> {
+    let mut bytes = [0; 4];
+    r.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Model {
+    pub order: usize,
+    pub unit: Unit,
+    /// for each order n (at n - 1): (the n - 1 units before, the unit) -> count
+    ngrams: Vec<HashMap<(u64, u64), u32>>,
+    /// for each order n (at n - 1): the n - 1 units before -> (count of the ngrams kept, backoff weight: how many
+    /// different units followed, plus the count of any ngrams pruned)
+    contexts: Vec<HashMap<u64, (u32, u32)>>,
+}
+
+impl Model {
+    pub fn new(order: usize, unit: Unit) -> Model {
+        let order = order.max(1);
+        Model {
+            order,
+            unit,
+            ngrams: vec![HashMap::new(); order],
+            contexts: vec![HashMap::new(); order],
+        }
+    }
+
+    /// A line as unit ids, with the start padding and the end.
+    fn ids(&self, line: &str) -> Vec<u64> {
+        let mut ids = vec![START; self.order - 1];
+        let hash = |unit: &str| hash_bytes(SEED, unit.as_bytes()).max(END + 1);
+        match self.unit {
+            Unit::Chars => ids.extend(line.chars().map(|c| hash(c.encode_utf8(&mut [0; 4])))),
+            Unit::Words => ids.extend(line.split_whitespace().map(hash)),
+        }
+        ids.push(END);
+        ids
+    }
+
+    pub fn add(&mut self, line: &str) {
+        let ids = self.ids(line);
+        for i in (self.order - 1)..ids.len() {
+            for n in 1..=self.order {
+                let context = hash_ids(&ids[i + 1 - n..i]);
+                let count = self.ngrams[n - 1].entry((context, ids[i])).or_insert(0);
+                *count += 1;
+                let seen = self.contexts[n - 1].entry(context).or_insert((0, 0));
+                *seen = (seen.0 + 1, seen.1 + (*count == 1) as u32);
+            }
+        }
+    }
+
+    pub fn train<'a>(&mut self, lines: impl Iterator<Item = &'a str>) {
+        lines.for_each(|line| self.add(line));
+    }
+
+    /// Drops ngrams longer than one unit seen fewer than `min_count` times, to make the model smaller. Their counts
+    /// move from their contexts' totals to their backoff weights, so it's for after training, not before adding more.
+    pub fn prune(&mut self, min_count: u32) {
+        for (ngrams, contexts) in self.ngrams.iter_mut().zip(self.contexts.iter_mut()).skip(1) {
+            ngrams.retain(|(context, _), count| {
+                if *count >= min_count {
+                    return true;
+                }
+                if let Some((total, weight)) = contexts.get_mut(context) {
+                    *total -= *count;
+                    *weight += *count;
+                }
+                false
+            });
+        }
+    }
+
+    /// How many different units the model has seen, counting the end of a line as one.
+    pub fn vocabulary(&self) -> usize {
+        self.contexts[0].get(&hash_ids(&[])).map(|(_, types)| *types as usize).unwrap_or_else(|| 0)
+    }
+
+    /// The chance of `ids[i]` following what came before it.
+    fn chance(&self, ids: &[u64], i: usize) -> f64 {
+        let mut chance = 1.0 / (self.vocabulary() + 1) as f64;
+        for n in 1..=self.order {
+            let context = hash_ids(&ids[i + 1 - n..i]);
+            if let Some((count, weight)) = self.contexts[n - 1].get(&context) {
+                let seen = self.ngrams[n - 1].get(&(context, ids[i])).copied().unwrap_or_else(|| 0);
+                chance = (seen as f64 + *weight as f64 * chance) / (*count + *weight) as f64;
+            }
+        }
+        chance
+    }
+
+    pub fn perplexity(&self, line: &str) -> f64 {
+        let ids = self.ids(line);
+        let predicted = (self.order - 1)..ids.len();
+        let n = predicted.len() as f64;
+        (-predicted.map(|i| self.chance(&ids, i).ln()).sum::<f64>() / n).exp()
+    }
+
+    /// The average chance the model gave each unit of the line (one over its perplexity), so higher is more natural.
+    pub fn score(&self, line: &str) -> f64 {
+        1.0 / self.perplexity(line)
+    }
+
+    pub fn save<W: Write>(&self, mut w: W) -> std::io::Result<()> {
+        w.write_all(MAGIC)?;
+        w.write_all(&[VERSION, (self.unit == Unit::Words) as u8, self.order as u8])?;
+        for (ngrams, contexts) in self.ngrams.iter().zip(&self.contexts) {
+            w.write_all(&(contexts.len() as u64).to_le_bytes())?;
+            for (hash, (count, types)) in contexts {
+                w.write_all(&hash.to_le_bytes())?;
+                w.write_all(&count.to_le_bytes())?;
+                w.write_all(&types.to_le_bytes())?;
+            }
+            w.write_all(&(ngrams.len() as u64).to_le_bytes())?;
+            for ((context, unit), count) in ngrams {
+                w.write_all(&context.to_le_bytes())?;
+                w.write_all(&unit.to_le_bytes())?;
+                w.write_all(&count.to_le_bytes())?;
+            }
+        }
+        w.flush()
+    }
+
+    pub fn load<R: Read>(mut r: R) -> std::io::Result<Model> {
+        let invalid = |e: &str| std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string());
+        let mut header = [0; 7];
+        r.read_exact(&mut header)?;
+        if &header[..4] != MAGIC {
+            return Err(invalid("not an ngrams model"));
+        }
+        if header[4] != VERSION {
+            return Err(invalid(&format!("ngrams model version {}, but this reads version {}", header[4], VERSION)));
+        }
+        let unit = if header[5] == 1 { Unit::Words } else { Unit::Chars };
+        let mut model = Model::new(header[6] as usize, unit);
+        for n in 0..model.order {
+            for _ in 0..read_u64(&mut r)? {
+                let hash = read_u64(&mut r)?;
+                model.contexts[n].insert(hash, (read_u32(&mut r)?, read_u32(&mut r)?));
+            }
+            for _ in 0..read_u64(&mut r)? {
+                let ngram = (read_u64(&mut r)?, read_u64(&mut r)?);
+                model.ngrams[n].insert(ngram, read_u32(&mut r)?);
+            }
+        }
+        Ok(model)
+    }
+}
+
+#[test]
+fn test_model() {
+    let reference = [
+        "the cat sat on the mat",
+        "the dog sat on the log",
+        "a cat and a dog sat together",
+        "the mat was on the floor",
+        "there was a dog on the mat",
+        "the cat was not on the log",
+        "we went to the market in the morning",
+        "she found her keys under the old newspaper",
+        "it rained all through the night and into the day",
+        "the children were playing quietly in the garden",
+        "nobody knew where the little boat had gone",
+        "he writes letters to his brother every week",
+        "they bought bread, milk and some fresh fruit",
+        "our neighbours moved away at the end of summer",
+    ];
+    let mut model = Model::new(3, Unit::Chars);
+    model.train(reference.iter().copied());
+    let natural = model.score("the dog was on the mat");
+    let garbled = model.score("hte tdo aws no hte tam");
+    let script = model.score("кошка сидела на коврике");
+    assert!(natural > garbled && garbled > script, "{} {} {}", natural, garbled, script);
+    assert!(natural < 1.0 && script > 0.0);
+
+    let mut saved = vec![];
+    model.save(&mut saved).unwrap();
+    assert_eq!(Model::load(saved.as_slice()).unwrap(), model);
+    assert!(Model::load(&b"NGLX"[..]).is_err());
+
+    let mut words = Model::new(2, Unit::Words);
+    words.train(reference.iter().copied());
+    assert!(words.score("the cat sat on the log") > words.score("log the on sat cat the"));
+}
+
+#[test]
+fn test_prune() {
+    let lines = ["the cat sat on the mat", "the cat ate the rat", "a hat that fits", "that was that"];
+    let mut model = Model::new(3, Unit::Chars);
+    model.train(lines.iter().copied());
+    let size = model.ngrams[2].len();
+    model.prune(2);
+    assert!(model.ngrams[2].len() < size);
+    // every unit seen, and one that never was: whatever came before, their chances still add up to one
+    let mut units: Vec<u64> = lines.iter().flat_map(|line| model.ids(line)).filter(|id| *id != START).collect();
+    units.sort_unstable();
+    units.dedup();
+    units.push(u64::MAX);
+    for before in ["th", "at", "a ", "zq"] {
+        let ids = model.ids(before);
+        let context = &ids[ids.len() - 3..ids.len() - 1];
+        let total: f64 = units
+            .iter()
+            .map(|unit| model.chance(&[context, &[*unit]].concat(), 2))
+            .sum();
+        assert!((total - 1.0).abs() < 1e-9, "{:?}: {}", before, total);
+    }
+}
@@ -4,7 +4,6 @@ use std::{sync::Arc};
 use rayon::prelude::*;
 use term_macros::*;
 use dashmap::DashMap;
-use memmap::MmapOptions;
 use std::io::Write;
 pub fn main() {
     tool! {
@@ -14,16 +13,37 @@ pub fn main() {
             - min_ngram_size: usize = 1;
             - filter_below: i32 = 1;
             - top_n: usize = 30000;
+            - model: Option<String> = None;
+            - order: usize = 3;
+            - unit: String = "chars".to_string();
+            - min_count: u32 = 1;
         ;
         body: || {
-            let file = std::fs::File::open(filename).unwrap();
-            let mmap = unsafe { MmapOptions::new().map(&file).unwrap() };
+            let mmap = mmap!(filename);
+            // --model trains a language model on the file and saves it there, instead of listing the ngrams
+            if let Some(path) = &model {
+                let unit = ngrams::Unit::from_name(&unit).unwrap_or_else(|e| {
+                    eprintln!("{}", e);
+                    std::process::exit(1)
+                });
+                let mut lm = ngrams::Model::new(order, unit);
+                lm.train(String::from_utf8_lossy(&mmap).lines());
+                lm.prune(min_count);
+                let saved = std::fs::File::create(path).and_then(|f| lm.save(std::io::BufWriter::new(f)));
+                if let Err(e) = saved {
+                    eprintln!("couldn't write --model {}: {}", path, e);
+                    std::process::exit(1)
+                }
+                return;
+            }
+            term_macros::progress::add_total_bytes(mmap.len() as u64);
 
             let map: DashMap<Arc<[u8]>, i32> = DashMap::with_capacity(1000000);
 
             let lines: Vec<_> = mmap[..].split(|c| c == &b'\n').collect();
 
             lines.into_par_iter().for_each(|byteline: &[u8]| {
+                term_macros::progress::read_line(byteline.len() + 1);
                 let words: Vec<_> = byteline.split(|c| c == &b' ').collect();
                 (min_ngram_size..max_ngram_size).for_each(|n| {
                     if words.len() < n {
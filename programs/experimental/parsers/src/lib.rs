@@ -1,9 +1,10 @@
 use nom::{
-    bytes::complete::{tag, take_while},
-    character::complete::one_of,
-    combinator::map_res,
+    branch::alt,
+    bytes::complete::{take_while, take_while1},
+    character::complete::char,
+    combinator::{map, map_res},
     multi::separated_list0,
-    sequence::tuple,
+    sequence::delimited,
     IResult,
 };
 
@@ -12,44 +13,232 @@ use nom::{
 // 1 2..4 5
 // 5 1
 // ..3 5
+// text 0      (a column named in the header)
+// -1 ..-1     (counting from the end: the last column, then all but the last)
+// !3          (every column but 3; exclusions apply to whatever else was picked, or to all columns if nothing was)
+// 0 0 '|' 1   (columns can be repeated, and quoted literals are inserted as they are)
+// ranges are half-open, like rust's: 1..4 is columns 1, 2 and 3.
+// on the command line, a spec that starts with a negative index needs a leading space (" -1 0") so it isn't taken for a flag.
 
-#[derive(Debug, PartialEq)]
+/// A column position, from the start (0 is the first column) or from the end (1 is the last).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Index {
+    Start(usize),
+    End(usize),
+}
+
+impl Index {
+    /// Where this index falls in a record of `len` columns, or None if it's before the start.
+    fn position(&self, len: usize) -> Option<usize> {
+        match self {
+            Index::Start(i) => Some(*i),
+            Index::End(i) => len.checked_sub(*i),
+        }
+    }
+}
+
+impl std::fmt::Display for Index {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Index::Start(i) => write!(f, "{}", i),
+            Index::End(i) => write!(f, "-{}", i),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum ColumnSpec {
-    Range(usize, usize),
-    NonTerminatingRange(usize),
-    Col(usize),
+    Range(Index, Index),
+    NonTerminatingRange(Index),
+    Col(Index),
+    Named(String),
+    Exclude(Box<ColumnSpec>),
+    Literal(String),
 }
 
 pub type ColumnSpecs = Vec<ColumnSpec>;
 
-fn from_num_or_range(input: &str) -> Result<ColumnSpec, &str> {
-    let mut ints = input.split("..");
-    let parse_digit = |inp: &str| usize::from_str_radix(inp, 10);
-    let start = ints
-        .next()
-        .map(|i| parse_digit(i).ok())
-        .flatten()
-        .unwrap_or_else(|| 0);
-    let end = ints.next().map(|i| parse_digit(i).ok()).flatten();
-    if end.is_some() {
-        return Ok(ColumnSpec::Range(start, end.unwrap()));
+fn parse_index(input: &str) -> Result<Index, String> {
+    let parse_digit = |inp: &str| usize::from_str_radix(inp, 10).map_err(|_| format!("{:?} isn't a column index", input));
+    match input.strip_prefix('-') {
+        Some("0") => Err("-0 isn't a column, -1 is the last one".to_string()),
+        Some(from_end) => parse_digit(from_end).map(Index::End),
+        None => parse_digit(input).map(Index::Start),
+    }
+}
+
+fn from_num_or_range(input: &str) -> Result<ColumnSpec, String> {
+    if let Some(excluded) = input.strip_prefix('!') {
+        return match from_num_or_range(excluded)? {
+            ColumnSpec::Exclude(_) => Err(format!("{:?} excludes twice", input)),
+            spec => Ok(ColumnSpec::Exclude(Box::new(spec))),
+        };
+    }
+    if input.starts_with(['"', '\'']) {
+        return Err(format!("{} has no closing quote", input));
     }
-    if input.ends_with(".") {
-        return Ok(ColumnSpec::NonTerminatingRange(start))
+    if input.is_empty() || !input.chars().all(is_num_or_dot) {
+        return Ok(ColumnSpec::Named(input.to_string()));
+    }
+    match input.split_once("..") {
+        Some((start, "")) => Ok(ColumnSpec::NonTerminatingRange(match start {
+            "" => Index::Start(0),
+            start => parse_index(start)?,
+        })),
+        Some(("", end)) => Ok(ColumnSpec::Range(Index::Start(0), parse_index(end)?)),
+        Some((start, end)) => Ok(ColumnSpec::Range(parse_index(start)?, parse_index(end)?)),
+        None => parse_index(input).map(ColumnSpec::Col),
     }
-    return Ok(ColumnSpec::Col(start));
 }
 
 fn is_num_or_dot(c: char) -> bool {
-    c.is_digit(10) || c == '.'
+    c.is_digit(10) || c == '.' || c == '-'
+}
+
+fn is_separator(c: char) -> bool {
+    c == ',' || c == ' '
+}
+
+fn parse_literal(input: &str) -> IResult<&str, ColumnSpec> {
+    let literal = |s: &str| ColumnSpec::Literal(s.to_string());
+    alt((
+        map(delimited(char('"'), take_while(|c| c != '"'), char('"')), literal),
+        map(delimited(char('\''), take_while(|c| c != '\''), char('\'')), literal),
+    ))(input)
 }
 
 fn parse_col_spec(input: &str) -> IResult<&str, ColumnSpec> {
-    map_res(take_while(is_num_or_dot), from_num_or_range)(input)
+    alt((
+        parse_literal,
+        map_res(take_while1(|c| !is_separator(c)), from_num_or_range),
+    ))(input)
 }
 
 fn parse_cols(input: &str) -> IResult<&str, ColumnSpecs> {
-    separated_list0(one_of(", "), parse_col_spec)(input)
+    separated_list0(take_while1(is_separator), parse_col_spec)(input)
+}
+
+/// Parses a whole spec, e.g. "2 1..5 ..6 3..", complaining about whatever it couldn't make sense of.
+pub fn parse_column_specs(input: &str) -> Result<ColumnSpecs, String> {
+    let input = input.trim();
+    match parse_cols(input) {
+        Ok(("", specs)) => Ok(specs),
+        Ok((rest, _)) => Err(format!("couldn't parse column spec {:?} from {:?}", input, rest)),
+        Err(_) => Err(format!("couldn't parse column spec {:?}", input)),
+    }
+}
+
+/// One thing a spec picks out of a record.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pick<'a> {
+    Column(usize),
+    /// asked for explicitly, but the record doesn't have it
+    Missing(Index),
+    Literal(&'a str),
+}
+
+// lenient leaves out named columns it can't find, rather than calling them a mistake
+fn columns(spec: &ColumnSpec, len: usize, names: Option<&[String]>, lenient: bool) -> Result<Vec<Pick<'static>>, String> {
+    let at = |index: &Index| match index.position(len) {
+        Some(i) if i < len => Pick::Column(i),
+        _ => Pick::Missing(*index),
+    };
+    // only explicit starts and ends past the last column count as missing; bounds from the end just stop at the edges
+    let bound = |index: &Index| match index {
+        Index::Start(i) => *i,
+        Index::End(_) => index.position(len).unwrap_or_else(|| 0),
+    };
+    Ok(match spec {
+        ColumnSpec::Col(index) => vec![at(index)],
+        ColumnSpec::Range(start, end) => (bound(start)..bound(end)).map(|i| at(&Index::Start(i))).collect(),
+        ColumnSpec::NonTerminatingRange(start) => (bound(start)..len.max(bound(start))).map(Pick::Column).collect(),
+        ColumnSpec::Named(name) => match names.and_then(|n| n.iter().position(|n| n == name)) {
+            Some(i) if i < len => vec![Pick::Column(i)],
+            Some(i) => vec![Pick::Missing(Index::Start(i))],
+            None if lenient => vec![],
+            None if names.is_none() => {
+                return Err(format!("column {:?} is named, but there's no header to find it in", name))
+            }
+            None => return Err(format!("there's no column named {:?}", name)),
+        },
+        ColumnSpec::Exclude(_) | ColumnSpec::Literal(_) => vec![],
+    })
+}
+
+/// What a spec picks out of a record with `len` columns, in order. Named columns are looked up in `names` (the header).
+/// Explicit columns past the end are kept as Missing, so the caller can decide what a missing column means.
+pub fn resolve<'a>(specs: &'a [ColumnSpec], len: usize, names: Option<&[String]>) -> Result<Vec<Pick<'a>>, String> {
+    resolve_with(specs, len, names, false)
+}
+
+fn resolve_with<'a>(
+    specs: &'a [ColumnSpec],
+    len: usize,
+    names: Option<&[String]>,
+    lenient: bool,
+) -> Result<Vec<Pick<'a>>, String> {
+    let mut excluded = vec![];
+    for spec in specs {
+        if let ColumnSpec::Exclude(spec) = spec {
+            excluded.extend(columns(spec, len, names, lenient)?);
+        }
+    }
+    let mut picked = vec![];
+    // exclusions on their own start from every column
+    if specs.iter().any(|s| matches!(s, ColumnSpec::Exclude(_)))
+        && specs
+            .iter()
+            .all(|s| matches!(s, ColumnSpec::Exclude(_) | ColumnSpec::Literal(_)))
+    {
+        picked.extend((0..len).map(Pick::Column));
+    }
+    for spec in specs {
+        match spec {
+            ColumnSpec::Literal(literal) => picked.push(Pick::Literal(literal)),
+            spec => picked.extend(columns(spec, len, names, lenient)?),
+        }
+    }
+    picked.retain(|pick| !matches!(pick, Pick::Column(_)) || !excluded.contains(pick));
+    Ok(picked)
+}
+
+/// The column indices a spec picks, leaving out literals and columns the record doesn't have.
+pub fn indices(specs: &[ColumnSpec], len: usize, names: Option<&[String]>) -> Result<Vec<usize>, String> {
+    resolve(specs, len, names).map(|picks| {
+        picks
+            .into_iter()
+            .filter_map(|pick| match pick {
+                Pick::Column(i) => Some(i),
+                _ => None,
+            })
+            .collect()
+    })
+}
+
+/// Like apply, but with named columns looked up in `names`, and an error for a column the fields don't have.
+pub fn try_apply<'a>(specs: &'a ColumnSpecs, fields: &[&'a str], names: Option<&[String]>) -> Result<Vec<&'a str>, String> {
+    resolve(specs, fields.len(), names)?
+        .into_iter()
+        .map(|pick| match pick {
+            Pick::Column(i) => Ok(fields[i]),
+            Pick::Literal(literal) => Ok(literal),
+            Pick::Missing(index) => Err(format!("there's no column {} in {} columns", index, fields.len())),
+        })
+        .collect()
+}
+
+/// Selects, reorders, repeats and inserts fields as the spec says. Columns the fields don't have are left out.
+pub fn apply<'a>(specs: &'a ColumnSpecs, fields: &[&'a str]) -> Vec<&'a str> {
+    // without a header there's nothing to look names up in, so they're left out too
+    resolve_with(specs, fields.len(), None, true)
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|pick| match pick {
+            Pick::Column(i) => Some(fields[i]),
+            Pick::Literal(literal) => Some(literal),
+            Pick::Missing(_) => None,
+        })
+        .collect()
 }
 
 #[test]
@@ -57,3 +246,59 @@ fn test1() {
     let r = parse_cols("2 1..5 ..6 3..");
     r.map(|res| println!("{:#?}", res));
 }
+
+#[test]
+fn test_parse_column_specs() {
+    assert_eq!(
+        parse_column_specs("2 1..5 ..6 3..,text  -1 -3..-1 !4 '|' \"a b\""),
+        Ok(vec![
+            ColumnSpec::Col(Index::Start(2)),
+            ColumnSpec::Range(Index::Start(1), Index::Start(5)),
+            ColumnSpec::Range(Index::Start(0), Index::Start(6)),
+            ColumnSpec::NonTerminatingRange(Index::Start(3)),
+            ColumnSpec::Named("text".to_string()),
+            ColumnSpec::Col(Index::End(1)),
+            ColumnSpec::Range(Index::End(3), Index::End(1)),
+            ColumnSpec::Exclude(Box::new(ColumnSpec::Col(Index::Start(4)))),
+            ColumnSpec::Literal("|".to_string()),
+            ColumnSpec::Literal("a b".to_string()),
+        ])
+    );
+    assert!(parse_column_specs("1.2").is_err());
+    assert!(parse_column_specs("-0").is_err());
+    assert!(parse_column_specs("!!1").is_err());
+    assert!(parse_column_specs("'unclosed").is_err());
+}
+
+#[test]
+fn test_indices() {
+    let specs = parse_column_specs("2 ..2 lang 3..").unwrap();
+    let names = vec!["id".to_string(), "lang".to_string(), "text".to_string(), "source".to_string()];
+    assert_eq!(indices(&specs, 5, Some(&names)), Ok(vec![2, 0, 1, 1, 3, 4]));
+    assert_eq!(
+        resolve(&parse_column_specs("7").unwrap(), 2, None),
+        Ok(vec![Pick::Missing(Index::Start(7))])
+    );
+    assert!(indices(&parse_column_specs("lang").unwrap(), 2, None).is_err());
+}
+
+#[test]
+fn test_apply() {
+    let fields = ["a", "b", "c", "d", "e"];
+    let apply_str = |spec: &str| apply(&parse_column_specs(spec).unwrap(), &fields).join(" ");
+    assert_eq!(apply_str("2 ..1 4.."), "c a e");
+    assert_eq!(apply_str("-1 ..-1"), "e a b c d");
+    assert_eq!(apply_str("-2.."), "d e");
+    assert_eq!(apply_str("!1 !3..-1"), "a c e");
+    assert_eq!(apply_str("0.. !2"), "a b d e");
+    assert_eq!(apply_str("0 0 '|' 1 9 -9"), "a a | b");
+    assert_eq!(apply_str("name 1"), "b");
+
+    let specs = parse_column_specs("1 '=' 0").unwrap();
+    assert_eq!(try_apply(&specs, &fields, None), Ok(vec!["b", "=", "a"]));
+    let specs = parse_column_specs("0 7").unwrap();
+    assert_eq!(try_apply(&specs, &fields, None), Err("there's no column 7 in 5 columns".to_string()));
+    let specs = parse_column_specs("text").unwrap();
+    let names = vec!["id".to_string(), "text".to_string()];
+    assert_eq!(try_apply(&specs, &["1", "hi"], Some(&names)), Ok(vec!["hi"]));
+}
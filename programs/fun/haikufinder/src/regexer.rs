@@ -80,7 +80,8 @@ pub struct Line {
     syllables: Vec<usize>,
 }
 
-// Tokenise into sentences via non-whitespace punctuation
+// Tokenise into haiku lines via non-whitespace punctuation. These are phrases rather than sentences (a line can
+// end at a comma or semicolon), so this splits by hand; the sentences come from the segmenter in summarise_file
 pub fn tokenise_sentences(sentence: &str) -> Vec<Line> {
     sentence.as_parallel_string().par_split(|c: char| c == ',' || c == '.' || c == ';').filter(|sen| sen.len() < 60 || sen.len() > 7).map(|sen| {
         Line {
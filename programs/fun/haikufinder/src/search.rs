@@ -12,6 +12,7 @@ use std::{
     sync::Arc,
 };
 use rayon::prelude::*;
+use segmenter::Segmenter;
 
 fn hash<T: std::hash::Hash>(value: T) -> u64 {
     let mut hasher = FxHasher::default();
@@ -96,12 +97,14 @@ fn random_in_range_or_end(start: usize, end: usize) -> bool {
 
 struct Engine {
     db: HashMap<u64, Sentence>,
+    segmenter: Segmenter,
 }
 
 impl Engine {
     pub fn new() -> Engine {
         Engine {
-            db: HashMap::new()
+            db: HashMap::new(),
+            segmenter: Segmenter::new("en").unwrap(),
         }
     }
     pub fn best(&self, query: IntSet<u64>) -> Vec<(u64, f32)> {
@@ -158,7 +161,9 @@ impl Engine {
         filtered_out: bool,
         source: &str,
     ) -> Vec<Sentence> {
-        text.split(".")
+        self.segmenter
+            .split(text)
+            .into_iter()
             .filter(|s| s.len() > 100)
             .map(|s| Sentence {
                 filtered_out,
@@ -238,6 +243,7 @@ impl Engine {
                 .into_iter()
                 .map(|s| (s.id, s.into()))
                 .collect(),
+            segmenter: Segmenter::new("en").unwrap(),
         };
         Ok(engine)
     }
@@ -1,4 +1,5 @@
 use rayon::prelude::*;
+use segmenter::Segmenter;
 use std::borrow::Cow;
 use std::collections::{HashMap, HashSet};
 use std::io::Read;
@@ -24,13 +25,12 @@ pub struct Summariser<'a> {
 impl<'a> Summariser<'a> {
     pub fn from_raw_text(
         raw_text: &'a str,
-        separator: &str,
         min_length: usize,
         max_length: usize,
         bias_strength: Option<f32>,
     ) -> Summariser<'a> {
         let mut sentences = HashMap::new();
-        let all_sentences = raw_text.split(|c|c == '.').collect::<Vec<&str>>();
+        let all_sentences = Segmenter::new("en").unwrap().split(raw_text);
         for (i, sentence) in all_sentences.iter().enumerate() {
             if sentence.len() > min_length && sentence.len() < max_length {
                 let words = HashSet::from_iter(
@@ -146,7 +146,6 @@ pub fn summarise_file<'a>(bias_list: HashSet<Cow<'a, [u8]>>, filename: &str) ->
     let raw_text = std::str::from_utf8(&buffer).unwrap();
     let mut summariser = Summariser::from_raw_text(
         raw_text,
-        ".",
         11,
         170,
         Some(2.0),
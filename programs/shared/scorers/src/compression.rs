@@ -0,0 +1,193 @@
+use crate::Polarity;
+use std::io::Write;
+use std::sync::Arc;
+use zstd::dict::EncoderDictionary;
+
+// compression distance: text that shares a lot with something else compresses better alongside it than on its own.
+// joint_ratio(a, b) is the size of a and b compressed together over the sizes of each compressed alone, so it's
+// near 1 for unrelated text and lower the more they share. (langfilter, diversify and cluster all use it.)
+// lz4 and gzip only look back so far (64kb and 32kb), so a reference is cut to its last REFERENCE_WINDOW bytes;
+// zstd can instead learn a dictionary from the whole reference, and then a line's distance is its size with the
+// dictionary over its size without.
+
+pub const REFERENCE_WINDOW: usize = 16 * 1024;
+const DICTIONARY_SIZE: usize = 64 * 1024;
+const ZSTD_LEVEL: i32 = 3;
+
+#[derive(Clone)]
+pub enum Codec {
+    Lz4,
+    Gzip,
+    Zstd(Option<Arc<EncoderDictionary<'static>>>),
+}
+
+impl std::fmt::Debug for Codec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl Codec {
+    pub fn from_name(name: &str) -> Result<Codec, String> {
+        match name.to_lowercase().as_str() {
+            "lz4" => Ok(Codec::Lz4),
+            "gzip" | "gz" => Ok(Codec::Gzip),
+            "zstd" | "zst" => Ok(Codec::Zstd(None)),
+            _ => Err(format!("the codec needs to be lz4, gzip or zstd, not {:?}", name)),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Codec::Lz4 => "lz4",
+            Codec::Gzip => "gzip",
+            Codec::Zstd(None) => "zstd",
+            Codec::Zstd(Some(_)) => "zstd with a dictionary",
+        }
+    }
+
+    /// zstd, with a dictionary trained on `samples` (say, the lines of a reference file).
+    pub fn zstd_trained<S: AsRef<[u8]>>(samples: &[S]) -> Result<Codec, String> {
+        let dictionary = zstd::dict::from_samples(samples, DICTIONARY_SIZE)
+            .map_err(|e| format!("couldn't train a zstd dictionary on {} samples: {}", samples.len(), e))?;
+        Ok(Codec::Zstd(Some(Arc::new(EncoderDictionary::copy(&dictionary, ZSTD_LEVEL)))))
+    }
+
+    /// The codec without its dictionary, if it has one.
+    pub fn plain(&self) -> Codec {
+        match self {
+            Codec::Zstd(_) => Codec::Zstd(None),
+            other => other.clone(),
+        }
+    }
+
+    pub fn compressed_len(&self, bytes: &[u8]) -> usize {
+        match self {
+            Codec::Lz4 => lz4_flex::compress_prepend_size(bytes).len(),
+            Codec::Gzip => {
+                let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+                encoder
+                    .write_all(bytes)
+                    .and_then(|_| encoder.finish())
+                    .map(|compressed| compressed.len())
+                    .unwrap_or_else(|_| bytes.len())
+            }
+            Codec::Zstd(None) => zstd::bulk::compress(bytes, ZSTD_LEVEL)
+                .map(|compressed| compressed.len())
+                .unwrap_or_else(|_| bytes.len()),
+            Codec::Zstd(Some(dictionary)) => zstd::bulk::Compressor::with_prepared_dictionary(dictionary)
+                .and_then(|mut compressor| compressor.compress(bytes))
+                .map(|compressed| compressed.len())
+                .unwrap_or_else(|_| bytes.len()),
+        }
+    }
+
+    /// Compressed over uncompressed size: lower is more compressible.
+    pub fn ratio(&self, bytes: &[u8]) -> f64 {
+        self.compressed_len(bytes) as f64 / bytes.len().max(1) as f64
+    }
+}
+
+/// a and b compressed together over each compressed alone, given those sizes; None if both are empty.
+pub fn joint_ratio(codec: &Codec, a: &[u8], b: &[u8], a_len: usize, b_len: usize) -> Option<f64> {
+    if a.is_empty() && b.is_empty() {
+        return None;
+    }
+    let together: Vec<u8> = a.iter().chain(b).copied().collect();
+    Some(codec.compressed_len(&together) as f64 / (a_len + b_len) as f64)
+}
+
+crate::scorer! {
+    CompressedVsAverage {
+        codec: Codec,
+        average: f64,
+        weight: f64
+    },
+    Polarity::LowerBetter,
+    fn score(&self, sentence: &str) -> f64 {
+        // how far, either way, a line's compressibility is from the average: repetitive boilerplate compresses
+        // much better than ordinary text and noise much worse
+        (self.codec.ratio(sentence.as_bytes()) / self.average).ln().abs() * self.weight
+    }
+}
+
+impl CompressedVsAverage {
+    /// Takes the average from `lines`, which should be a fair sample of what's going to be scored.
+    pub fn from_lines<S: AsRef<str>>(codec: Codec, lines: &[S], weight: f64) -> CompressedVsAverage {
+        let ratios: Vec<f64> = lines
+            .iter()
+            .filter(|line| !line.as_ref().is_empty())
+            .map(|line| codec.ratio(line.as_ref().as_bytes()))
+            .collect();
+        let average = match ratios.is_empty() {
+            true => 1.0,
+            false => ratios.iter().sum::<f64>() / ratios.len() as f64,
+        };
+        CompressedVsAverage::new(codec, average, weight)
+    }
+}
+
+crate::scorer! {
+    CompressedVsReference {
+        codec: Codec,
+        reference: Vec<u8>,
+        reference_len: usize,
+        weight: f64
+    },
+    Polarity::LowerBetter,
+    fn score(&self, sentence: &str) -> f64 {
+        let line = sentence.as_bytes();
+        let plain = self.codec.plain().compressed_len(line);
+        let distance = match &self.codec {
+            Codec::Zstd(Some(_)) => self.codec.compressed_len(line) as f64 / plain.max(1) as f64,
+            _ => joint_ratio(&self.codec, &self.reference, line, self.reference_len, plain).unwrap_or_else(|| 1.0),
+        };
+        distance * self.weight
+    }
+}
+
+impl CompressedVsReference {
+    /// Uses `reference` (the text lines should look like), or a zstd dictionary trained on its lines if the codec is
+    /// zstd and `dictionary` is set.
+    pub fn from_reference(codec: Codec, reference: &[u8], dictionary: bool, weight: f64) -> Result<CompressedVsReference, String> {
+        let codec = match (codec, dictionary) {
+            (Codec::Zstd(_), true) => Codec::zstd_trained(&reference.split(|b| *b == b'\n').collect::<Vec<_>>())?,
+            (_, true) => return Err("only zstd can learn a dictionary from the reference".to_string()),
+            (codec, false) => codec,
+        };
+        let reference = reference[reference.len().saturating_sub(REFERENCE_WINDOW)..].to_vec();
+        Ok(CompressedVsReference::new(codec.clone(), reference.clone(), codec.plain().compressed_len(&reference), weight))
+    }
+
+    pub fn load(path: &str, codec: Codec, dictionary: bool, weight: f64) -> Result<CompressedVsReference, String> {
+        let reference = std::fs::read(path).map_err(|e| format!("couldn't read the reference {}: {}", path, e))?;
+        CompressedVsReference::from_reference(codec, &reference, dictionary, weight)
+    }
+}
+
+#[test]
+fn test_compression() {
+    use crate::Scorer;
+    let english: Vec<String> = (0..400)
+        .map(|i| format!("the {} quick brown foxes jumped over {} lazy dogs near the river bank", i, i * 7))
+        .collect();
+    let reference = english.join("\n");
+    let like = "the 12 quick brown foxes jumped over 9 lazy dogs near the river bank";
+    let unlike = "zwölf Boxkämpfer jagen Viktor quer über den großen Sylter Deich";
+    for codec in ["lz4", "gzip", "zstd"] {
+        let codec = Codec::from_name(codec).unwrap();
+        let scorer = CompressedVsReference::from_reference(codec.clone(), reference.as_bytes(), false, 1.0).unwrap();
+        assert!(scorer.score(like) < scorer.score(unlike), "{:?}", codec);
+        let a = like.as_bytes();
+        assert!(joint_ratio(&codec, a, a, codec.compressed_len(a), codec.compressed_len(a)).unwrap() < 1.0);
+    }
+    let trained = CompressedVsReference::from_reference(Codec::Zstd(None), reference.as_bytes(), true, 1.0).unwrap();
+    assert!(trained.score(like) < trained.score(unlike));
+    assert!(CompressedVsReference::from_reference(Codec::Lz4, b"", true, 1.0).is_err());
+    assert!(Codec::from_name("brotli").is_err());
+    assert_eq!(joint_ratio(&Codec::Lz4, b"", b"", 0, 0), None);
+
+    let average = CompressedVsAverage::from_lines(Codec::Zstd(None), &english, 1.0);
+    let boilerplate = "ok ok ok ok ok ok ok ok ok ok ok ok ok ok ok ok ok ok ok ok ok ok ok ok ok ok ok ok ok ok ok ok";
+    assert!(average.score(&english[3]) < average.score(boilerplate));
+}
@@ -0,0 +1,359 @@
+use core::fmt::Debug;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Instant;
+
+pub mod compression;
+pub mod stats;
+
+// line scorers, shared by sort.rs (which filters lines on them) and qc (which runs them on each side of a pair).
+// seems like fundamental compositional pattern is: divide into units (bytes, chars, words), measure properties of each, normalise to btwn 0 and 1
+// two ways to use them: SoftScorer averages every score into one, HardScorer applies a cutoff per scorer.
+// compression.rs has the compression-distance scorers, against the input's average and against a reference.
+// stats.rs has the distributions sort.rs --stats prints and --filter picks percentile cutoffs from.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Polarity {
+    HigherBetter,
+    LowerBetter,
+}
+
+pub trait Scorer: Debug {
+    fn polarity(&self) -> Polarity;
+    fn score(&self, sentence: &str) -> f64;
+    fn name(&self) -> &str;
+}
+
+pub type SharedScorer = Arc<dyn Scorer + Send + Sync>;
+
+// initialisation needs to be handled case-by-case. scorerfactory?
+/*
+
+fn uppercased(&self) -> Policy<f64>;
+fn long(&self) -> Policy<f64>;
+fn noisy(&self) -> Policy<f64>;
+fn gzip_against_average(&self) -> Policy<f64>; // how gzippable is it by itself compared to the average? (CompressedVsAverage)
+fn gzip_against_reference(&self) -> Policy<f64>; // (CompressedVsReference) how gzippable is it compared to the reference corpus? <-  this is more powerful than the threshold based language detector and more likely to yield good results because it will take relatives into account.
+fn infrequent(&self) -> Policy<f64>;
+fn weird_whitespace(&self) -> Policy<f64>;
+fn irregular_charset
+
+longest word?
+
+*/
+
+/// Declares a scorer struct with the given fields, a `new` taking them in order, and its Scorer impl from the
+/// polarity and `fn score`.
+#[macro_export]
+macro_rules! scorer {
+    ($name:ident { $($prop:ident: $typ:ty),* }, $polar:expr, $($fnbody:tt)+) => {
+        #[derive(Debug)]
+        pub struct $name {
+            $(pub $prop: $typ),*
+        }
+        impl $name {
+            pub fn new($($prop: $typ),*) -> Self {
+                Self {
+                    $($prop),*
+                }
+            }
+        }
+        impl $crate::Scorer for $name {
+            fn name(&self) -> &str {
+                stringify!($name)
+            }
+            fn polarity(&self) -> $crate::Polarity {
+                $polar
+            }
+            $(
+                    $fnbody
+            )+
+        }
+    }
+}
+
+scorer! {
+    Uppercased {
+        weight: f64
+    },
+    Polarity::HigherBetter,
+    fn score(&self, sentence: &str) -> f64 {
+        (1.0 / sentence.chars().filter(|c| c.is_uppercase()).count() as f64) * self.weight
+    }
+}
+
+scorer! {
+    Noisy {
+        weight: f64
+    },
+    Polarity::HigherBetter,
+    fn score(&self, sentence: &str) -> f64 {
+        (sentence.chars().filter(|c| c.is_whitespace() || c.is_alphabetic()).count() as f64 / sentence.chars().count() as f64) * self.weight
+    }
+}
+
+scorer! {
+    WordLengthVariety {
+        weight: f64
+    },
+    Polarity::HigherBetter,
+    fn score(&self, sentence: &str) -> f64 {
+        let mut word_lengths = sentence.split(" ").map(|l| l.len()).collect::<Vec<_>>();
+        word_lengths.sort();
+        let diff = (*word_lengths.iter().next().unwrap() as f64 - *word_lengths.iter().rev().next().unwrap() as f64).abs();
+        1.0 - (1.0 / (1.0 + diff))
+    }
+}
+
+scorer! {
+    Perplexity {
+        model: ngrams::Model,
+        weight: f64
+    },
+    Polarity::HigherBetter,
+    fn score(&self, sentence: &str) -> f64 {
+        self.model.score(sentence) * self.weight
+    }
+}
+
+impl Perplexity {
+    /// Loads a language model written by ngrams --model.
+    pub fn load(path: &str, weight: f64) -> Result<Perplexity, String> {
+        std::fs::File::open(path)
+            .and_then(|f| ngrams::Model::load(std::io::BufReader::new(f)))
+            .map(|model| Perplexity::new(model, weight))
+            .map_err(|e| format!("couldn't load the language model {}: {}", path, e))
+    }
+}
+
+/// The scorers that need nothing but a weight, by the names tools take them by.
+pub const NAMES: &[&str] = &["uppercased", "noisy", "word_length_variety"];
+
+pub fn by_name(name: &str, weight: f64) -> Option<SharedScorer> {
+    match name {
+        "uppercased" => Some(Arc::new(Uppercased::new(weight))),
+        "noisy" => Some(Arc::new(Noisy::new(weight))),
+        "word_length_variety" => Some(Arc::new(WordLengthVariety::new(weight))),
+        _ => None,
+    }
+}
+
+/// Whether `name` (as tools take it, e.g. word_length_variety) is the scorer named `scorer` (e.g. WordLengthVariety).
+pub fn is_named(scorer: &dyn Scorer, name: &str) -> bool {
+    scorer.name().eq_ignore_ascii_case(&name.replace('_', ""))
+}
+
+// todo: penalty for upper-case chars in the middle of words. would penalise swahili though?
+// contextual meanings: the f64 returned can be an "ideal" number, or a cutoff. enum?
+// they should return floats between 0 and 1. boolean?
+// activate vs inactive, on vs off, off -> discard, irrelevant.
+
+// several policies: lazily apply hard binary cutoff ranges, or score all of them and sort all of them
+// HardScorer is the lazy one: it stops at the first cutoff a line misses, and calibrate() times each scorer on a
+// sample to run the cheap ones that turn down the most lines first.
+// weights?
+pub trait Metascorer<T> {
+    fn score(&self, sentence: &str) -> T;
+}
+
+/// How a scorer did on HardScorer's warm-up sample.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Calibration {
+    pub name: String,
+    /// the average time it took to score a line
+    pub nanos: f64,
+    /// the share of lines it turned down
+    pub rejected: f64,
+}
+
+impl Calibration {
+    /// Time per line turned down; infinite for a scorer that turns none down (0 / 0 would be a nan, which total_cmp
+    /// can sort first).
+    fn cost(&self) -> f64 {
+        match self.rejected == 0.0 {
+            true => f64::INFINITY,
+            false => self.nanos / self.rejected,
+        }
+    }
+}
+
+/// The expected time to judge a line with scorers run in this order, each only on the lines all before it passed.
+pub fn expected_nanos(order: &[Calibration]) -> f64 {
+    order
+        .iter()
+        .fold((0.0, 1.0), |(nanos, reached), c| (nanos + reached * c.nanos, reached * (1.0 - c.rejected)))
+        .0
+}
+
+/// Passes a line if every scorer's score is at least as good as its cutoff, stopping at the first it misses.
+#[derive(Default, Debug)]
+pub struct HardScorer {
+    scorers: HashMap<String, SharedScorer>,
+    cutoffs: HashMap<String, f64>,
+    /// the order the scorers run in: the order they were added, until calibrate() finds a cheaper one
+    order: Vec<String>,
+}
+
+impl HardScorer {
+    pub fn add_scorer<S: Scorer + Send + Sync + 'static>(&mut self, scorer: S, cutoff: f64) {
+        self.add_shared(Arc::new(scorer), cutoff);
+    }
+
+    pub fn add_shared(&mut self, scorer: SharedScorer, cutoff: f64) {
+        if !self.scorers.contains_key(scorer.name()) {
+            self.order.push(scorer.name().into());
+        }
+        self.cutoffs.insert(scorer.name().into(), cutoff);
+        self.scorers.insert(scorer.name().into(), scorer);
+    }
+
+    pub fn order(&self) -> &[String] {
+        &self.order
+    }
+
+    fn passes(&self, scorer: &dyn Scorer, sentence: &str) -> bool {
+        let score = scorer.score(sentence);
+        let cutoff = self.cutoffs[scorer.name()];
+        match scorer.polarity() {
+            Polarity::HigherBetter => score >= cutoff,
+            Polarity::LowerBetter => score <= cutoff,
+        }
+    }
+
+    /// Runs every scorer over `sample`, then reorders them so a line costs the least to judge on average, and
+    /// returns how each did, in the new order.
+    pub fn calibrate<S: AsRef<str>>(&mut self, sample: &[S]) -> Vec<Calibration> {
+        let lines = sample.len().max(1) as f64;
+        let mut calibrations: Vec<Calibration> = self
+            .order
+            .iter()
+            .map(|name| {
+                let scorer = self.scorers[name].as_ref();
+                let start = Instant::now();
+                let rejected = sample.iter().filter(|line| !self.passes(scorer, line.as_ref())).count();
+                Calibration {
+                    name: name.clone(),
+                    nanos: start.elapsed().as_nanos() as f64 / lines,
+                    rejected: rejected as f64 / lines,
+                }
+            })
+            .collect();
+        // a scorer costs its time on every line that gets to it and saves everything after it on the lines it
+        // turns down, so the cheapest order goes by time per line turned down (those that turn down none go last)
+        calibrations.sort_by(|a, b| a.cost().total_cmp(&b.cost()));
+        self.order = calibrations.iter().map(|c| c.name.clone()).collect();
+        calibrations
+    }
+}
+
+impl Metascorer<bool> for HardScorer {
+    fn score(&self, sentence: &str) -> bool {
+        self.order
+            .iter()
+            .all(|name| self.passes(self.scorers[name].as_ref(), sentence))
+    }
+}
+
+/// Averages every scorer's score, with those where lower is better counting against a line.
+#[derive(Default, Debug)]
+pub struct SoftScorer {
+    scorers: HashMap<String, SharedScorer>,
+}
+
+impl SoftScorer {
+    pub fn add_scorer<S: Scorer + Send + Sync + 'static>(&mut self, scorer: S) {
+        self.add_shared(Arc::new(scorer));
+    }
+
+    pub fn add_shared(&mut self, scorer: SharedScorer) {
+        self.scorers.insert(scorer.name().into(), scorer);
+    }
+}
+
+impl Metascorer<f64> for SoftScorer {
+    fn score(&self, sentence: &str) -> f64 {
+        self.scorers.values().map(|s| {
+            let polarity = s.polarity();
+            let score = s.score(sentence);
+            //println!("Scored {} as {}", sentence, score);
+            match polarity {
+                Polarity::HigherBetter => score,
+                Polarity::LowerBetter => score * -1.0,
+            }
+        }).sum::<f64>() / self.scorers.len() as f64
+    }
+}
+
+#[test]
+fn test_scorers() {
+    scorer! {
+        Long {
+            weight: f64
+        },
+        Polarity::LowerBetter,
+        fn score(&self, sentence: &str) -> f64 {
+            sentence.len() as f64 * self.weight
+        }
+    }
+    let mut hard = HardScorer::default();
+    hard.add_scorer(Long::new(1.0), 10.0);
+    hard.add_shared(by_name("noisy", 1.0).unwrap(), 0.8);
+    assert!(hard.score("short one"));
+    assert!(!hard.score("much too long"));
+    assert!(!hard.score("#%&*!"));
+
+    let mut soft = SoftScorer::default();
+    soft.add_scorer(Noisy::new(1.0));
+    soft.add_scorer(Long::new(0.1));
+    assert_eq!(soft.score("abcd"), (1.0 - 0.4) / 2.0);
+
+    assert!(is_named(&WordLengthVariety::new(1.0), "word_length_variety"));
+    assert!(NAMES.iter().all(|name| is_named(by_name(name, 1.0).unwrap().as_ref(), name)));
+}
+
+#[test]
+fn test_calibrate() {
+    scorer! {
+        Slow {
+            weight: f64
+        },
+        Polarity::HigherBetter,
+        fn score(&self, sentence: &str) -> f64 {
+            std::hint::black_box((0..20000).fold(sentence.len(), |a, i| std::hint::black_box(a ^ i)));
+            self.weight
+        }
+    }
+    scorer! {
+        Short {
+            weight: f64
+        },
+        Polarity::LowerBetter,
+        fn score(&self, sentence: &str) -> f64 {
+            sentence.len() as f64 * self.weight
+        }
+    }
+    let mut hard = HardScorer::default();
+    hard.add_scorer(Slow::new(1.0), 0.0);
+    hard.add_scorer(Short::new(1.0), 5.0);
+    hard.add_scorer(Slow::new(1.0), 0.0);
+    assert_eq!(hard.order(), ["Slow", "Short"]);
+    let sample = ["short", "much too long", "also too long"];
+    let calibrations = hard.calibrate(&sample);
+    assert_eq!(hard.order(), ["Short", "Slow"]);
+    assert_eq!((calibrations[0].rejected, calibrations[1].rejected), (2.0 / 3.0, 0.0));
+    assert!(hard.score("short") && !hard.score("too long"));
+
+    let order = |nanos: [f64; 2], rejected: [f64; 2]| {
+        ["a", "b"]
+            .iter()
+            .zip(nanos.iter().zip(rejected))
+            .map(|(name, (nanos, rejected))| Calibration { name: name.to_string(), nanos: *nanos, rejected })
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(expected_nanos(&order([10.0, 100.0], [0.5, 0.0])), 60.0);
+    assert_eq!(expected_nanos(&order([100.0, 10.0], [0.0, 0.5])), 110.0);
+    // one too quick to time that turns nothing down still goes after one that does
+    let mut calibrations = order([0.0, 10.0], [0.0, 0.5]);
+    calibrations.sort_by(|a, b| a.cost().total_cmp(&b.cost()));
+    assert_eq!(calibrations[0].name, "b");
+}
@@ -0,0 +1,167 @@
+use crate::Polarity;
+use std::io::Write;
+
+// score distributions over a whole input, for seeing where a scorer's cutoffs ought to go (sort.rs --stats) and
+// for picking them as percentiles (sort.rs --filter noisy:20 drops about the worst 20% of lines by Noisy).
+// scores that aren't numbers are left out and counted on their own; infinities are kept, at the ends.
+
+pub const PERCENTILES: &[f64] = &[1.0, 5.0, 10.0, 25.0, 50.0, 75.0, 90.0, 95.0, 99.0];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bin {
+    pub start: f64,
+    pub end: f64,
+    pub count: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct Distribution {
+    sorted: Vec<f64>,
+    pub nan: usize,
+}
+
+impl Distribution {
+    pub fn new(scores: Vec<f64>) -> Distribution {
+        let len = scores.len();
+        let mut sorted: Vec<f64> = scores.into_iter().filter(|s| !s.is_nan()).collect();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+        Distribution {
+            nan: len - sorted.len(),
+            sorted,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.sorted.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sorted.is_empty()
+    }
+
+    /// The score `p` percent of the way up, by nearest rank.
+    pub fn percentile(&self, p: f64) -> Option<f64> {
+        let rank = ((p / 100.0) * self.len() as f64).ceil() as usize;
+        self.sorted.get(rank.clamp(1, self.len().max(1)) - 1).copied()
+    }
+
+    pub fn mean(&self) -> Option<f64> {
+        let finite: Vec<f64> = self.sorted.iter().copied().filter(|s| s.is_finite()).collect();
+        match finite.is_empty() {
+            true => None,
+            false => Some(finite.iter().sum::<f64>() / finite.len() as f64),
+        }
+    }
+
+    /// `bins` equal-width bins between the lowest and highest finite scores, with infinities in the end bins.
+    pub fn histogram(&self, bins: usize) -> Vec<Bin> {
+        let finite: Vec<f64> = self.sorted.iter().copied().filter(|s| s.is_finite()).collect();
+        let (min, max) = match (finite.first(), finite.last()) {
+            (Some(min), Some(max)) => (*min, *max),
+            _ => return vec![],
+        };
+        let bins = if max > min { bins.max(1) } else { 1 };
+        let width = (max - min) / bins as f64;
+        let mut histogram: Vec<Bin> = (0..bins)
+            .map(|i| Bin {
+                start: min + width * i as f64,
+                end: if i + 1 == bins { max } else { min + width * (i + 1) as f64 },
+                count: 0,
+            })
+            .collect();
+        for score in &self.sorted {
+            let i = match *score {
+                s if s == f64::NEG_INFINITY => 0,
+                s if s == f64::INFINITY => bins - 1,
+                _ if width == 0.0 => 0,
+                s => (((s - min) / width) as usize).min(bins - 1),
+            };
+            histogram[i].count += 1;
+        }
+        histogram
+    }
+
+    /// The cutoff that drops about the worst `percent` percent of scores.
+    pub fn cutoff(&self, percent: f64, polarity: Polarity) -> Option<f64> {
+        match polarity {
+            Polarity::HigherBetter => self.percentile(percent),
+            Polarity::LowerBetter => self.percentile(100.0 - percent),
+        }
+    }
+}
+
+pub fn write_stats<W: Write>(mut w: W, name: &str, polarity: Polarity, distribution: &Distribution, bins: usize) -> std::io::Result<()> {
+    let better = match polarity {
+        Polarity::HigherBetter => "higher",
+        Polarity::LowerBetter => "lower",
+    };
+    writeln!(w, "{} ({} is better): {} scores, {} not a number", name, better, distribution.len(), distribution.nan)?;
+    if distribution.is_empty() {
+        return Ok(());
+    }
+    let percentiles: Vec<String> = PERCENTILES
+        .iter()
+        .map(|p| format!("p{} {:.4}", p, distribution.percentile(*p).unwrap_or_else(|| f64::NAN)))
+        .collect();
+    writeln!(
+        w,
+        "  min {:.4}  {}  max {:.4}  mean {:.4}",
+        distribution.percentile(0.0).unwrap_or_else(|| f64::NAN),
+        percentiles.join("  "),
+        distribution.percentile(100.0).unwrap_or_else(|| f64::NAN),
+        distribution.mean().unwrap_or_else(|| f64::NAN)
+    )?;
+    let histogram = distribution.histogram(bins);
+    let most = histogram.iter().map(|bin| bin.count).max().unwrap_or_else(|| 0).max(1);
+    for bin in histogram {
+        let bar = "#".repeat((bin.count * 40).div_ceil(most));
+        writeln!(w, "  {:>10.4} .. {:<10.4} {:<40} {}", bin.start, bin.end, bar, bin.count)?;
+    }
+    Ok(())
+}
+
+/// Parses --filter's "name:percent,name:percent".
+pub fn parse_filter(spec: &str) -> Result<Vec<(String, f64)>, String> {
+    spec.split(',')
+        .map(|part| part.trim())
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let (name, percent) = part
+                .split_once(':')
+                .ok_or_else(|| format!("--filter needs name:percent, like noisy:20, not {:?}", part))?;
+            let percent: f64 = percent
+                .trim_end_matches('%')
+                .parse()
+                .map_err(|_| format!("--filter needs a percentage for {}, not {:?}", name, percent))?;
+            if !(0.0..=100.0).contains(&percent) {
+                return Err(format!("--filter can drop between 0 and 100% by {}, not {}", name, percent));
+            }
+            Ok((name.to_string(), percent))
+        })
+        .collect()
+}
+
+#[test]
+fn test_distribution() {
+    let distribution = Distribution::new(vec![5.0, 1.0, f64::NAN, 3.0, 2.0, 4.0, f64::INFINITY]);
+    assert_eq!((distribution.len(), distribution.nan), (6, 1));
+    assert_eq!(distribution.percentile(0.0), Some(1.0));
+    assert_eq!(distribution.percentile(50.0), Some(3.0));
+    assert_eq!(distribution.percentile(100.0), Some(f64::INFINITY));
+    assert_eq!(distribution.mean(), Some(3.0));
+    assert_eq!(distribution.cutoff(20.0, Polarity::HigherBetter), Some(2.0));
+    assert_eq!(distribution.cutoff(20.0, Polarity::LowerBetter), Some(5.0));
+    let histogram = distribution.histogram(2);
+    assert_eq!(histogram.iter().map(|bin| bin.count).collect::<Vec<_>>(), vec![2, 4]);
+    assert_eq!((histogram[0].start, histogram[1].end), (1.0, 5.0));
+    assert!(Distribution::new(vec![]).percentile(50.0).is_none());
+
+    let mut stats = vec![];
+    write_stats(&mut stats, "Noisy", Polarity::HigherBetter, &distribution, 2).unwrap();
+    assert!(String::from_utf8(stats).unwrap().starts_with("Noisy (higher is better): 6 scores, 1 not a number\n"));
+
+    assert_eq!(parse_filter("noisy:20, uppercased:5%").unwrap(), vec![("noisy".to_string(), 20.0), ("uppercased".to_string(), 5.0)]);
+    assert!(parse_filter("noisy").is_err());
+    assert!(parse_filter("noisy:lots").is_err());
+    assert!(parse_filter("noisy:120").is_err());
+}
@@ -0,0 +1,69 @@
+// words that a period after doesn't end a sentence, lowercase and without that last period. ordinary words that
+// happen to be abbreviations too ("no", "sat", "jan") are left out, since leaving them in would join up real sentences;
+// punkt.rs can learn them from a corpus where they're mostly abbreviations.
+
+pub const LANGUAGES: &[&str] = &["en", "de", "fr", "es", "it", "pt", "nl", "ru", "none"];
+
+const EN: &[&str] = &[
+    "mr", "mrs", "ms", "dr", "prof", "sr", "jr", "st", "mt", "ft", "vs", "etc", "e.g", "i.e", "cf", "al", "approx",
+    "dept", "figs", "govt", "inc", "ltd", "corp", "feb", "apr", "jun", "jul", "aug", "sept", "oct", "nov", "vol",
+    "vols", "pp", "capt", "lt", "sgt", "hon", "u.s", "u.k", "a.m", "p.m", "ph.d", "b.a", "m.a", "viz", "ca", "ibid",
+    "esp", "misc", "incl", "nos",
+];
+
+const DE: &[&str] = &[
+    "bzw", "ca", "d.h", "dr", "evtl", "ggf", "hr", "fr", "inkl", "nr", "prof", "str", "u.a", "usw", "vgl", "z.b",
+    "z.t", "abs", "bsp", "chr", "dgl", "etc", "geb", "gest", "hrsg", "jh", "jhd", "mio", "mrd", "o.ä", "s.o", "s.u",
+    "sog", "u.ä", "u.u", "v.a", "v.chr", "n.chr", "zzgl", "allg", "ggü", "tel", "bzgl", "z.zt",
+];
+
+const FR: &[&str] = &[
+    "m", "mm", "mme", "mlle", "dr", "pr", "st", "ste", "etc", "cf", "pp", "av", "env", "fig", "vol", "éd", "bd",
+    "chap", "c.-à-d", "p.ex", "jr", "j.-c", "boul", "hab",
+];
+
+const ES: &[&str] = &[
+    "sr", "sra", "srta", "dr", "dra", "ud", "uds", "etc", "p.ej", "pág", "págs", "núm", "art", "av", "avda", "cap",
+    "cía", "dpto", "ej", "fig", "gral", "lic", "prof", "sta", "sto", "vol", "aprox", "admón", "tel", "ee.uu",
+];
+
+const IT: &[&str] = &[
+    "sig", "sigg", "sig.ra", "dott", "dott.ssa", "prof", "ing", "avv", "arch", "geom", "rag", "ecc", "pag", "pagg",
+    "vol", "cap", "fig", "art", "ca", "sec", "tel", "p.es", "sg",
+];
+
+const PT: &[&str] = &[
+    "sr", "sra", "srta", "dr", "dra", "prof", "profa", "etc", "p.ex", "pág", "págs", "núm", "art", "av", "cap", "fig",
+    "vol", "tel", "obs", "aprox", "ltda", "cia",
+];
+
+const NL: &[&str] = &[
+    "dhr", "mevr", "mw", "dr", "prof", "ir", "ing", "drs", "mr", "bijv", "bv", "d.w.z", "enz", "etc", "evt", "ca",
+    "blz", "nr", "o.a", "m.a.w", "t.a.v", "i.p.v", "vs", "zgn", "mln", "mld",
+];
+
+const RU: &[&str] = &[
+    "г", "гг", "т.е", "т.д", "т.п", "т.к", "др", "пр", "см", "стр", "ул", "кв", "им", "проф", "акад", "тыс", "млн",
+    "млрд", "руб", "коп", "напр", "т.н", "т.о", "в.т.ч", "обл", "пер",
+];
+
+/// The list for a language code, or None if there isn't one ("none" has an empty list).
+pub fn for_language(lang: &str) -> Option<&'static [&'static str]> {
+    match lang.to_lowercase().as_str() {
+        "en" => Some(EN),
+        "de" => Some(DE),
+        "fr" => Some(FR),
+        "es" => Some(ES),
+        "it" => Some(IT),
+        "pt" => Some(PT),
+        "nl" => Some(NL),
+        "ru" => Some(RU),
+        "none" => Some(&[]),
+        _ => None,
+    }
+}
+
+/// Languages where a number before a period is an ordinal ("am 3. Mai") rather than the end of a sentence.
+pub fn has_ordinal_periods(lang: &str) -> bool {
+    matches!(lang.to_lowercase().as_str(), "de")
+}
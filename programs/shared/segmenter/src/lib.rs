@@ -0,0 +1,192 @@
+use std::collections::HashSet;
+
+pub mod abbreviations;
+pub mod punkt;
+
+// sentence segmentation, shared by sentences.rs and sentence_seg.
+// a sentence ends at a run of terminals ("." "?!" "..." "。"), along with any closing quotes and brackets straight
+// after it ("Stop." she said -> `"Stop."`), where whitespace or the end of the text follows. it doesn't end there if:
+//   - the next word starts lowercase (`"Really?" she asked`, `wait... what`)
+//   - the terminal is a lone period after an abbreviation from the language's list or a model, after a single
+//     letter (an initial, "J. Smith"), after a word with periods inside ("U.S."), or after a number in a language
+//     with ordinal periods ("am 3. Mai"): unless what follows is a word a model has learnt starts sentences
+// decimals ("3.14") never have the whitespace. chinese and japanese full stops end sentences without any whitespace
+// after them, and thai, which has no sentence punctuation, has a sentence end at each space between thai letters.
+// punkt.rs learns abbreviations and sentence starters from unsegmented text, for languages without a list or to
+// add to one.
+
+/// Everything that can end a sentence, from latin, cjk, arabic, indic, ethiopic and other scripts, along with ⍰ and
+/// ≟, which stand in for question marks.
+pub const TERMINALS: &str = ".!?…‼⁇⁈⁉⸮⍰≟。！？｡︒؟\u{37e}۔܀܁܂።፧፨᙮᠃᠉꓿꘎꘏߹႟᥄।॥។៕။՞՜";
+
+/// Terminals that end a sentence with no whitespace after them.
+const UNSPACED: &str = "。！？｡︒";
+
+/// What can close a sentence after its terminal.
+pub const CLOSERS: &str = "\"'”’»›)]}）」』】〉》〗〕";
+
+/// What can open a sentence before its first word.
+pub const OPENERS: &str = "\"'“‘«‹([{（「『【〈《〖〔¿¡";
+
+fn is_thai(c: char) -> bool {
+    ('\u{0e00}'..='\u{0e7f}').contains(&c)
+}
+
+/// A token without the quotes and brackets around it.
+pub fn bare(token: &str) -> &str {
+    token.trim_start_matches(|c| OPENERS.contains(c)).trim_end_matches(|c| CLOSERS.contains(c))
+}
+
+#[derive(Debug, Clone)]
+pub struct Segmenter {
+    terminals: HashSet<char>,
+    abbreviations: HashSet<String>,
+    starters: HashSet<String>,
+    ordinals: bool,
+}
+
+impl Segmenter {
+    /// With the abbreviations of a language from abbreviations::LANGUAGES.
+    pub fn new(lang: &str) -> Result<Segmenter, String> {
+        let abbreviations = abbreviations::for_language(lang).ok_or_else(|| {
+            format!("there's no abbreviation list for {:?}, only {}", lang, abbreviations::LANGUAGES.join(", "))
+        })?;
+        Ok(Segmenter {
+            terminals: TERMINALS.chars().collect(),
+            abbreviations: abbreviations.iter().map(|a| a.to_string()).collect(),
+            starters: HashSet::new(),
+            ordinals: abbreviations::has_ordinal_periods(lang),
+        })
+    }
+
+    /// Only these terminals, instead of TERMINALS.
+    pub fn only_terminals(mut self, terminals: &str) -> Segmenter {
+        self.terminals = terminals.chars().collect();
+        self
+    }
+
+    pub fn add_terminals(mut self, terminals: &str) -> Segmenter {
+        self.terminals.extend(terminals.chars());
+        self
+    }
+
+    pub fn remove_terminals(mut self, terminals: &str) -> Segmenter {
+        terminals.chars().for_each(|c| {
+            self.terminals.remove(&c);
+        });
+        self
+    }
+
+    /// Adds the abbreviations and sentence starters a punkt model learnt.
+    pub fn learn(mut self, learned: &punkt::Learned) -> Segmenter {
+        self.abbreviations.extend(learned.abbreviations.iter().cloned());
+        self.starters.extend(learned.starters.iter().cloned());
+        self
+    }
+
+    pub fn is_abbreviation(&self, word: &str) -> bool {
+        self.abbreviations.contains(&word.to_lowercase())
+    }
+
+    /// The sentences in `text`, without the whitespace between them.
+    pub fn split<'a>(&self, text: &'a str) -> Vec<&'a str> {
+        let chars: Vec<(usize, char)> = text.char_indices().collect();
+        let byte = |i: usize| chars.get(i).map(|(b, _)| *b).unwrap_or_else(|| text.len());
+        let mut sentences = vec![];
+        let mut start = 0;
+        let mut i = 0;
+        while i < chars.len() {
+            let c = chars[i].1;
+            if c.is_whitespace() && i > 0 && is_thai(chars[i - 1].1) {
+                let next = chars[i..].iter().find(|(_, c)| !c.is_whitespace());
+                if next.map(|(_, c)| is_thai(*c)).unwrap_or_else(|| false) {
+                    sentences.push(&text[start..byte(i)]);
+                    start = byte(i);
+                }
+                i += 1;
+                continue;
+            }
+            if !self.terminals.contains(&c) {
+                i += 1;
+                continue;
+            }
+            let mut end = i + 1;
+            while end < chars.len() && self.terminals.contains(&chars[end].1) {
+                end += 1;
+            }
+            let last = chars[end - 1].1;
+            while end < chars.len() && CLOSERS.contains(chars[end].1) {
+                end += 1;
+            }
+            let ends = end == chars.len()
+                || UNSPACED.contains(last)
+                || (chars[end].1.is_whitespace() && self.ends_sentence(text, &chars[..i], &text[byte(i)..byte(end)], &text[byte(end)..]));
+            if ends {
+                sentences.push(&text[start..byte(end)]);
+                start = byte(end);
+            }
+            i = end;
+        }
+        sentences.push(&text[start..]);
+        sentences.into_iter().map(str::trim).filter(|s| !s.is_empty()).collect()
+    }
+
+    /// Whether a run of terminals (and closers) followed by whitespace ends a sentence, given what's before and after.
+    fn ends_sentence(&self, text: &str, before: &[(usize, char)], run: &str, after: &str) -> bool {
+        let next = after.split_whitespace().next().map(bare).unwrap_or_else(|| "");
+        let next_first = next.chars().next();
+        if next_first.map(|c| c.is_lowercase()).unwrap_or_else(|| false) {
+            return false;
+        }
+        if run.trim_end_matches(|c| CLOSERS.contains(c)) != "." {
+            return true;
+        }
+        let word_start = before
+            .iter()
+            .rposition(|(_, c)| c.is_whitespace())
+            .map(|p| before[p].0 + 1)
+            .unwrap_or_else(|| 0);
+        let word_end = before.last().map(|(b, c)| b + c.len_utf8()).unwrap_or_else(|| 0);
+        let word = bare(&text[word_start.min(word_end)..word_end]);
+        if word.is_empty() {
+            return true;
+        }
+        let letters = word.chars().filter(|c| c.is_alphabetic()).count();
+        let abbreviated = self.is_abbreviation(word)
+            || (letters == 1 && word.chars().count() == 1)
+            || (word.contains('.') && letters > 0 && word.split('.').all(|part| part.chars().count() <= 4))
+            || (self.ordinals && word.chars().all(|c| c.is_ascii_digit()));
+        !abbreviated || (next_first.map(|c| c.is_uppercase()).unwrap_or_else(|| false) && self.starters.contains(&next.to_lowercase()))
+    }
+}
+
+#[test]
+fn test_split() {
+    let en = Segmenter::new("en").unwrap();
+    assert_eq!(en.split("It works. It really does!"), vec!["It works.", "It really does!"]);
+    assert_eq!(en.split("Dr. Smith paid $3.50 for it. Then he left."), vec!["Dr. Smith paid $3.50 for it.", "Then he left."]);
+    assert_eq!(en.split("We met J. R. Tolkien, e.g. at lunch. Yes."), vec!["We met J. R. Tolkien, e.g. at lunch.", "Yes."]);
+    assert_eq!(en.split("\"Stop.\" She did. \"Really?\" she asked."), vec!["\"Stop.\"", "She did.", "\"Really?\" she asked."]);
+    assert_eq!(en.split("Wait... what? Well... Fine."), vec!["Wait... what?", "Well...", "Fine."]);
+    assert_eq!(en.split("(See above.) Done"), vec!["(See above.)", "Done"]);
+    assert_eq!(en.split("no terminal at all"), vec!["no terminal at all"]);
+    assert_eq!(en.split("  "), Vec::<&str>::new());
+    assert_eq!(en.split("今日は晴れです。明日は雨です！本当？"), vec!["今日は晴れです。", "明日は雨です！", "本当？"]);
+    assert_eq!(en.split("วันนี้อากาศดี พรุ่งนี้ฝนตก"), vec!["วันนี้อากาศดี", "พรุ่งนี้ฝนตก"]);
+    assert_eq!(en.split("Это так? Да."), vec!["Это так?", "Да."]);
+    assert_eq!(en.split("Ինչ՜ Is it⍰ Maybe≟ No"), vec!["Ինչ՜", "Is it⍰", "Maybe≟", "No"]);
+
+    let de = Segmenter::new("de").unwrap();
+    assert_eq!(de.split("Er kam am 3. Mai, d.h. spät. Dann ging er."), vec!["Er kam am 3. Mai, d.h. spät.", "Dann ging er."]);
+    assert_eq!(en.split("It was 3. Then 4."), vec!["It was 3.", "Then 4."]);
+
+    let mut learned = punkt::Learned::default();
+    learned.starters.insert("the".to_string());
+    let taught = Segmenter::new("en").unwrap().learn(&learned);
+    assert_eq!(en.split("Apples, pears etc. The rest"), vec!["Apples, pears etc. The rest"]);
+    assert_eq!(taught.split("Apples, pears etc. The rest"), vec!["Apples, pears etc.", "The rest"]);
+
+    let semicolons = Segmenter::new("none").unwrap().add_terminals(";").remove_terminals("!");
+    assert_eq!(semicolons.split("one; Two! Three"), vec!["one;", "Two! Three"]);
+    assert!(Segmenter::new("xx").is_err());
+}
@@ -0,0 +1,199 @@
+use crate::bare;
+use std::collections::{BTreeSet, HashMap};
+use std::io::Write;
+
+// unsupervised training after punkt (kiss & strunk, 2006): from plain text that hasn't been segmented, learn
+//   - abbreviations: words nearly always followed by a period, short, maybe with periods inside, and seldom seen
+//     without one. a word's score is how much likelier a period after it is than after any word (a log-likelihood
+//     ratio), shrunk the longer it is and the more often it turns up without a period.
+//   - sentence starters: words that come after sentence ends (periods after words that aren't abbreviations) far
+//     more often than chance, so a capitalised one after an abbreviation starts a new sentence ("etc. The").
+// a model is a text file of `abbreviation\t<word>` and `starter\t<word>` lines, so it can be read and edited.
+
+const ABBREVIATION_SCORE: f64 = 0.3;
+const STARTER_SCORE: f64 = 30.0;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Learned {
+    pub abbreviations: BTreeSet<String>,
+    pub starters: BTreeSet<String>,
+}
+
+impl Learned {
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let write = || -> std::io::Result<()> {
+            let mut w = std::io::BufWriter::new(std::fs::File::create(path)?);
+            for abbreviation in &self.abbreviations {
+                writeln!(w, "abbreviation\t{}", abbreviation)?;
+            }
+            for starter in &self.starters {
+                writeln!(w, "starter\t{}", starter)?;
+            }
+            w.flush()
+        };
+        write().map_err(|e| format!("couldn't write the model {}: {}", path, e))
+    }
+
+    pub fn load(path: &str) -> Result<Learned, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("couldn't read the model {}: {}", path, e))?;
+        let mut learned = Learned::default();
+        for (i, line) in text.lines().enumerate().filter(|(_, l)| !l.trim().is_empty()) {
+            match line.split_once('\t') {
+                Some(("abbreviation", word)) => learned.abbreviations.insert(word.to_string()),
+                Some(("starter", word)) => learned.starters.insert(word.to_string()),
+                _ => return Err(format!("line {} of {} isn't `abbreviation\\t<word>` or `starter\\t<word>`", i + 1, path)),
+            };
+        }
+        Ok(learned)
+    }
+}
+
+/// How often each word (lowercase, without a final period) turns up with and without a period after it, and after
+/// what look like sentence ends.
+#[derive(Debug, Clone, Default)]
+pub struct Trainer {
+    with_period: HashMap<String, u64>,
+    without_period: HashMap<String, u64>,
+    // how often each word ending in a period is followed by each word
+    breaks: HashMap<(String, String), u64>,
+    tokens: u64,
+    periods: u64,
+}
+
+/// A token's word, lowercase, and whether a lone period ended it (not "..." or "?").
+fn word(token: &str) -> Option<(String, bool)> {
+    let token = bare(token);
+    let (word, period) = match token.strip_suffix('.') {
+        Some(word) if !word.ends_with('.') => (word, true),
+        _ => (token, false),
+    };
+    match word.chars().any(|c| c.is_alphabetic()) {
+        true => Some((word.to_lowercase(), period)),
+        false => None,
+    }
+}
+
+fn ln(x: f64) -> f64 {
+    x.max(f64::MIN_POSITIVE).ln()
+}
+
+/// Dunning's log-likelihood that a period after a word is no likelier than after any word, against it being 0.99.
+fn abbreviation_likelihood(word_count: f64, periods: f64, word_with_period: f64, tokens: f64) -> f64 {
+    let (p_any, p_abbreviation) = (periods / tokens, 0.99);
+    let without = word_count - word_with_period;
+    let null = word_with_period * ln(p_any) + without * ln(1.0 - p_any);
+    let alternative = word_with_period * ln(p_abbreviation) + without * ln(1.0 - p_abbreviation);
+    -2.0 * (null - alternative)
+}
+
+/// Dunning's log-likelihood ratio for `a` and `b` turning up together `ab` times out of `n`.
+fn collocation_likelihood(a: f64, b: f64, ab: f64, n: f64) -> f64 {
+    let p = b / n;
+    let p1 = ab / a;
+    let p2 = (b - ab) / (n - a);
+    let null = ab * ln(p) + (a - ab) * ln(1.0 - p) + (b - ab) * ln(p) + (n - a - b + ab) * ln(1.0 - p);
+    let together = match a == ab {
+        true => 0.0,
+        false => ab * ln(p1) + (a - ab) * ln(1.0 - p1),
+    };
+    let apart = match b == ab {
+        true => 0.0,
+        false => (b - ab) * ln(p2) + (n - a - b + ab) * ln(1.0 - p2),
+    };
+    -2.0 * (null - together - apart)
+}
+
+impl Trainer {
+    pub fn add(&mut self, text: &str) {
+        let words: Vec<Option<(String, bool)>> = text.split_whitespace().map(word).collect();
+        for (i, w) in words.iter().enumerate() {
+            let (word, period) = match w {
+                Some(w) => w,
+                None => continue,
+            };
+            self.tokens += 1;
+            let counts = match period {
+                true => &mut self.with_period,
+                false => &mut self.without_period,
+            };
+            *counts.entry(word.clone()).or_insert(0) += 1;
+            if *period {
+                self.periods += 1;
+                if let Some(Some((next, _))) = words.get(i + 1) {
+                    *self.breaks.entry((word.clone(), next.clone())).or_insert(0) += 1;
+                }
+            }
+        }
+    }
+
+    fn abbreviation_score(&self, word: &str) -> f64 {
+        let with = *self.with_period.get(word).unwrap_or_else(|| &0) as f64;
+        let without = *self.without_period.get(word).unwrap_or_else(|| &0) as f64;
+        let length = word.chars().filter(|c| *c != '.').count() as f64;
+        let periods_inside = word.chars().filter(|c| *c == '.').count() as f64;
+        abbreviation_likelihood(with + without, self.periods as f64, with, self.tokens.max(1) as f64)
+            * (-length).exp()
+            * (periods_inside + 1.0)
+            * length.powf(-without)
+    }
+
+    pub fn finish(&self) -> Learned {
+        let abbreviations: BTreeSet<String> = self
+            .with_period
+            .keys()
+            .filter(|word| self.abbreviation_score(word) >= ABBREVIATION_SCORE)
+            .cloned()
+            .collect();
+
+        // sentence ends are the periods after anything that isn't an abbreviation or an initial
+        let mut after_ends: HashMap<&str, u64> = HashMap::new();
+        let mut ends = 0;
+        for ((word, next), count) in &self.breaks {
+            if abbreviations.contains(word) || word.chars().count() == 1 {
+                continue;
+            }
+            ends += count;
+            *after_ends.entry(next.as_str()).or_insert(0) += count;
+        }
+        let tokens = self.tokens.max(1) as f64;
+        let starters = after_ends
+            .into_iter()
+            .filter(|(word, after)| {
+                let count = (self.with_period.get(*word).unwrap_or_else(|| &0)
+                    + self.without_period.get(*word).unwrap_or_else(|| &0)) as f64;
+                // likelier after a sentence end than anywhere, and likely enough to count
+                (*after as f64 / ends as f64) > (count / tokens)
+                    && collocation_likelihood(ends as f64, count, *after as f64, tokens) >= STARTER_SCORE
+            })
+            .map(|(word, _)| word.to_string())
+            .collect();
+        Learned { abbreviations, starters }
+    }
+}
+
+#[test]
+fn test_punkt() {
+    let mut trainer = Trainer::default();
+    for i in 0..300 {
+        trainer.add(&format!(
+            "The shop sold approx. {} apples and pears on the day. However the farmer grew more. \
+             It cost approx. {} coins. Nobody knew why more of the day went by so quickly at the shop.",
+            i,
+            i * 3
+        ));
+    }
+    let learned = trainer.finish();
+    assert!(learned.abbreviations.contains("approx"), "{:?}", learned);
+    assert!(!learned.abbreviations.contains("day"));
+    assert!(!learned.abbreviations.contains("more"));
+    assert!(learned.starters.contains("however"), "{:?}", learned);
+    assert!(!learned.starters.contains("apples"));
+
+    let path = std::env::temp_dir().join(format!("segmenter_{}.model", std::process::id()));
+    let path = path.to_str().unwrap();
+    learned.save(path).unwrap();
+    assert_eq!(Learned::load(path).unwrap(), learned);
+    std::fs::write(path, "abbrev dr\n").unwrap();
+    assert!(Learned::load(path).is_err());
+    let _ = std::fs::remove_file(path);
+}
@@ -0,0 +1,371 @@
+use crate::parse_args::Args;
+use crate::spec::ArgSpec;
+
+// the arguments every tool! binary accepts on top of its own
+pub(crate) const META_ARGS: &[ArgSpec] = &[
+    ArgSpec {
+        name: "config",
+        typ: Some("Option<Filename>"),
+        default: Some("None"),
+        cond: None,
+        because: None,
+    },
+    ArgSpec {
+        name: "generate-completions",
+        typ: Some("Option<String>"),
+        default: Some("None"),
+        cond: None,
+        because: None,
+    },
+    ArgSpec {
+        name: "input",
+        typ: Some("Option<Vec<Filename>>"),
+        default: Some("None"),
+        cond: None,
+        because: None,
+    },
+    ArgSpec {
+        name: "with_source",
+        typ: None,
+        default: None,
+        cond: None,
+        because: None,
+    },
+    ArgSpec {
+        name: "progress",
+        typ: None,
+        default: None,
+        cond: None,
+        because: None,
+    },
+    ArgSpec {
+        name: "output_compression",
+        typ: Some("Option<String>"),
+        default: Some("None"),
+        cond: None,
+        because: None,
+    },
+    ArgSpec {
+        name: "record_format",
+        typ: Some("Option<String>"),
+        default: Some("None"),
+        cond: None,
+        because: None,
+    },
+    ArgSpec {
+        name: "fields",
+        typ: Some("Option<String>"),
+        default: Some("None"),
+        cond: None,
+        because: None,
+    },
+    ArgSpec {
+        name: "header",
+        typ: None,
+        default: None,
+        cond: None,
+        because: None,
+    },
+    ArgSpec {
+        name: "on_missing",
+        typ: Some("Option<String>"),
+        default: Some("None"),
+        cond: None,
+        because: None,
+    },
+    // None is all of them
+    ArgSpec {
+        name: "threads",
+        typ: Some("Option<usize>"),
+        default: Some("None"),
+        cond: None,
+        because: None,
+    },
+    ArgSpec {
+        name: "chunk_lines",
+        typ: Some("usize"),
+        default: Some("4096"),
+        cond: None,
+        because: None,
+    },
+    ArgSpec {
+        name: "sync_mode",
+        typ: Some("bool"),
+        default: Some("false"),
+        cond: None,
+        because: None,
+    },
+    ArgSpec {
+        name: "serve",
+        typ: None,
+        default: None,
+        cond: None,
+        because: None,
+    },
+    ArgSpec {
+        name: "schema",
+        typ: None,
+        default: None,
+        cond: None,
+        because: None,
+    },
+    ArgSpec {
+        name: "generate-man",
+        typ: None,
+        default: None,
+        cond: None,
+        because: None,
+    },
+    ArgSpec {
+        name: "help",
+        typ: None,
+        default: None,
+        cond: None,
+        because: None,
+    },
+];
+
+pub const SHELLS: &[&str] = &["bash", "zsh", "fish"];
+
+fn all_args(specs: &[ArgSpec]) -> impl Iterator<Item = &ArgSpec> {
+    specs.iter().chain(META_ARGS.iter())
+}
+
+pub fn bash_completions(tool: &str, specs: &[ArgSpec]) -> String {
+    let fn_name = format!("_{}", tool.replace(|c: char| !c.is_ascii_alphanumeric(), "_"));
+    let words = all_args(specs).map(|s| s.flag()).collect::<Vec<_>>().join(" ");
+    let paths = all_args(specs)
+        .filter(|s| s.takes_path())
+        .map(|s| s.flag())
+        .collect::<Vec<_>>();
+    let bools = all_args(specs)
+        .filter(|s| s.is_bool())
+        .map(|s| s.flag())
+        .collect::<Vec<_>>();
+    let mut cases = String::new();
+    if !paths.is_empty() {
+        cases.push_str(&format!(
+            "        {})\n            COMPREPLY=( $(compgen -f -- \"$cur\") )\n            return 0;;\n",
+            paths.join("|")
+        ));
+    }
+    if !bools.is_empty() {
+        cases.push_str(&format!(
+            "        {})\n            COMPREPLY=( $(compgen -W \"true false\" -- \"$cur\") )\n            return 0;;\n",
+            bools.join("|")
+        ));
+    }
+    cases.push_str(&format!(
+        "        --generate-completions)\n            COMPREPLY=( $(compgen -W \"{}\" -- \"$cur\") )\n            return 0;;\n",
+        SHELLS.join(" ")
+    ));
+    format!(
+        "{fn_name}() {{\n    local cur prev\n    cur=\"${{COMP_WORDS[COMP_CWORD]}}\"\n    prev=\"${{COMP_WORDS[COMP_CWORD-1]}}\"\n    case \"$prev\" in\n{cases}    esac\n    COMPREPLY=( $(compgen -W \"{words}\" -- \"$cur\") )\n}}\ncomplete -F {fn_name} {tool}\n"
+    )
+}
+
+fn zsh_escape(s: &str) -> String {
+    s.replace('\'', "'\\''")
+        .replace('[', "\\[")
+        .replace(']', "\\]")
+}
+
+pub fn zsh_completions(tool: &str, specs: &[ArgSpec]) -> String {
+    let lines = all_args(specs)
+        .map(|s| {
+            let action = if s.is_flag() {
+                "".to_string()
+            } else if s.name == "generate-completions" {
+                format!(":shell:({})", SHELLS.join(" "))
+            } else if s.is_bool() {
+                format!(":{}:(true false)", s.name)
+            } else if s.takes_path() {
+                format!(":{}:_files", s.name)
+            } else {
+                format!(":{}:", s.name)
+            };
+            format!("    '{}[{}]{}'", s.flag(), zsh_escape(&s.describe()), action)
+        })
+        .collect::<Vec<_>>()
+        .join(" \\\n");
+    format!("#compdef {tool}\n\n_arguments \\\n{lines}\n")
+}
+
+pub fn fish_completions(tool: &str, specs: &[ArgSpec]) -> String {
+    all_args(specs)
+        .map(|s| {
+            let mut line = format!("complete -c {} -l {}", tool, s.name);
+            if !s.is_flag() {
+                line.push_str(" -r");
+            }
+            if s.name == "generate-completions" {
+                line.push_str(&format!(" -f -a '{}'", SHELLS.join(" ")));
+            } else if s.is_bool() {
+                line.push_str(" -f -a 'true false'");
+            } else if s.takes_path() {
+                line.push_str(" -F");
+            }
+            line.push_str(&format!(" -d '{}'\n", s.describe().replace('\'', "\\'")));
+            line
+        })
+        .collect()
+}
+
+fn roff_escape(s: &str) -> String {
+    let escaped = s.replace('\\', "\\e").replace('-', "\\-");
+    match escaped.starts_with('.') || escaped.starts_with('\'') {
+        true => format!("\\&{}", escaped),
+        false => escaped,
+    }
+}
+
+pub fn man_page(tool: &str, specs: &[ArgSpec]) -> String {
+    let upper = crate::config::tool_name();
+    let mut page = format!(
+        ".TH {} 1\n.SH NAME\n{}\n.SH SYNOPSIS\n.B {}\n",
+        roff_escape(&tool.to_uppercase()),
+        roff_escape(tool),
+        roff_escape(tool)
+    );
+    specs.iter().for_each(|s| {
+        let usage = match s.typ {
+            Some(typ) => format!("\\fB{}\\fR \\fI{}\\fR", roff_escape(&s.flag()), roff_escape(typ)),
+            None => format!("\\fB{}\\fR", roff_escape(&s.flag())),
+        };
+        match s.is_required() {
+            true => page.push_str(&format!("{}\n", usage)),
+            false => page.push_str(&format!("[{}]\n", usage)),
+        }
+    });
+    page.push_str(".SH OPTIONS\n");
+    all_args(specs).for_each(|s| {
+        page.push_str(".TP\n");
+        match s.typ {
+            Some(typ) => page.push_str(&format!(
+                "\\fB{}\\fR \\fI{}\\fR\n",
+                roff_escape(&s.flag()),
+                roff_escape(typ)
+            )),
+            None => page.push_str(&format!("\\fB{}\\fR\n", roff_escape(&s.flag()))),
+        }
+        let mut details = vec![];
+        if let Some(default) = s.default {
+            details.push(format!("default: {}", roff_escape(default)));
+        }
+        if s.is_required() {
+            details.push("required".to_string());
+        }
+        if let Some(cond) = s.cond {
+            details.push(format!("fails if: {}", roff_escape(cond)));
+        }
+        if let Some(because) = s.because {
+            details.push(format!("because: {}", roff_escape(because)));
+        }
+        if details.is_empty() {
+            details.push(match s.is_flag() {
+                true => "flag".to_string(),
+                false => "optional".to_string(),
+            });
+        }
+        page.push_str(&details.join("\n.br\n"));
+        page.push('\n');
+    });
+    page.push_str(&format!(
+        ".SH ENVIRONMENT\nEvery option can also be set with a {}_ARGNAME environment variable, e.g. {}_{}.\nThe command line wins over the environment, which wins over the \\fB\\-\\-config\\fR file.\n",
+        roff_escape(&upper),
+        roff_escape(&upper),
+        roff_escape(&specs.first().map(|s| s.name.to_uppercase()).unwrap_or_else(|| "ARGNAME".to_string()))
+    ));
+    page
+}
+
+pub fn completions(shell: &str, tool: &str, specs: &[ArgSpec]) -> Result<String, String> {
+    match shell {
+        "bash" => Ok(bash_completions(tool, specs)),
+        "zsh" => Ok(zsh_completions(tool, specs)),
+        "fish" => Ok(fish_completions(tool, specs)),
+        _ => Err(format!(
+            "--generate-completions needs one of {}, not {:?}",
+            SHELLS.join(", "),
+            shell
+        )),
+    }
+}
+
+/// Returns the completion script, man page or json schema if one was asked for, in which case the tool shouldn't run.
+pub fn generate_from_specs(specs: &[ArgSpec]) -> Result<Option<String>, String> {
+    let args = Args::new(std::env::args());
+    let tool = crate::config::binary_name();
+    if let Some(shell) = args.get_value_of("--generate-completions") {
+        return completions(&shell, &tool, specs).map(Some);
+    }
+    if args.check_flag("--generate-man") {
+        return Ok(Some(man_page(&tool, specs)));
+    }
+    if args.check_flag("--schema") {
+        return Ok(Some(format!("{}\n", crate::schema::schema_string(&tool, specs))));
+    }
+    Ok(None)
+}
+
+pub fn completions_help() -> String {
+    format!(
+        "--generate-completions {} prints a completion script, --generate-man prints a man page, and --schema prints a json schema of the arguments.\n",
+        SHELLS.join("|")
+    )
+}
+
+#[cfg(test)]
+const TEST_SPECS: &[ArgSpec] = &[
+    ArgSpec {
+        name: "max_words",
+        typ: Some("usize"),
+        default: Some("1000000"),
+        cond: Some("max_words == 0"),
+        because: Some("max_words can't be zero"),
+    },
+    ArgSpec {
+        name: "input_file",
+        typ: Some("String"),
+        default: None,
+        cond: None,
+        because: None,
+    },
+    ArgSpec {
+        name: "lowercase",
+        typ: None,
+        default: None,
+        cond: None,
+        because: None,
+    },
+];
+
+#[test]
+fn test_completions() {
+    let bash = completions("bash", "lengthfilter", TEST_SPECS).unwrap();
+    assert!(bash.contains("complete -F _lengthfilter lengthfilter"));
+    assert!(bash.contains("--input_file|--config|--input)"));
+    assert!(bash.contains("--max_words --input_file --lowercase --config"));
+    assert!(bash.contains("--threads --chunk_lines --sync_mode"));
+    assert!(bash.contains("--sync_mode)\n            COMPREPLY=( $(compgen -W \"true false\""));
+
+    let zsh = completions("zsh", "lengthfilter", TEST_SPECS).unwrap();
+    assert!(zsh.starts_with("#compdef lengthfilter"));
+    assert!(zsh.contains("'--input_file[type: String, required]:input_file:_files'"));
+    assert!(zsh.contains("'--lowercase[flag]'"));
+
+    let fish = completions("fish", "lengthfilter", TEST_SPECS).unwrap();
+    assert!(fish.contains("complete -c lengthfilter -l max_words -r -d 'type: usize, default: 1000000, fails if: max_words == 0, because: max_words can\\'t be zero'"));
+    assert!(fish.contains("complete -c lengthfilter -l lowercase -d 'flag'"));
+
+    assert!(completions("powershell", "lengthfilter", TEST_SPECS).is_err());
+}
+
+#[test]
+fn test_man_page() {
+    let page = man_page("lengthfilter", TEST_SPECS);
+    assert!(page.starts_with(".TH LENGTHFILTER 1"));
+    assert!(page.contains("\\fB\\-\\-input_file\\fR \\fIString\\fR\n[\\fB\\-\\-lowercase\\fR]"));
+    assert!(page.contains("fails if: max_words == 0\n.br\nbecause: max_words can't be zero"));
+    assert!(page.contains("\\-\\-chunk_lines"));
+}
@@ -0,0 +1,319 @@
+use std::io::prelude::*;
+use std::io::{Cursor, Error, ErrorKind};
+
+// input compression is sniffed from the first bytes, so stdin and file arguments can be gz/zst/xz/bz2 or plain
+// without anyone saying which. output stays plain unless --output_compression asks otherwise.
+// each codec sits behind a cargo feature of the same name (all on by default).
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+    Xz,
+    Bzip2,
+}
+
+/// How many bytes Compression::detect needs to see at most.
+pub const HEADER_LEN: usize = 10;
+
+// after "BZh" and the block size, a bzip2 stream starts with a block or, if it's empty, the end of the stream
+const BZIP2_BLOCK: [u8; 6] = [0x31, 0x41, 0x59, 0x26, 0x53, 0x59];
+const BZIP2_END: [u8; 6] = [0x17, 0x72, 0x45, 0x38, 0x50, 0x90];
+
+impl Compression {
+    pub fn detect(header: &[u8]) -> Compression {
+        if header.starts_with(&[0x1f, 0x8b]) {
+            Compression::Gzip
+        } else if header.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Compression::Zstd
+        } else if header.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Compression::Xz
+        } else if header.len() >= HEADER_LEN
+            && header.starts_with(b"BZh")
+            && (b'1'..=b'9').contains(&header[3])
+            && (header[4..HEADER_LEN] == BZIP2_BLOCK || header[4..HEADER_LEN] == BZIP2_END)
+        {
+            Compression::Bzip2
+        } else {
+            Compression::None
+        }
+    }
+
+    /// Whether more bytes could still make `header` detect as compressed: it's short, and the start of a magic.
+    pub fn undecided(header: &[u8]) -> bool {
+        let prefix_of = |magic: &[u8]| magic.starts_with(header) || header.starts_with(magic);
+        let bzip2 = header.iter().enumerate().all(|(i, b)| match i {
+            0..=2 => *b == b"BZh"[i],
+            3 => (b'1'..=b'9').contains(b),
+            _ => BZIP2_BLOCK[i - 4] == *b || BZIP2_END[i - 4] == *b,
+        });
+        header.len() < HEADER_LEN
+            && Compression::detect(header) == Compression::None
+            && (prefix_of(&[0x1f, 0x8b]) || prefix_of(&[0x28, 0xb5, 0x2f, 0xfd]) || prefix_of(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) || bzip2)
+    }
+
+    pub fn from_name(name: &str) -> Result<Compression, String> {
+        match name.trim_start_matches('.').to_lowercase().as_str() {
+            "none" | "" => Ok(Compression::None),
+            "gz" | "gzip" => Ok(Compression::Gzip),
+            "zst" | "zstd" => Ok(Compression::Zstd),
+            "xz" => Ok(Compression::Xz),
+            "bz2" | "bzip2" => Ok(Compression::Bzip2),
+            _ => Err(format!(
+                "--output_compression needs one of none, gz, zst, xz, bz2, not {:?}",
+                name
+            )),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Compression::None => "none",
+            Compression::Gzip => "gz",
+            Compression::Zstd => "zst",
+            Compression::Xz => "xz",
+            Compression::Bzip2 => "bz2",
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        match self {
+            Compression::None => true,
+            Compression::Gzip => cfg!(feature = "gz"),
+            Compression::Zstd => cfg!(feature = "zst"),
+            Compression::Xz => cfg!(feature = "xz"),
+            Compression::Bzip2 => cfg!(feature = "bz2"),
+        }
+    }
+
+    fn disabled_error(&self) -> Error {
+        Error::new(
+            ErrorKind::Unsupported,
+            format!(
+                "{} data, but term_macros was built without its {:?} feature",
+                self.name(),
+                self.name()
+            ),
+        )
+    }
+}
+
+/// Wraps input in whichever decoder its first bytes call for. Concatenated streams (e.g. `cat a.gz b.gz`) are read through.
+/// It only waits for more than the first bytes to arrive while they could be a compressed header, so a line typed
+/// into --sync_mode comes straight through.
+pub fn decompress_reader<R: Read + Send + 'static>(mut input: R) -> std::io::Result<Box<dyn Read + Send>> {
+    let mut header = vec![0; HEADER_LEN];
+    let mut len = 0;
+    while len == 0 || Compression::undecided(&header[..len]) {
+        match input.read(&mut header[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    header.truncate(len);
+    let compression = Compression::detect(&header);
+    let input = Cursor::new(header).chain(input);
+    match compression {
+        Compression::None => Ok(Box::new(input)),
+        #[cfg(feature = "gz")]
+        Compression::Gzip => Ok(Box::new(flate2::read::MultiGzDecoder::new(input))),
+        #[cfg(feature = "zst")]
+        Compression::Zstd => Ok(Box::new(zstd::stream::read::Decoder::new(input)?)),
+        #[cfg(feature = "xz")]
+        Compression::Xz => Ok(Box::new(xz2::read::XzDecoder::new_multi_decoder(input))),
+        #[cfg(feature = "bz2")]
+        Compression::Bzip2 => Ok(Box::new(bzip2::read::MultiBzDecoder::new(input))),
+        #[allow(unreachable_patterns)]
+        other => Err(other.disabled_error()),
+    }
+}
+
+/// Wraps output in an encoder. The stream is finished when the writer is dropped.
+pub fn compress_writer<W: Write + 'static>(output: W, compression: Compression) -> std::io::Result<Box<dyn Write>> {
+    match compression {
+        Compression::None => Ok(Box::new(output)),
+        #[cfg(feature = "gz")]
+        Compression::Gzip => Ok(Box::new(flate2::write::GzEncoder::new(output, flate2::Compression::default()))),
+        #[cfg(feature = "zst")]
+        Compression::Zstd => Ok(Box::new(zstd::stream::write::Encoder::new(output, 0)?.auto_finish())),
+        #[cfg(feature = "xz")]
+        Compression::Xz => Ok(Box::new(xz2::write::XzEncoder::new(output, 6))),
+        #[cfg(feature = "bz2")]
+        Compression::Bzip2 => Ok(Box::new(bzip2::write::BzEncoder::new(output, bzip2::Compression::default()))),
+        #[allow(unreachable_patterns)]
+        other => Err(other.disabled_error()),
+    }
+}
+
+/// What --output_compression asked for, from any layer (cli, env, config), or no compression.
+/// tool! checks this before the body runs, so a bad name is reported with the help rather than mid-output.
+pub fn output_compression() -> Result<Compression, String> {
+    let compression = match crate::config::find_layered_arg::<String>("--output_compression")? {
+        Some(name) => Compression::from_name(&name)?,
+        None => Compression::None,
+    };
+    match compression.enabled() {
+        true => Ok(compression),
+        false => Err(compression.disabled_error().to_string()),
+    }
+}
+
+pub fn compression_help() -> String {
+    "gz, zst, xz and bz2 input is decompressed automatically; --output_compression gz|zst|xz|bz2 compresses the output.\n".to_string()
+}
+
+/// stdin, decompressed if it needs to be. What readin!/filter_in! read from.
+pub fn stdin() -> Box<dyn Read + Send> {
+    decompress_reader(std::io::stdin()).unwrap_or_else(|e| panic!("couldn't read stdin: {}", e))
+}
+
+/// stdout, compressed if --output_compression says so. What readin!/filter_in! write to.
+pub fn stdout() -> Box<dyn Write> {
+    let compression = output_compression().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1)
+    });
+    compress_writer(std::io::stdout(), compression).unwrap_or_else(|e| {
+        eprintln!("couldn't write to stdout: {}", e);
+        std::process::exit(1)
+    })
+}
+
+/// Opens a file for reading, decompressed if it needs to be.
+pub fn open(path: impl AsRef<std::path::Path>) -> std::io::Result<Box<dyn Read + Send>> {
+    decompress_reader(std::fs::File::open(path)?)
+}
+
+pub fn read_to_string(path: impl AsRef<std::path::Path>) -> std::io::Result<String> {
+    let mut data = String::new();
+    open(path)?.read_to_string(&mut data)?;
+    Ok(data)
+}
+
+/// A plain file stays memory-mapped; a compressed one is decompressed into memory, since there's nothing to map.
+pub enum Mapped {
+    Mmap(memmap::Mmap),
+    Owned(Vec<u8>),
+}
+
+impl std::ops::Deref for Mapped {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Mapped::Mmap(mmap) => &mmap[..],
+            Mapped::Owned(data) => &data[..],
+        }
+    }
+}
+
+/// What mmap!/mmap_str! use.
+pub fn map_file(path: impl AsRef<std::path::Path>) -> std::io::Result<Mapped> {
+    let mut file = std::fs::File::open(&path)?;
+    let mut header = Vec::with_capacity(HEADER_LEN);
+    (&mut file).take(HEADER_LEN as u64).read_to_end(&mut header)?;
+    if header.is_empty() {
+        // empty files can't be mapped
+        return Ok(Mapped::Owned(vec![]));
+    }
+    if Compression::detect(&header) == Compression::None {
+        return unsafe { memmap::MmapOptions::new().map(&file) }.map(Mapped::Mmap);
+    }
+    let mut data = vec![];
+    open(path)?.read_to_end(&mut data)?;
+    Ok(Mapped::Owned(data))
+}
+
+#[test]
+fn test_detect() {
+    assert_eq!(Compression::detect(b"\x1f\x8b\x08\x00"), Compression::Gzip);
+    assert_eq!(Compression::detect(b"\x28\xb5\x2f\xfd\x04"), Compression::Zstd);
+    assert_eq!(Compression::detect(b"\xfd7zXZ\x00\x00"), Compression::Xz);
+    assert_eq!(Compression::detect(b"BZh91AY&SY\x00"), Compression::Bzip2);
+    assert_eq!(Compression::detect(b"BZh9\x17rE8P\x90"), Compression::Bzip2);
+    assert_eq!(Compression::detect(b"BZh is how it starts\n"), Compression::None);
+    assert_eq!(Compression::detect(b"BZh91AY"), Compression::None);
+    assert_eq!(Compression::detect(b"plain text\n"), Compression::None);
+    assert_eq!(Compression::detect(b""), Compression::None);
+    assert!(Compression::undecided(b"") && Compression::undecided(b"\x1f") && Compression::undecided(b"BZh9\x31A"));
+    assert!(!Compression::undecided(b"hi\n") && !Compression::undecided(b"BZh is") && !Compression::undecided(b"\x1f\x8b"));
+    assert_eq!(Compression::from_name("zstd"), Ok(Compression::Zstd));
+    assert!(Compression::from_name("lz4").is_err());
+}
+
+#[cfg(test)]
+#[derive(Clone, Default)]
+struct Sink(std::rc::Rc<std::cell::RefCell<Vec<u8>>>);
+
+#[cfg(test)]
+impl Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_roundtrip() {
+    let text: String = (0..1000).map(|i| format!("line {}\n", i)).collect();
+    [Compression::None, Compression::Gzip, Compression::Zstd, Compression::Xz, Compression::Bzip2]
+        .iter()
+        .filter(|c| c.enabled())
+        .for_each(|&compression| {
+            let sink = Sink::default();
+            compress_writer(sink.clone(), compression)
+                .unwrap()
+                .write_all(text.as_bytes())
+                .unwrap();
+            let compressed = sink.0.take();
+            assert_eq!(Compression::detect(&compressed), compression);
+            let mut decompressed = String::new();
+            decompress_reader(Cursor::new(compressed))
+                .unwrap()
+                .read_to_string(&mut decompressed)
+                .unwrap();
+            assert_eq!(decompressed, text, "{:?}", compression);
+        });
+}
+
+/// Gives out its parts one read at a time, like a pipe written to bit by bit, and fails if read past them.
+#[cfg(test)]
+struct Trickle(Vec<&'static [u8]>);
+
+#[cfg(test)]
+impl Read for Trickle {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        assert!(!self.0.is_empty(), "read past what's been written so far");
+        let part = self.0.remove(0);
+        let n = part.len().min(buf.len());
+        buf[..n].copy_from_slice(&part[..n]);
+        if n < part.len() {
+            self.0.insert(0, &part[n..]);
+        }
+        Ok(n)
+    }
+}
+
+#[test]
+fn test_decompress_reader_doesnt_wait() {
+    let mut line = [0; 3];
+    decompress_reader(Trickle(vec![b"hi\n"])).unwrap().read_exact(&mut line).unwrap();
+    assert_eq!(&line, b"hi\n");
+    // a header that arrives in pieces is still put together
+    if Compression::Gzip.enabled() {
+        let sink = Sink::default();
+        compress_writer(sink.clone(), Compression::Gzip).unwrap().write_all(b"hi\n").unwrap();
+        let compressed: &'static [u8] = Box::leak(sink.0.take().into_boxed_slice());
+        let mut reader = decompress_reader(Trickle(vec![&compressed[..1], &compressed[1..], b""])).unwrap();
+        let mut text = String::new();
+        reader.read_to_string(&mut text).unwrap();
+        assert_eq!(text, "hi\n");
+    }
+}
@@ -0,0 +1,205 @@
+use crate::find_arg;
+use crate::parse_args::Args;
+use miniserde::{json, Deserialize};
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+// where a tool! argument can come from, highest precedence first:
+// json-rpc request (with --serve) > command line > TOOLNAME_ARGNAME environment variable > --config file > default
+
+pub type ConfigValues = HashMap<String, String>;
+
+static CONFIG: OnceLock<Result<Option<(String, ConfigValues)>, String>> = OnceLock::new();
+
+pub fn binary_name() -> String {
+    std::env::args()
+        .next()
+        .and_then(|exe| {
+            std::path::Path::new(&exe)
+                .file_stem()
+                .map(|s| s.to_string_lossy().to_string())
+        })
+        .unwrap_or_else(|| "tool".to_string())
+}
+
+/// The uppercased name of the running binary, used as the prefix for environment variables.
+pub fn tool_name() -> String {
+    binary_name()
+        .chars()
+        .map(|c| match c.is_ascii_alphanumeric() {
+            true => c.to_ascii_uppercase(),
+            false => '_',
+        })
+        .collect()
+}
+
+pub fn env_var_name(tool: &str, argname: &str) -> String {
+    format!("{}_{}", tool, argname.trim_start_matches('-').to_uppercase())
+}
+
+/// Flattens a config file into argname -> raw value, where each value is parsed the same way as a command-line value would be.
+/// .json files must be a single object; .toml and .yaml/.yml files are read as flat `key = value` / `key: value` lines,
+/// and `[section]` headers or indented mappings are errors rather than being merged in.
+pub fn parse_config(path: &str, contents: &str) -> Result<ConfigValues, String> {
+    let extension = path.rsplit('.').next().unwrap_or_else(|| "").to_lowercase();
+    match extension.as_str() {
+        "json" => match json::from_str::<json::Value>(contents) {
+            Ok(json::Value::Object(obj)) => Ok(obj
+                .iter()
+                .map(|(k, v)| (k.trim_start_matches('-').to_string(), json::to_string(v)))
+                .collect()),
+            Ok(_) => Err(format!("config file {} needs to be a json object", path)),
+            Err(_) => Err(format!("config file {} isn't valid json", path)),
+        },
+        "toml" => parse_flat(contents, '='),
+        "yaml" | "yml" => parse_flat(contents, ':'),
+        _ => Err(format!(
+            "config file {} needs to end in .json, .toml, .yaml or .yml",
+            path
+        )),
+    }
+}
+
+fn parse_flat(contents: &str, sep: char) -> Result<ConfigValues, String> {
+    contents
+        .lines()
+        .filter(|line| !(line.trim().is_empty() || line.trim().starts_with('#') || line.trim() == "---"))
+        .map(|line| {
+            // tables and nested mappings would otherwise land in the flat namespace alongside everything else
+            if line.trim().starts_with('[') || (sep == ':' && line.starts_with(char::is_whitespace)) {
+                return Err(format!("config line {:?} is inside a section, but config files have to be flat", line.trim()));
+            }
+            let line = line.trim();
+            line.split_once(sep)
+                .map(|(k, v)| {
+                    let v = v.trim();
+                    let v = match v.len() > 1 && v.starts_with('\'') && v.ends_with('\'') {
+                        true => format!("{:?}", &v[1..v.len() - 1]),
+                        false => v.to_string(),
+                    };
+                    (k.trim().trim_matches('"').trim_start_matches('-').to_string(), v)
+                })
+                .ok_or_else(|| format!("couldn't read config line {:?}", line))
+        })
+        .collect()
+}
+
+fn load_config() -> &'static Result<Option<(String, ConfigValues)>, String> {
+    CONFIG.get_or_init(|| {
+        let path = match Args::new(std::env::args()).get_value_of("--config") {
+            Some(path) => path.to_string(),
+            None => return Ok(None),
+        };
+        let contents = std::fs::read_to_string(&path)
+            .map_err(|e| format!("config file {} couldn't be read: {}", path, e))?;
+        parse_config(&path, &contents).map(|values| Some((path, values)))
+    })
+}
+
+fn raw_cli_arg(argname: &str) -> Option<String> {
+    let args: Vec<String> = std::env::args().collect();
+    args.iter()
+        .position(|a| a == argname)
+        .map(|p| {
+            args.iter()
+                .skip(p + 1)
+                .take_while(|el| !el.starts_with("-"))
+                .cloned()
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .filter(|s| !s.is_empty())
+        .or_else(|| match argname.len() {
+            2 => None,
+            _ => argname
+                .chars()
+                .find(|c| c != &'-')
+                .and_then(|c| raw_cli_arg(&format!("-{}", c))),
+        })
+}
+
+fn bad_value<T>(argname: &str, raw: &str, layer: &str) -> String {
+    format!(
+        "{} got {:?} from {}, which couldn't be parsed as {}",
+        argname,
+        raw,
+        layer,
+        std::any::type_name::<T>()
+    )
+}
+
+/// Like find_arg, but falls back to the environment and then the --config file, and complains about
+/// (rather than silently skipping) a value that was supplied but can't be parsed.
+pub fn find_layered_arg<T: Deserialize>(argname: &str) -> Result<Option<T>, String> {
+    if let Some(raw) = crate::server::request_arg(argname) {
+        return Args::parse_arg::<T>(&raw)
+            .map(Some)
+            .ok_or_else(|| bad_value::<T>(argname, &raw, "the json-rpc request"));
+    }
+    if let Some(raw) = raw_cli_arg(argname) {
+        return find_arg::<T>(argname)
+            .map(Some)
+            .ok_or_else(|| bad_value::<T>(argname, &raw, "the command line"));
+    }
+    let env_name = env_var_name(&tool_name(), argname);
+    if let Ok(raw) = std::env::var(&env_name) {
+        return Args::parse_arg::<T>(&raw)
+            .map(Some)
+            .ok_or_else(|| bad_value::<T>(argname, &raw, &format!("environment variable {}", env_name)));
+    }
+    match load_config() {
+        Err(e) => Err(e.clone()),
+        Ok(None) => Ok(None),
+        Ok(Some((path, values))) => match values.get(argname.trim_start_matches('-')) {
+            None => Ok(None),
+            Some(raw) => Args::parse_arg::<T>(raw)
+                .map(Some)
+                .ok_or_else(|| bad_value::<T>(argname, raw, &format!("config file {}", path))),
+        },
+    }
+}
+
+/// Flags are true if they're on the command line, or set to true in the environment or the --config file.
+pub fn find_layered_flag(flagname: &str) -> Result<bool, String> {
+    if crate::server::request_arg(flagname).is_some() {
+        return find_layered_arg::<bool>(flagname).map(|b| b.unwrap_or_else(|| false));
+    }
+    if crate::find_flag(flagname) {
+        return Ok(true);
+    }
+    find_layered_arg::<bool>(flagname).map(|b| b.unwrap_or_else(|| false))
+}
+
+pub fn layers_help() -> String {
+    format!(
+        "Arguments can also be set with {}_ARGNAME environment variables, or in a --config file (.json, .toml or .yaml).\nThe command line wins over the environment, which wins over the config file.\n",
+        tool_name()
+    )
+}
+
+#[test]
+fn test_parse_config() {
+    let values = parse_config("qc.json", r#"{"cutoff": 200, "--sep": "|", "k_top": 0.5}"#).unwrap();
+    assert_eq!(Args::parse_arg::<usize>(&values["cutoff"]), Some(200));
+    assert_eq!(Args::parse_arg::<String>(&values["sep"]), Some("|".to_string()));
+    assert_eq!(Args::parse_arg::<f64>(&values["k_top"]), Some(0.5));
+
+    let values = parse_config("qc.toml", "# comment\ncutoff = 200\nsep = '|'\nname = \"x\"\n").unwrap();
+    assert_eq!(Args::parse_arg::<usize>(&values["cutoff"]), Some(200));
+    assert_eq!(Args::parse_arg::<String>(&values["sep"]), Some("|".to_string()));
+    assert_eq!(Args::parse_arg::<String>(&values["name"]), Some("x".to_string()));
+
+    let values = parse_config("qc.yaml", "---\ncutoff: 200\norder: [2, 0, 1]\nlang: en\n").unwrap();
+    assert_eq!(Args::parse_arg::<Vec<usize>>(&values["order"]), Some(vec![2, 0, 1]));
+    assert_eq!(Args::parse_arg::<String>(&values["lang"]), Some("en".to_string()));
+
+    assert!(parse_config("qc.toml", "cutoff = 1\n[qc]\nsep = '|'\n").is_err());
+    assert!(parse_config("qc.yaml", "qc:\n  cutoff: 1\n").is_err());
+    assert!(parse_config("qc.json", "[1, 2]").is_err());
+    assert!(parse_config("qc.ini", "cutoff = 1").is_err());
+}
+
+#[test]
+fn test_env_var_name() {
+    assert_eq!(env_var_name("LANGFILTER", "--top_n"), "LANGFILTER_TOP_N");
+}
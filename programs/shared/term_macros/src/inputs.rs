@@ -0,0 +1,403 @@
+use crate::progress::Counted;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+// --input takes any number of files, globs and directories (read recursively), e.g.
+//   clean --input shards/*.gz 'more/**/*.txt' extra_dir/
+// and readin!/filter_in! read them one after the other, each decompressed as needed, instead of stdin.
+// --with_source prefixes every output line with `file:line\t`, the input line it came from.
+
+/// One input, opened only when it's about to be read, so hundreds of shards don't hold hundreds of files open.
+pub struct Source {
+    pub name: String,
+    path: Option<PathBuf>,
+    // so the last line of one file can't run into the first line of the next
+    end_with_newline: bool,
+}
+
+impl Source {
+    pub fn stdin() -> Source {
+        Source {
+            name: "-".to_string(),
+            path: None,
+            end_with_newline: false,
+        }
+    }
+
+    pub fn open(&self) -> std::io::Result<Box<dyn Read + Send>> {
+        let reader = match &self.path {
+            Some(path) => crate::compression::decompress_reader(Counted {
+                inner: std::fs::File::open(path)?,
+            })?,
+            None => crate::compression::decompress_reader(Counted {
+                inner: std::io::stdin(),
+            })?,
+        };
+        match self.end_with_newline {
+            true => Ok(Box::new(EndWithNewline {
+                inner: reader,
+                last: None,
+                done: false,
+            })),
+            false => Ok(reader),
+        }
+    }
+
+    /// Size on disk, if it's a file rather than a pipe.
+    pub fn size(&self) -> Option<u64> {
+        let metadata = match &self.path {
+            Some(path) => std::fs::metadata(path).ok()?,
+            None => stdin_metadata()?,
+        };
+        match metadata.is_file() {
+            true => Some(metadata.len()),
+            false => None,
+        }
+    }
+
+    /// Like open, but names the file if it can't be read, as open! does.
+    pub fn open_or_panic(&self) -> Box<dyn Read + Send> {
+        self.open()
+            .unwrap_or_else(|e| panic!("Could not open {}: {}", self.name, e))
+    }
+}
+
+#[cfg(unix)]
+fn stdin_metadata() -> Option<std::fs::Metadata> {
+    use std::os::fd::AsFd;
+    let fd = std::io::stdin().as_fd().try_clone_to_owned().ok()?;
+    std::fs::File::from(fd).metadata().ok()
+}
+
+#[cfg(not(unix))]
+fn stdin_metadata() -> Option<std::fs::Metadata> {
+    None
+}
+
+struct EndWithNewline<R: Read> {
+    inner: R,
+    last: Option<u8>,
+    done: bool,
+}
+
+impl<R: Read> Read for EndWithNewline<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.done || buf.is_empty() {
+            return Ok(0);
+        }
+        let n = self.inner.read(buf)?;
+        if n > 0 {
+            self.last = Some(buf[n - 1]);
+            return Ok(n);
+        }
+        self.done = true;
+        match self.last {
+            Some(b'\n') | None => Ok(0),
+            Some(_) => {
+                buf[0] = b'\n';
+                Ok(1)
+            }
+        }
+    }
+}
+
+/// The values after every --input in `args`, so `--input a --input b` reads both.
+fn cli_inputs(args: &[String]) -> Option<Vec<String>> {
+    let mut given = false;
+    let mut patterns = vec![];
+    for (p, _) in args.iter().enumerate().filter(|(_, a)| *a == "--input") {
+        given = true;
+        patterns.extend(args.iter().skip(p + 1).take_while(|a| !a.starts_with("--")).cloned());
+    }
+    match given {
+        true => Some(patterns),
+        false => None,
+    }
+}
+
+fn raw_cli_inputs() -> Option<Vec<String>> {
+    cli_inputs(&std::env::args().collect::<Vec<_>>())
+}
+
+/// The --input values from an environment variable, split like PATH (on ':', or ';' on windows), so they can have spaces.
+fn split_env_inputs(raw: &str) -> Vec<String> {
+    std::env::split_paths(raw)
+        .map(|p| p.display().to_string())
+        .filter(|p| !p.is_empty())
+        .collect()
+}
+
+/// The --input values, from the command line (space-separated, as the shell passes them), a json-rpc request or
+/// config file (a list, or one pattern), or a TOOLNAME_INPUT environment variable (split like PATH).
+pub fn input_patterns() -> Result<Option<Vec<String>>, String> {
+    if let Some(patterns) = raw_cli_inputs() {
+        return Ok(Some(patterns));
+    }
+    let env_name = crate::config::env_var_name(&crate::config::tool_name(), "--input");
+    if let (None, Ok(raw)) = (crate::server::request_arg("--input"), std::env::var(&env_name)) {
+        return Ok(Some(split_env_inputs(&raw)));
+    }
+    match crate::config::find_layered_arg::<Vec<String>>("--input") {
+        Ok(patterns) => Ok(patterns),
+        Err(_) => crate::config::find_layered_arg::<String>("--input").map(|pattern| pattern.map(|p| vec![p])),
+    }
+}
+
+pub fn given() -> bool {
+    matches!(input_patterns(), Ok(Some(_)))
+}
+
+pub fn with_source() -> bool {
+    crate::config::find_layered_flag("--with_source").unwrap_or_else(|_| false)
+}
+
+fn walk(dir: &Path, files: &mut Vec<PathBuf>) -> Result<(), String> {
+    let mut entries = std::fs::read_dir(dir)
+        .and_then(|entries| entries.map(|e| e.map(|e| e.path())).collect::<std::io::Result<Vec<_>>>())
+        .map_err(|e| format!("couldn't read directory {}: {}", dir.display(), e))?;
+    entries.sort();
+    for path in entries {
+        match path.is_dir() {
+            true => walk(&path, files)?,
+            false => files.push(path),
+        }
+    }
+    Ok(())
+}
+
+fn push_path(path: PathBuf, files: &mut Vec<PathBuf>) -> Result<(), String> {
+    match path.is_dir() {
+        true => walk(&path, files),
+        false => {
+            files.push(path);
+            Ok(())
+        }
+    }
+}
+
+/// Expands the patterns into files, in the order given: globs sorted, directories walked in sorted order, "-" for stdin.
+/// A path that doesn't exist, or a glob that matches nothing, is an error rather than silently reading less.
+pub fn expand(patterns: &[String]) -> Result<Vec<Source>, String> {
+    let mut sources = vec![];
+    for pattern in patterns {
+        if pattern == "-" {
+            sources.push(Source::stdin());
+            continue;
+        }
+        let mut files = vec![];
+        if pattern.contains(['*', '?', '[']) {
+            let matches = glob::glob(pattern).map_err(|e| format!("--input {:?} isn't a valid glob: {}", pattern, e))?;
+            let mut paths = matches
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| format!("--input {:?}: {}", pattern, e))?;
+            if paths.is_empty() {
+                return Err(format!("--input {:?} didn't match any files", pattern));
+            }
+            paths.sort();
+            for path in paths {
+                push_path(path, &mut files)?;
+            }
+        } else {
+            let path = PathBuf::from(pattern);
+            if !path.exists() {
+                return Err(format!("--input {:?} doesn't exist", pattern));
+            }
+            push_path(path, &mut files)?;
+        }
+        sources.extend(files.into_iter().map(|path| Source {
+            name: path.display().to_string(),
+            path: Some(path),
+            end_with_newline: false,
+        }));
+    }
+    let followed = sources.len().saturating_sub(1);
+    sources.iter_mut().take(followed).for_each(|s| s.end_with_newline = true);
+    Ok(sources)
+}
+
+/// What readin!/filter_in! read: the --input files if there are any, otherwise stdin.
+pub fn sources() -> Result<Vec<Source>, String> {
+    let sources = match input_patterns()? {
+        Some(patterns) if !patterns.is_empty() => expand(&patterns)?,
+        Some(_) => return Err("--input needs at least one file, glob or directory".to_string()),
+        None => vec![Source::stdin()],
+    };
+    if crate::progress::enabled() {
+        // the eta is only worth showing if every input's size is known
+        if let Some(total) = sources.iter().map(|s| s.size()).sum::<Option<u64>>() {
+            crate::progress::add_total_bytes(total);
+        }
+    }
+    Ok(sources)
+}
+
+pub fn sources_or_exit() -> Vec<Source> {
+    sources().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1)
+    })
+}
+
+/// Writes what the closure produced for one input line, each output line prefixed with `name:line_number\t`.
+pub fn write_tagged(output: &mut impl Write, name: &str, line_number: usize, produced: &[u8]) -> std::io::Result<()> {
+    for line in produced.split_inclusive(|b| *b == b'\n') {
+        write!(output, "{}:{}\t", name, line_number)?;
+        output.write_all(line)?;
+        if !line.ends_with(b"\n") {
+            output.write_all(b"\n")?;
+        }
+    }
+    Ok(())
+}
+
+/// What readin! hands its closure as the writer: straight through to the output, or with --with_source,
+/// held until end_line knows which input line it all came from. Also counts the lines written for --progress.
+pub struct SourceWriter<W: Write> {
+    output: W,
+    with_source: bool,
+    produced: Vec<u8>,
+    counting: bool,
+    newlines: usize,
+    open_line: bool,
+}
+
+impl<W: Write> SourceWriter<W> {
+    pub fn new(output: W, with_source: bool) -> SourceWriter<W> {
+        SourceWriter {
+            output,
+            with_source,
+            produced: vec![],
+            counting: crate::progress::enabled(),
+            newlines: 0,
+            open_line: false,
+        }
+    }
+
+    pub fn end_line(&mut self, name: &str, line_number: usize, line_bytes: usize) -> std::io::Result<()> {
+        if self.counting {
+            crate::progress::line_done(line_bytes, self.newlines + self.open_line as usize);
+            self.newlines = 0;
+            self.open_line = false;
+        }
+        if !self.with_source {
+            return Ok(());
+        }
+        let result = write_tagged(&mut self.output, name, line_number, &self.produced);
+        self.produced.clear();
+        result
+    }
+}
+
+impl<W: Write> Write for SourceWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.counting && !buf.is_empty() {
+            self.newlines += buf.iter().filter(|b| **b == b'\n').count();
+            self.open_line = !buf.ends_with(b"\n");
+        }
+        match self.with_source {
+            true => {
+                self.produced.extend_from_slice(buf);
+                Ok(buf.len())
+            }
+            false => self.output.write_all(buf).map(|_| buf.len()),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.output.flush()
+    }
+}
+
+pub fn inputs_help() -> String {
+    "--input reads files, globs and directories (recursively) instead of stdin, and --with_source prefixes each output line with file:line and a tab.\nIn a config file --input can be a list; in the environment, separate the patterns as in PATH.\n".to_string()
+}
+
+#[test]
+fn test_expand() {
+    let dir = std::env::temp_dir().join(format!("term_macros_inputs_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(dir.join("nested")).unwrap();
+    ["b.txt", "a.txt", "c.gz", "nested/d.txt"]
+        .iter()
+        .for_each(|f| std::fs::write(dir.join(f), "x\n").unwrap());
+    let root = dir.display().to_string();
+    let names = |patterns: &[String]| {
+        expand(patterns)
+            .unwrap()
+            .into_iter()
+            .map(|s| s.name.trim_start_matches(&root).to_string())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(names(&[format!("{}/*.txt", root)]), vec!["/a.txt", "/b.txt"]);
+    assert_eq!(
+        names(&[format!("{}/c.gz", root), root.clone()]),
+        vec!["/c.gz", "/a.txt", "/b.txt", "/c.gz", "/nested/d.txt"]
+    );
+    assert_eq!(names(&[format!("{}/**/d.txt", root), "-".to_string()]), vec!["/nested/d.txt", "-"]);
+    assert!(expand(&[format!("{}/*.zst", root)]).is_err());
+    assert!(expand(&[format!("{}/missing.txt", root)]).is_err());
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_end_with_newline() {
+    let read = |text: &'static [u8]| {
+        let mut out = vec![];
+        EndWithNewline {
+            inner: text,
+            last: None,
+            done: false,
+        }
+        .read_to_end(&mut out)
+        .unwrap();
+        out
+    };
+    assert_eq!(read(b"x\ny"), b"x\ny\n");
+    assert_eq!(read(b"x\n"), b"x\n");
+    assert_eq!(read(b""), b"");
+}
+
+#[test]
+fn test_write_tagged() {
+    let mut output = vec![];
+    write_tagged(&mut output, "shard.gz", 7, b"one\ntwo").unwrap();
+    write_tagged(&mut output, "shard.gz", 8, b"").unwrap();
+    assert_eq!(output, b"shard.gz:7\tone\nshard.gz:7\ttwo\n");
+}
+
+#[test]
+fn test_source_writer() {
+    let mut writer = SourceWriter::new(vec![], true);
+    writer.write_all(b"first\nsec").unwrap();
+    writer.write_all(b"ond\n").unwrap();
+    writer.end_line("-", 3, 6).unwrap();
+    writer.end_line("-", 4, 6).unwrap();
+    assert_eq!(writer.output, b"-:3\tfirst\n-:3\tsecond\n");
+
+    let mut writer = SourceWriter::new(vec![], false);
+    writer.write_all(b"as is\n").unwrap();
+    writer.end_line("-", 1, 6).unwrap();
+    assert_eq!(writer.output, b"as is\n");
+}
+
+#[test]
+fn test_layered_inputs() {
+    #[cfg(unix)]
+    assert_eq!(split_env_inputs("my corpus/a.txt:b/*.gz:"), vec!["my corpus/a.txt", "b/*.gz"]);
+    let values = crate::config::parse_config("qc.json", r#"{"input": ["my corpus/a.txt", "b"]}"#).unwrap();
+    assert_eq!(
+        crate::parse_args::Args::parse_arg::<Vec<String>>(&values["input"]),
+        Some(vec!["my corpus/a.txt".to_string(), "b".to_string()])
+    );
+    let values = crate::config::parse_config("qc.toml", "input = \"my corpus/a.txt\"\n").unwrap();
+    assert_eq!(crate::parse_args::Args::parse_arg::<Vec<String>>(&values["input"]), None);
+    assert_eq!(crate::parse_args::Args::parse_arg::<String>(&values["input"]), Some("my corpus/a.txt".to_string()));
+}
+
+#[test]
+fn test_cli_inputs() {
+    let args = |line: &str| line.split(' ').map(str::to_string).collect::<Vec<_>>();
+    assert_eq!(cli_inputs(&args("dedup --input c.txt --no_punct --input a.txt b.txt")), Some(args("c.txt a.txt b.txt")));
+    assert_eq!(cli_inputs(&args("dedup --input")), Some(vec![]));
+    assert_eq!(cli_inputs(&args("dedup --no_punct")), None);
+}
@@ -5,25 +5,40 @@ pub use miniserde;
 use miniserde::*;
 use std::fmt::Debug;
 use std::time::SystemTime;
+pub mod completions;
+pub mod compression;
+pub mod config;
+pub mod inputs;
+pub mod parse_args;
+pub mod pipeline;
+pub mod progress;
+pub mod prompt;
+pub mod records;
+pub mod schema;
+pub mod server;
+pub mod spec;
+pub use completions::*;
+pub use config::*;
+pub use spec::*;
 //mod lib2;
 // TODO:
 //* feature-gate everything
-//* implementation that supports interactive prompts
-//* implementation that supports a json file as an untagged config argument
-//* impl for yaml files
-//* impl for json-rpc server?
-//* impl for outputting an openapi spec
+//* implementation that supports interactive prompts - DONE for missing required arguments (see prompt.rs)
+//* implementation that supports a json file as an untagged config argument - DONE, --config (see config.rs)
+//* impl for yaml files - DONE, flat key: value files only
+//* impl for json-rpc server? - DONE, --serve (see server.rs)
+//* impl for outputting an openapi spec - DONE as a json schema, --schema (see schema.rs)
 //* would ideally like it to be run as a wasm app, so no annoying binary crosscomp
 
 // TODO-META:
-//* instead of ambiently absorbing arguments from std::env, take them explicitly from the macro
+//* instead of ambiently absorbing arguments from std::env, take them explicitly from the macro - see args! in parse_args
 //* impl that allows for testing different scenarios
 //* ideally the "body" section would just be a function that the arguments get routed to?
 //* the line -> wtr closure seems generalisable to other formats quite easily. so it seems like it'd be a good idea to explicitly have "setup/config", followed by
 //* create a "mock" environment object.
 //* actually allow for pattern-matching against multiple possible input-states
 //* but provide a convenience option for the default case of just one script
-//* meta-parameters like --buffer-size
+//* meta-parameters like --buffer-size - see --threads and --chunk_lines on par_readin!/par_filter_in!
 //* find more recurring idioms in commandline apps, macroify them
 
 pub fn random(within_range: u128) -> u128 {
@@ -96,61 +111,133 @@ pub fn find_flag(flagname: &str) -> bool {
 macro_rules! tool {
     (args: $(- $identname:ident $(: $typeof:ty )? $( = $default:expr)?; $( ? $cond:expr )? $( => $lit:literal )? )+; body: $main_body:expr) => {
         use std::convert::TryInto;
-        let case1 = (|| {
-            if (find_flag("-h") || find_flag("-H") || find_flag("--help") || find_flag("help") || find_flag("-help") ) {
-                return Err("Here's some help!".to_string());
-            }
-            $(
-                let argflag = format!("--{}", stringify!($identname));
-                tool!(- $identname argflag $(: $typeof )? $( = $default)?; $( ? $cond )? $( => $lit )?);
-            )+;
-            if 1 != 1 {
-                return Err("The universe is broken".to_string());
-            }
+        let serving = term_macros::server::start_if_requested();
+        loop {
+            let case1 = (|| {
+                if (find_flag("-h") || find_flag("-H") || find_flag("--help") || find_flag("help") || find_flag("-help") ) {
+                    return Err("Here's some help!".to_string());
+                }
+                let arg_specs: &[ArgSpec] = &[
+                    $(
+                        ArgSpec {
+                            name: stringify!($identname),
+                            typ: None $(.or(Some(stringify!($typeof))))?,
+                            default: None $(.or(Some(stringify!($default))))?,
+                            cond: None $(.or(Some(stringify!($cond))))?,
+                            because: None $(.or(Some($lit)))?,
+                        },
+                    )+
+                ];
+                if let Some(generated) = generate_from_specs(arg_specs)? {
+                    print!("{}", generated);
+                    return Ok(());
+                }
+                $(
+                    let argflag = format!("--{}", stringify!($identname));
+                    tool!(- $identname argflag $(: $typeof )? $( = $default)?; $( ? $cond )? $( => $lit )?);
+                )+;
+                if 1 != 1 {
+                    return Err("The universe is broken".to_string());
+                }
+                term_macros::compression::output_compression()?;
 
-            ($main_body)();
+                term_macros::progress::start();
+                ($main_body)();
+                term_macros::progress::finish();
 
-            Ok(())
-        })();
+                Ok(())
+            })();
 
-        match case1 {
-            Err(e1) => {
-                use term_macros::owo_colors::OwoColorize;
-                eprintln!("{}", e1);
-                eprintln!("⚘⚘⚘ Help: ⚘⚘⚘\n");
-                $(
-                    eprintln!("--{} ⚘", stringify!($identname).magenta());
-                    $(
-                        eprintln!("  type: {}", stringify!($typeof).bold().italic().magenta());
-                    )?
-                    $(
-                        eprintln!("  default: {}", stringify!($default).cyan().bold());
-                    )?
-                    $(
-                        eprintln!("  fails if: {}", stringify!($cond).green());
-                    )?
+            match case1 {
+                Err(e1) => {
+                    use term_macros::owo_colors::OwoColorize;
+                    if serving && term_macros::server::fail_pending(&e1) {
+                        continue;
+                    }
+                    eprintln!("{}", e1);
+                    eprintln!("⚘⚘⚘ Help: ⚘⚘⚘\n");
+                    eprintln!("{}", layers_help());
+                    eprintln!("{}", completions_help());
+                    eprintln!("{}", term_macros::server::server_help());
+                    eprintln!("{}", term_macros::compression::compression_help());
+                    eprintln!("{}", term_macros::inputs::inputs_help());
+                    eprintln!("{}", term_macros::progress::progress_help());
+                    eprintln!("{}", term_macros::records::records_help());
                     $(
-                        eprintln!("      because: {}", $lit.green());
-                    )?
-                    eprintln!("");
-                )+;
-            },
-            Ok(_) => {
-                return;
-            }
-            _ => {}
+                        eprintln!("--{} ⚘", stringify!($identname).magenta());
+                        $(
+                            eprintln!("  type: {}", stringify!($typeof).bold().italic().magenta());
+                        )?
+                        $(
+                            eprintln!("  default: {}", stringify!($default).cyan().bold());
+                        )?
+                        $(
+                            eprintln!("  fails if: {}", stringify!($cond).green());
+                        )?
+                        $(
+                            eprintln!("      because: {}", $lit.green());
+                        )?
+                        eprintln!("");
+                    )+;
+                },
+                Ok(_) => {
+                    if serving && term_macros::server::take_rerun() {
+                        continue;
+                    }
+                    return;
+                }
+                _ => {}
+            };
+            break;
+        }
+    };
+    (@prompt $argname:ident $argflag:ident : $typ:ty; $(? $cond:expr)? $(=> $err:literal)?) => {{
+        let spec = ArgSpec {
+            name: stringify!($argname),
+            typ: Some(stringify!($typ)),
+            default: None,
+            cond: None $(.or(Some(stringify!($cond))))?,
+            because: None $(.or(Some($err)))?,
         };
+        loop {
+            let $argname = term_macros::prompt::prompt_arg::<$typ>(&spec)
+                .ok_or_else(|| format!("{} is a required argument", $argflag))?;
+            $(
+                if ($cond) {
+                    eprintln!("{}", spec.because.unwrap_or_else(|| stringify!($cond)));
+                    continue;
+                }
+            )?
+            break $argname;
+        }
+    }};
+    (- $argname:ident $argflag:ident : $typ:ty; ? $cond:expr => $err:literal) => {
+        let $argname = match find_layered_arg::<$typ>(&$argflag)? {
+            Some(value) => value,
+            None => tool!(@prompt $argname $argflag : $typ; ? $cond => $err),
+        };
+        tool!(? $cond => $err);
+    };
+    (- $argname:ident $argflag:ident : $typ:ty; ? $cond:expr) => {
+        let $argname = match find_layered_arg::<$typ>(&$argflag)? {
+            Some(value) => value,
+            None => tool!(@prompt $argname $argflag : $typ; ? $cond),
+        };
+        tool!(? $cond);
     };
     (- $argname:ident $argflag:ident : $typ:ty; $($remaining_tokens:tt)*) => {
-        let $argname = find_arg::<$typ>(&$argflag).ok_or_else(|| format!("{} is a required argument", $argflag))?;
+        let $argname = match find_layered_arg::<$typ>(&$argflag)? {
+            Some(value) => value,
+            None => tool!(@prompt $argname $argflag : $typ;),
+        };
         tool!($($remaining_tokens)*);
     };
     (- $argname:ident $argflag:ident : $typ:ty = $default:expr; $($remaining_tokens:tt)*) => {
-        let $argname = find_arg::<$typ>(&$argflag).unwrap_or_else(|| $default);
+        let $argname = find_layered_arg::<$typ>(&$argflag)?.unwrap_or_else(|| $default);
         tool!($($remaining_tokens)*);
     };
     (- $argname:ident $argflag:ident; $($remaining_tokens:tt)*) => {
-        let $argname = find_flag(&$argflag);
+        let $argname = find_layered_flag(&$argflag)?;
         tool!($($remaining_tokens)*);
     };
     (? $evalfn:expr => $err:literal) => {
@@ -209,14 +296,24 @@ macro_rules! filter_in {
     ($closure:expr) => {
         use linereader::LineReader;
         use std::io::prelude::*;
+        if term_macros::server::serving() {
+            term_macros::server::serve(|lines: &[String]| {
+                lines
+                    .iter()
+                    .filter(|l| ($closure)(format!("{}\n", l).as_bytes()))
+                    .cloned()
+                    .collect()
+            });
+            return;
+        }
         let sync_mode = find_arg::<bool>("--sync_mode").unwrap_or_else(|| false);
-        if sync_mode {
-            let stdin = std::io::stdin();
-            let mut lock = stdin.lock();
+        if sync_mode && !term_macros::inputs::given() {
+            let mut lock = std::io::BufReader::new(term_macros::compression::stdin());
+            let mut writer = term_macros::compression::stdout();
             let mut s = String::new();
             while let Ok(_) = lock.read_line(&mut s) {
                 if ($closure)(s.as_bytes()) {
-                    println!("{}", s);
+                    let _ = writeln!(writer, "{}", s);
                 }
             };
             return;
@@ -224,19 +321,43 @@ macro_rules! filter_in {
 
 
 
-        let mut reader = LineReader::new(std::io::stdin());
-        let mut writer = std::io::BufWriter::new(std::io::stdout());
-
-        let _ = reader.for_each(|line| {
-            if ($closure)(line) {
-                let res = writer.write_all(line);
-                //let _ = writer.write_all(b"\n");
-                if res.is_err() {
-                    return Ok(false);
+        let with_source = term_macros::inputs::with_source();
+        let mut writer = std::io::BufWriter::new(term_macros::compression::stdout());
+
+        term_macros::progress::start();
+        for source in term_macros::inputs::sources_or_exit() {
+            let mut reader = LineReader::new(source.open_or_panic());
+            let mut line_number = 0;
+            let mut stopped = false;
+            let read = reader.for_each(|line| {
+                line_number += 1;
+                let keep = ($closure)(line);
+                term_macros::progress::line_done(line.len(), keep as usize);
+                if keep {
+                    let res = match with_source {
+                        true => term_macros::inputs::write_tagged(&mut writer, &source.name, line_number, line),
+                        false => writer.write_all(line),
+                    };
+                    //let _ = writer.write_all(b"\n");
+                    if res.is_err() {
+                        stopped = true;
+                        return Ok(false);
+                    }
                 }
+                Ok(true)
+            });
+            // a truncated or corrupt input (say a cut-off .gz) ends the run there, rather than looking like it all went
+            if let Err(e) = read {
+                let _ = writer.flush();
+                eprintln!("Could not read {}: {}", source.name, e);
+                std::process::exit(1);
             }
-            Ok(true)
-        });
+            if stopped {
+                break;
+            }
+        }
+        let _ = writer.flush();
+        term_macros::progress::finish();
     };
 }
 
@@ -246,14 +367,22 @@ macro_rules! readin {
         use linereader::LineReader;
         use std::io::prelude::*;
 
+        if term_macros::server::serving() {
+            let server_buf = term_macros::server::SharedBuf::default();
+            let mut $writer = server_buf.clone();
+            term_macros::server::serve(|lines: &[String]| {
+                lines.iter().for_each(|l| ($closure)(format!("{}\n", l).as_bytes()));
+                server_buf.take_lines()
+            });
+            return;
+        }
+
         let sync_mode = find_arg::<bool>("--sync_mode").unwrap_or_else(|| false);
 
-        if sync_mode {
-            let stdin = std::io::stdin();
-            let mut lock = stdin.lock();
+        if sync_mode && !term_macros::inputs::given() {
+            let mut lock = std::io::BufReader::new(term_macros::compression::stdin());
             let mut s = String::new();
-            let stdout = std::io::stdout();
-            let mut $writer = stdout.lock();
+            let mut $writer = term_macros::compression::stdout();
             while let Ok(_) = lock.read_line(&mut s) {
                 ($closure)(s.as_bytes());
                 let _ = $writer.flush();
@@ -261,13 +390,132 @@ macro_rules! readin {
             return;
         }
 
-        let mut reader = LineReader::new(std::io::stdin());
-        let mut $writer = std::io::BufWriter::new(std::io::stdout());
+        let mut $writer = term_macros::inputs::SourceWriter::new(
+            std::io::BufWriter::new(term_macros::compression::stdout()),
+            term_macros::inputs::with_source(),
+        );
 
-        let _ = reader.for_each(|line| {
-            ($closure)(line);
-            Ok(true)
-        });
+        term_macros::progress::start();
+        for source in term_macros::inputs::sources_or_exit() {
+            let mut reader = LineReader::new(source.open_or_panic());
+            let mut line_number = 0;
+            let mut stopped = false;
+            let read = reader.for_each(|line| {
+                line_number += 1;
+                ($closure)(line);
+                if $writer.end_line(&source.name, line_number, line.len()).is_err() {
+                    stopped = true;
+                    return Ok(false);
+                }
+                Ok(true)
+            });
+            if let Err(e) = read {
+                let _ = $writer.flush();
+                eprintln!("Could not read {}: {}", source.name, e);
+                std::process::exit(1);
+            }
+            if stopped {
+                break;
+            }
+        }
+        let _ = $writer.flush();
+        term_macros::progress::finish();
+    };
+}
+
+/// Same closure shape as readin!, but spread over --threads threads (default: all of them) in chunks of
+/// --chunk_lines lines, with the output kept in input order. The closure runs concurrently, so it can only
+/// read from whatever it captures; $writer is a per-chunk buffer rather than stdout.
+#[macro_export]
+macro_rules! par_readin {
+    ($writer:ident, $closure:expr) => {
+        if term_macros::server::serving() || find_arg::<bool>("--sync_mode").unwrap_or_else(|| false) {
+            term_macros::readin!($writer, $closure);
+            return;
+        }
+        let threads = find_arg::<usize>("--threads").unwrap_or_else(|| term_macros::pipeline::default_threads());
+        let chunk_lines = find_arg::<usize>("--chunk_lines").unwrap_or_else(|| 4096);
+        term_macros::progress::start();
+        let result = term_macros::pipeline::par_sources(
+            term_macros::inputs::sources_or_exit(),
+            term_macros::compression::stdout(),
+            threads,
+            chunk_lines,
+            term_macros::inputs::with_source(),
+            |$writer: &mut Vec<u8>, line: &[u8]| {
+                use std::io::prelude::*;
+                ($closure)(line);
+            },
+        );
+        term_macros::progress::finish();
+        // an input that couldn't be opened or read to the end; a closed stdout just stops the run, as in filter_in!
+        match result {
+            Err(e) if e.kind() != std::io::ErrorKind::BrokenPipe => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+            _ => {}
+        }
+    };
+}
+
+/// filter_in!, spread over threads the same way as par_readin!.
+#[macro_export]
+macro_rules! par_filter_in {
+    ($closure:expr) => {
+        if term_macros::server::serving() || find_arg::<bool>("--sync_mode").unwrap_or_else(|| false) {
+            term_macros::filter_in!($closure);
+            return;
+        }
+        let threads = find_arg::<usize>("--threads").unwrap_or_else(|| term_macros::pipeline::default_threads());
+        let chunk_lines = find_arg::<usize>("--chunk_lines").unwrap_or_else(|| 4096);
+        term_macros::progress::start();
+        let result = term_macros::pipeline::par_sources(
+            term_macros::inputs::sources_or_exit(),
+            term_macros::compression::stdout(),
+            threads,
+            chunk_lines,
+            term_macros::inputs::with_source(),
+            |out: &mut Vec<u8>, line: &[u8]| {
+                if ($closure)(line) {
+                    out.extend_from_slice(line);
+                }
+            },
+        );
+        term_macros::progress::finish();
+        // an input that couldn't be opened or read to the end; a closed stdout just stops the run, as in filter_in!
+        match result {
+            Err(e) if e.kind() != std::io::ErrorKind::BrokenPipe => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+            _ => {}
+        }
+    };
+}
+
+/// filter_in!, but the closure gets the --fields of each tsv/csv/jsonl record (see records.rs) and the records it
+/// keeps are written out as they were read. $default_fields is the ColumnSpec used when --fields isn't given, e.g. "0..".
+#[macro_export]
+macro_rules! filter_records {
+    ($default_fields:expr, $closure:expr) => {
+        let record_options = term_macros::records::RecordOptions::from_args_or_exit($default_fields);
+        term_macros::records::filter_records(&record_options, $closure);
+    };
+}
+
+/// readin!, but over records: the closure gets the --fields of each record and $writer is a RecordWriter,
+/// so whatever it writes comes out in the same format it came in.
+#[macro_export]
+macro_rules! readin_records {
+    ($default_fields:expr, $writer:ident, $closure:expr) => {
+        let record_options = term_macros::records::RecordOptions::from_args_or_exit($default_fields);
+        term_macros::records::map_records(
+            &record_options,
+            |$writer: &mut term_macros::records::RecordWriter<Vec<u8>>, fields: &[&str]| {
+                ($closure)(fields);
+            },
+        );
     };
 }
 
@@ -286,20 +534,14 @@ macro_rules! then {
 #[macro_export]
 macro_rules! mmap {
     ($filename:ident) => {
-        unsafe {
-            use memmap::MmapOptions;
-            std::fs::File::open(&$filename).ok().and_then(|m| MmapOptions::new().map(&m).ok()).unwrap()
-        }
+        term_macros::compression::map_file(&$filename).unwrap()
     }
 }
 
 #[macro_export]
 macro_rules! mmap_str {
     ($filename:ident) => {
-        let mmap_data = unsafe {
-            use memmap::MmapOptions;
-            std::fs::File::open(&$filename).ok().and_then(|m| MmapOptions::new().map(&m).ok()).unwrap()
-        };
+        let mmap_data = term_macros::compression::map_file(&$filename).unwrap();
         std::str::from_utf8(&mmap_data[..]).unwrap()
     }
 }
@@ -415,7 +657,7 @@ macro_rules! open {
     ($filename:expr) => {
         {
             let mut data = String::new();
-            std::fs::File::open($filename)
+            term_macros::compression::open($filename)
                 .expect(&format!("Could not open {}", $filename))
                 .read_to_string(&mut data)
                 .expect(&format!("Could not read {} to string", $filename));
@@ -424,3 +666,52 @@ macro_rules! open {
     }
 }
 
+
+#[cfg(test)]
+extern crate self as term_macros;
+
+/// What a tool's body would be, for test_par_macros to run in a copy of the test binary.
+#[cfg(test)]
+fn par_echo() {
+    par_filter_in!(|_line: &[u8]| true);
+}
+
+#[test]
+fn test_par_macros() {
+    if std::env::var("TERM_MACROS_PAR_ECHO").is_ok() {
+        return par_echo();
+    }
+    // the test binary, running only this test, with the tool's arguments after the --
+    let run = |args: &[&str]| {
+        std::process::Command::new(std::env::current_exe().unwrap())
+            .args(["test_par_macros", "--exact", "--quiet", "--nocapture", "--"])
+            .args(args)
+            .env("TERM_MACROS_PAR_ECHO", "1")
+            .output()
+            .unwrap()
+    };
+    let dir = std::env::temp_dir().join(format!("term_macros_par_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("a.txt");
+    std::fs::write(&path, "alpha\nbeta\n").unwrap();
+    let path = path.display().to_string();
+    for args in [vec!["--input", &path], vec!["--sync_mode", "true", "--input", &path]] {
+        let output = run(&args);
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(output.status.success(), "{:?}", output);
+        assert_eq!(stdout.lines().filter(|l| *l == "alpha").count(), 1, "{:?}: {}", args, stdout);
+    }
+    if cfg!(feature = "gz") {
+        let compressed = dir.join("cut.gz");
+        let text: String = (0..100000).map(|i| format!("line {}\n", i)).collect();
+        let mut gz = crate::compression::compress_writer(std::fs::File::create(&compressed).unwrap(), crate::compression::Compression::Gzip).unwrap();
+        gz.write_all(text.as_bytes()).unwrap();
+        drop(gz);
+        let bytes = std::fs::read(&compressed).unwrap();
+        std::fs::write(&compressed, &bytes[..bytes.len() / 2]).unwrap();
+        let output = run(&["--input", &compressed.display().to_string()]);
+        assert!(!output.status.success());
+        assert!(String::from_utf8_lossy(&output.stderr).contains("Could not read"), "{:?}", output);
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -22,10 +22,11 @@ impl Args {
                     .map(|a| Arc::from(a.as_str()))
             })
             .or_else(|| {
+                let prefix = format!("{}=", flag);
                 self.0
                     .iter()
-                    .find(|a| a.starts_with(flag))
-                    .map(|a| a.split("=").nth(1).map(Arc::from))
+                    .find(|a| a.starts_with(&prefix))
+                    .map(|a| a.split_once("=").map(|(_, value)| Arc::from(value)))
             })
             .flatten()
     }
@@ -39,13 +40,29 @@ impl Args {
             .ok()
             .or_else(|| json::from_str(&format!("{:#?}", arg)).ok())
     }
+
+    /// Ok(None) if the flag wasn't passed at all, Err if it was passed but couldn't be parsed into T.
+    pub fn fetch<T: Deserialize>(&self, flag: &str) -> Result<Option<T>, String> {
+        match self.get_value_of(flag) {
+            None => Ok(None),
+            Some(raw) => Args::parse_arg(&raw)
+                .map(Some)
+                .ok_or_else(|| format!("{} couldn't be parsed from {:?}", flag, raw)),
+        }
+    }
+
+    pub fn wants_help(&self) -> bool {
+        ["-h", "-H", "--help", "help", "-help"]
+            .iter()
+            .any(|flag| self.check_flag(flag))
+    }
 }
 
 // - parse with eq - DONE
 // - parse with space as sep - DONE
 // - parse boolean flag - DONE
-// - parse into type T
-// - macro creating a custom struct which gets the value of specified arguments
+// - parse into type T - DONE
+// - macro creating a custom struct which gets the value of specified arguments - DONE, see args!
 // - generalised type that can hold useful information about defaults, errors, expected types, etc.
 // so in this case we would want an arc-wrapped series of validation functions
 
@@ -103,8 +120,8 @@ impl ErrAccum {
 
     pub fn is_good(&self) -> bool {
         match self {
-            ErrAccum::Good => false,
-            ErrAccum::Bad(_) => true,
+            ErrAccum::Good => true,
+            ErrAccum::Bad(_) => false,
         }
     }
 
@@ -113,12 +130,160 @@ impl ErrAccum {
     }
 }
 
+impl std::fmt::Display for ErrAccum {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ErrAccum::Good => Ok(()),
+            ErrAccum::Bad(errs) => errs.iter().try_for_each(|e| writeln!(f, "{:?}", e)),
+        }
+    }
+}
+
 pub type ValidatorFn<T> = Arc<dyn Fn(&T) -> bool + Send + Sync>;
 
+#[allow(dead_code)]
 pub struct Validator<E: std::error::Error + Send + Sync> {
     err: Box<dyn Fn() -> E>,
 }
 
+/// Same argument grammar as tool!, but produces a plain struct that's parsed once from an explicit
+/// Args rather than re-scanning std::env for every argument, so it can be driven from tests.
+/// Missing and unparseable arguments are all reported together; the `? cond => "because"`
+/// validators run once everything has a value, and are also reported together.
+///
+/// ```ignore
+/// args! {
+///     pub struct LengthArgs {
+///         - min_words: usize = 0;
+///         - max_words: usize;
+///             ? max_words == 0
+///             => "max_words can't be zero"
+///         - max_chars: usize = max_words * 15;
+///         - lowercase;
+///     }
+/// }
+/// let parsed = LengthArgs::from_args(&Args::new(["--max_words", "20"]));
+/// ```
+#[macro_export]
+macro_rules! args {
+    (@ty $typ:ty) => { $typ };
+    (@ty) => { bool };
+
+    (@fetch $errs:ident, $args:ident, $field:ident : $typ:ty) => {{
+        let flag = concat!("--", stringify!($field));
+        match $args.fetch::<$typ>(flag) {
+            Ok(Some(value)) => Some(value),
+            Ok(None) => {
+                $errs = $errs.and(|| Err(format!("{} is a required argument", flag)));
+                None
+            }
+            Err(e) => {
+                $errs = $errs.and(|| Err(e));
+                None
+            }
+        }
+    }};
+    (@fetch $errs:ident, $args:ident, $field:ident : $typ:ty = $default:expr) => {{
+        match $args.fetch::<$typ>(concat!("--", stringify!($field))) {
+            Ok(value) => value,
+            Err(e) => {
+                $errs = $errs.and(|| Err(e));
+                None
+            }
+        }
+    }};
+    (@fetch $errs:ident, $args:ident, $field:ident) => {
+        $args.check_flag(concat!("--", stringify!($field)))
+    };
+
+    (@resolve $field:ident : $typ:ty) => { $field.unwrap() };
+    (@resolve $field:ident : $typ:ty = $default:expr) => { $field.unwrap_or_else(|| $default) };
+    (@resolve $field:ident) => { $field };
+
+    (@check $errs:ident, ? $cond:expr => $lit:literal) => {
+        $errs = $errs.and(|| if $cond { Err($lit.to_string()) } else { Ok(()) });
+    };
+    (@check $errs:ident, ? $cond:expr) => {
+        $errs = $errs.and(|| if $cond { Err(stringify!($cond).to_string()) } else { Ok(()) });
+    };
+    (@check $errs:ident, => $lit:literal) => {
+        compile_error!(concat!("`=> ", stringify!($lit), "` explains a `? condition`, so it needs one before it"));
+    };
+    (@check $errs:ident,) => {};
+
+    ($(#[$attr:meta])* $vis:vis struct $name:ident {
+        $(- $field:ident $(: $typeof:ty )? $( = $default:expr)?; $( ? $cond:expr )? $( => $lit:literal )? )+
+    }) => {
+        $(#[$attr])*
+        $vis struct $name {
+            $(pub $field: $crate::args!(@ty $($typeof)?)),+
+        }
+
+        #[allow(dead_code)]
+        impl $name {
+            pub fn from_args(args: &$crate::parse_args::Args) -> Result<Self, $crate::parse_args::ErrAccum> {
+                let mut errs = $crate::parse_args::ErrAccum::new();
+                $(
+                    let $field = $crate::args!(@fetch errs, args, $field $(: $typeof)? $(= $default)?);
+                )+
+                if errs.is_bad() {
+                    return Err(errs);
+                }
+                $(
+                    let $field: $crate::args!(@ty $($typeof)?) = $crate::args!(@resolve $field $(: $typeof)? $(= $default)?);
+                )+
+                $(
+                    $crate::args!(@check errs, $(? $cond)? $(=> $lit)?);
+                )+
+                if errs.is_bad() {
+                    return Err(errs);
+                }
+                Ok($name { $($field),+ })
+            }
+
+            /// Parses std::env::args, printing the errors and help screen to stderr and exiting if that fails.
+            pub fn from_env() -> Self {
+                let args = $crate::parse_args::Args::new(std::env::args().skip(1));
+                if args.wants_help() {
+                    eprintln!("Here's some help!");
+                    eprintln!("{}", Self::help());
+                    std::process::exit(0);
+                }
+                match Self::from_args(&args) {
+                    Ok(parsed) => parsed,
+                    Err(errs) => {
+                        eprint!("{}", errs);
+                        eprintln!("{}", Self::help());
+                        std::process::exit(1);
+                    }
+                }
+            }
+
+            pub fn help() -> String {
+                use $crate::owo_colors::OwoColorize;
+                let mut help = String::from("⚘⚘⚘ Help: ⚘⚘⚘\n\n");
+                $(
+                    help.push_str(&format!("--{} ⚘\n", stringify!($field).magenta()));
+                    $(
+                        help.push_str(&format!("  type: {}\n", stringify!($typeof).bold().italic().magenta()));
+                    )?
+                    $(
+                        help.push_str(&format!("  default: {}\n", stringify!($default).cyan().bold()));
+                    )?
+                    $(
+                        help.push_str(&format!("  fails if: {}\n", stringify!($cond).green()));
+                    )?
+                    $(
+                        help.push_str(&format!("      because: {}\n", $lit.green()));
+                    )?
+                    help.push('\n');
+                )+
+                help
+            }
+        }
+    };
+}
+
 #[test]
 fn test_err_accum() {
     let num = 17;
@@ -142,3 +307,45 @@ fn test_1() {
     assert_eq!(parsed.get_value_of("--arg2"), Some(Arc::from("off")));
     assert_eq!(parsed.check_flag("-flag"), true);
 }
+
+#[cfg(test)]
+args! {
+    #[derive(Debug)]
+    struct TestArgs {
+        - min_words: usize = 0;
+        - max_words: usize;
+            ? max_words == 0
+            => "max_words can't be zero"
+        - max_chars: usize = max_words * 15;
+        - sep: String = "\t".to_string();
+        - lowercase;
+    }
+}
+
+#[test]
+fn test_args_struct() {
+    let parsed = TestArgs::from_args(&Args::new(["--max_words", "20", "--lowercase"])).unwrap();
+    assert_eq!(parsed.min_words, 0);
+    assert_eq!(parsed.max_words, 20);
+    assert_eq!(parsed.max_chars, 300);
+    assert_eq!(parsed.sep, "\t");
+    assert!(parsed.lowercase);
+
+    let parsed = TestArgs::from_args(&Args::new(["--max_words=2", "--max_chars=7", "--sep", "|"])).unwrap();
+    assert_eq!(parsed.max_chars, 7);
+    assert_eq!(parsed.sep, "|");
+    assert!(!parsed.lowercase);
+    assert!(TestArgs::help().contains("max_words can't be zero"));
+}
+
+#[test]
+fn test_args_struct_errors() {
+    match TestArgs::from_args(&Args::new(["--min_words", "lots"])) {
+        Err(ErrAccum::Bad(errs)) => assert_eq!(errs.len(), 2),
+        other => panic!("expected two errors, got {:?}", other),
+    }
+    match TestArgs::from_args(&Args::new(["--max_words", "0"])) {
+        Err(ErrAccum::Bad(errs)) => assert_eq!(errs.len(), 1),
+        other => panic!("expected one error, got {:?}", other),
+    }
+}
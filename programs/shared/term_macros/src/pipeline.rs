@@ -0,0 +1,277 @@
+use crate::inputs::{write_tagged, Source};
+use crate::progress;
+use std::collections::BTreeMap;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::sync::mpsc::sync_channel;
+use std::sync::{Arc, Mutex};
+
+// reader thread -> chunks of chunk_lines lines (of one input at a time) -> worker threads -> reordered by sequence number -> output.
+// every chunk needs a slot before it's read and only gives it back once it's been written out, so at most
+// threads * SLOTS_PER_THREAD chunks are ever held in memory, however far ahead the fast workers get.
+// if process panics on a chunk, its worker sends that back in the chunk's place, so the writer stops there and
+// hangs up on everything else instead of waiting forever for a chunk that's never coming.
+
+const SLOTS_PER_THREAD: usize = 4;
+
+pub fn default_threads() -> usize {
+    std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or_else(|_| 4)
+}
+
+/// Runs process over every line of input (newline included, like LineReader) on `threads` threads,
+/// writing whatever each call puts in its buffer to output in the original line order.
+pub fn par_lines<R, W, F>(
+    input: R,
+    output: W,
+    threads: usize,
+    chunk_lines: usize,
+    process: F,
+) -> std::io::Result<()>
+where
+    R: Read + Send,
+    W: Write,
+    F: Fn(&mut Vec<u8>, &[u8]) + Sync,
+{
+    let input: Box<dyn Read + Send + '_> = Box::new(input);
+    par_readers(std::iter::once(("-".to_string(), Ok(input))), output, threads, chunk_lines, false, process)
+}
+
+/// par_lines over each source in turn. With `with_source`, everything process outputs for a line
+/// is prefixed with the source's name and the line's number, as in write_tagged.
+pub fn par_sources<W, F>(
+    sources: Vec<Source>,
+    output: W,
+    threads: usize,
+    chunk_lines: usize,
+    with_source: bool,
+    process: F,
+) -> std::io::Result<()>
+where
+    W: Write,
+    F: Fn(&mut Vec<u8>, &[u8]) + Sync,
+{
+    // opened on the reader thread as it gets to them; one that can't be opened stops the run there, and its error is
+    // what par_sources returns
+    let inputs = sources.into_iter().map(|source| {
+        let reader = source
+            .open()
+            .map_err(|e| std::io::Error::new(e.kind(), format!("Could not open {}: {}", source.name, e)));
+        (source.name, reader)
+    });
+    par_readers(inputs, output, threads, chunk_lines, with_source, process)
+}
+
+struct Chunk {
+    seq: usize,
+    source: Arc<str>,
+    first_line: usize,
+    data: Vec<u8>,
+}
+
+fn par_readers<'a, I, W, F>(
+    inputs: I,
+    mut output: W,
+    threads: usize,
+    chunk_lines: usize,
+    with_source: bool,
+    process: F,
+) -> std::io::Result<()>
+where
+    I: Iterator<Item = (String, std::io::Result<Box<dyn Read + Send + 'a>>)> + Send,
+    W: Write,
+    F: Fn(&mut Vec<u8>, &[u8]) + Sync,
+{
+    let threads = threads.max(1);
+    let chunk_lines = chunk_lines.max(1);
+    let slots = threads * SLOTS_PER_THREAD;
+    let counting = progress::enabled();
+
+    let (chunk_tx, chunk_rx) = sync_channel::<Chunk>(slots);
+    let chunk_rx = Mutex::new(chunk_rx);
+    let (done_tx, done_rx) = sync_channel::<(usize, Result<Vec<u8>, String>)>(slots);
+    let (slot_tx, slot_rx) = sync_channel::<()>(slots);
+    for _ in 0..slots {
+        let _ = slot_tx.send(());
+    }
+
+    std::thread::scope(|scope| {
+        let reader = scope.spawn(move || -> std::io::Result<()> {
+            let mut seq = 0;
+            // chunks never span two inputs, so every chunk belongs to exactly one source
+            for (name, input) in inputs {
+                let source: Arc<str> = Arc::from(name);
+                let mut reader = BufReader::with_capacity(1 << 16, input?);
+                let mut first_line = 1;
+                loop {
+                    let mut data = Vec::new();
+                    let mut n = 0;
+                    let read_error = |e: std::io::Error| std::io::Error::new(e.kind(), format!("Could not read {}: {}", source, e));
+                    while n < chunk_lines && reader.read_until(b'\n', &mut data).map_err(read_error)? > 0 {
+                        n += 1;
+                    }
+                    if n == 0 {
+                        break;
+                    }
+                    let chunk = Chunk {
+                        seq,
+                        source: source.clone(),
+                        first_line,
+                        data,
+                    };
+                    if slot_rx.recv().is_err() || chunk_tx.send(chunk).is_err() {
+                        return Ok(());
+                    }
+                    seq += 1;
+                    first_line += n;
+                }
+            }
+            Ok(())
+        });
+
+        for _ in 0..threads {
+            let done_tx = done_tx.clone();
+            let chunk_rx = &chunk_rx;
+            let process = &process;
+            scope.spawn(move || loop {
+                let next = chunk_rx.lock().unwrap_or_else(|p| p.into_inner()).recv();
+                let chunk = match next {
+                    Ok(next) => next,
+                    Err(_) => break,
+                };
+                let processed = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                    let mut out = Vec::with_capacity(chunk.data.len());
+                    let mut produced = vec![];
+                    chunk
+                        .data
+                        .split_inclusive(|b| *b == b'\n')
+                        .enumerate()
+                        .for_each(|(i, line)| match with_source {
+                            true => {
+                                produced.clear();
+                                process(&mut produced, line);
+                                if counting {
+                                    progress::line_done(line.len(), progress::count_lines(&produced));
+                                }
+                                let _ = write_tagged(&mut out, &chunk.source, chunk.first_line + i, &produced);
+                            }
+                            false => {
+                                let before = out.len();
+                                process(&mut out, line);
+                                if counting {
+                                    progress::line_done(line.len(), progress::count_lines(&out[before..]));
+                                }
+                            }
+                        });
+                    out
+                }));
+                let panicked = processed.is_err();
+                let processed = processed.map_err(|_| {
+                    format!(
+                        "processing {} panicked somewhere in lines {} to {}",
+                        chunk.source,
+                        chunk.first_line,
+                        chunk.first_line + chunk.data.split_inclusive(|b| *b == b'\n').count() - 1
+                    )
+                });
+                if done_tx.send((chunk.seq, processed)).is_err() || panicked {
+                    break;
+                }
+            });
+        }
+        drop(done_tx);
+
+        let written = (|| -> std::io::Result<()> {
+            let mut waiting = BTreeMap::new();
+            let mut next_seq = 0;
+            for (seq, out) in done_rx.iter() {
+                waiting.insert(seq, out);
+                while let Some(out) = waiting.remove(&next_seq) {
+                    output.write_all(&out.map_err(std::io::Error::other)?)?;
+                    next_seq += 1;
+                    let _ = slot_tx.send(());
+                }
+            }
+            output.flush()
+        })();
+        // if the output went away or a worker panicked, hang up on the reader and workers so they don't block forever
+        drop(done_rx);
+        drop(slot_tx);
+        let read = reader
+            .join()
+            .unwrap_or_else(|_| Err(std::io::Error::other("reader thread panicked")));
+        written.and(read)
+    })
+}
+
+#[test]
+fn test_par_lines_keeps_order() {
+    let input: String = (0..10000).map(|i| format!("line {}\n", i)).collect();
+    let mut output = vec![];
+    par_lines(input.as_bytes(), &mut output, 8, 7, |out, line| {
+        if line.ends_with(b"3\n") {
+            return;
+        }
+        out.extend(line.to_ascii_uppercase());
+    })
+    .unwrap();
+    let expected: String = (0..10000)
+        .filter(|i| i % 10 != 3)
+        .map(|i| format!("LINE {}\n", i))
+        .collect();
+    assert_eq!(String::from_utf8(output).unwrap(), expected);
+}
+
+#[test]
+fn test_par_lines_last_line_without_newline() {
+    let mut output = vec![];
+    par_lines("a\nb\nc".as_bytes(), &mut output, 3, 1, |out, line| out.extend(line)).unwrap();
+    assert_eq!(output, b"a\nb\nc");
+}
+
+#[test]
+fn test_par_readers_with_source() {
+    let inputs = vec![("a.txt", "x\ny\n"), ("b.txt", "z")].into_iter().map(|(name, text)| {
+        let reader: Box<dyn Read + Send> = Box::new(text.as_bytes());
+        (name.to_string(), Ok(reader))
+    });
+    let mut output = vec![];
+    par_readers(inputs, &mut output, 2, 1, true, |out, line| {
+        out.extend(line);
+        out.extend(line);
+    })
+    .unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "a.txt:1\tx\na.txt:1\tx\na.txt:2\ty\na.txt:2\ty\nb.txt:1\tzz\n"
+    );
+}
+
+#[test]
+fn test_par_sources_unreadable() {
+    let path = std::env::temp_dir().join(format!("par_sources_{}.txt", std::process::id()));
+    std::fs::write(&path, "x\n").unwrap();
+    let sources = crate::inputs::expand(&[path.display().to_string()]).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let mut output = vec![];
+    let err = par_sources(sources, &mut output, 2, 1, false, |out, line| out.extend(line)).unwrap_err();
+    assert!(err.to_string().starts_with("Could not open"), "{}", err);
+    assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+}
+
+#[test]
+fn test_par_lines_panic() {
+    // far more chunks than slots, so the run would hang if the panicked chunk were waited for
+    let input: String = (0..10000).map(|i| format!("line {}\n", i)).collect();
+    let mut output = vec![];
+    let err = par_lines(input.as_bytes(), &mut output, 4, 10, |out, line| {
+        if line == b"line 5003\n" {
+            panic!("bad line");
+        }
+        out.extend(line);
+    })
+    .unwrap_err();
+    assert_eq!(err.to_string(), "processing - panicked somewhere in lines 5001 to 5010");
+    assert!(String::from_utf8(output).unwrap().ends_with("line 4999\n"));
+}
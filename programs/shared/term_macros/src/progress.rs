@@ -0,0 +1,255 @@
+use std::collections::BTreeMap;
+use std::io::{IsTerminal, Read};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Mutex, Once, OnceLock};
+use std::time::{Duration, Instant};
+
+// --progress reports to stderr while a tool runs: lines read, bytes processed, lines emitted and dropped,
+// throughput, and an eta when the size of the input is known. readin!/filter_in! (and the par_ versions)
+// keep the counts themselves; tools that read some other way (e.g. over an mmap) call read_line/add_total_bytes.
+// everything here is a no-op unless --progress was given, so the counters cost one relaxed load otherwise.
+
+static ENABLED: OnceLock<bool> = OnceLock::new();
+static STARTED: OnceLock<Instant> = OnceLock::new();
+static START: Once = Once::new();
+static FINISHED: AtomicBool = AtomicBool::new(false);
+
+static LINES_READ: AtomicU64 = AtomicU64::new(0);
+static BYTES_READ: AtomicU64 = AtomicU64::new(0);
+// bytes of the input files as they are on disk, i.e. before decompression, for the eta
+static RAW_BYTES_READ: AtomicU64 = AtomicU64::new(0);
+static TOTAL_BYTES: AtomicU64 = AtomicU64::new(0);
+static EMITTED: AtomicU64 = AtomicU64::new(0);
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+static DROPPED_BY: Mutex<BTreeMap<&'static str, u64>> = Mutex::new(BTreeMap::new());
+// only one of the reporter and the summary gets to write at a time, and nothing is reported after the summary
+static PRINTING: Mutex<()> = Mutex::new(());
+
+const REPORT_EVERY: Duration = Duration::from_secs(1);
+// without a terminal there's no \r to redraw with, so print a full line less often
+const LOG_EVERY: Duration = Duration::from_secs(10);
+
+pub fn enabled() -> bool {
+    *ENABLED.get_or_init(|| {
+        !crate::server::serving() && crate::config::find_layered_flag("--progress").unwrap_or_else(|_| false)
+    })
+}
+
+/// Starts the reporter thread, if --progress was given. Safe to call more than once.
+pub fn start() {
+    if !enabled() {
+        return;
+    }
+    START.call_once(|| {
+        STARTED.get_or_init(Instant::now);
+        std::thread::spawn(|| {
+            let terminal = std::io::stderr().is_terminal();
+            let mut last = Instant::now();
+            loop {
+                std::thread::sleep(REPORT_EVERY);
+                if !terminal && last.elapsed() < LOG_EVERY {
+                    continue;
+                }
+                last = Instant::now();
+                let _lock = PRINTING.lock().unwrap_or_else(|p| p.into_inner());
+                if FINISHED.load(Ordering::Relaxed) {
+                    return;
+                }
+                match terminal {
+                    true => eprint!("\r\x1b[K{}", status()),
+                    false => eprintln!("{}", status()),
+                }
+            }
+        });
+    });
+}
+
+pub fn add_total_bytes(n: u64) {
+    if enabled() {
+        TOTAL_BYTES.fetch_add(n, Ordering::Relaxed);
+    }
+}
+
+pub fn read_raw_bytes(n: u64) {
+    if enabled() {
+        RAW_BYTES_READ.fetch_add(n, Ordering::Relaxed);
+    }
+}
+
+pub fn read_line(bytes: usize) {
+    if enabled() {
+        LINES_READ.fetch_add(1, Ordering::Relaxed);
+        BYTES_READ.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+}
+
+pub fn emitted(lines: usize) {
+    if enabled() {
+        EMITTED.fetch_add(lines as u64, Ordering::Relaxed);
+    }
+}
+
+pub fn dropped() {
+    if enabled() {
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Records which of a tool's filters dropped a line, for the summary. Doesn't count towards dropped() on its own.
+pub fn dropped_by(filter: &'static str) {
+    if enabled() {
+        *DROPPED_BY
+            .lock()
+            .unwrap_or_else(|p| p.into_inner())
+            .entry(filter)
+            .or_insert(0) += 1;
+    }
+}
+
+/// What one input line turned into: the number of lines written for it, or dropped if there weren't any.
+pub fn line_done(bytes: usize, lines: usize) {
+    if enabled() {
+        read_line(bytes);
+        match lines {
+            0 => dropped(),
+            n => emitted(n),
+        }
+    }
+}
+
+/// Lines in some output, counting a last line without its newline.
+pub fn count_lines(produced: &[u8]) -> usize {
+    let newlines = produced.iter().filter(|b| **b == b'\n').count();
+    newlines + (!produced.is_empty() && !produced.ends_with(b"\n")) as usize
+}
+
+pub fn human_bytes(bytes: f64) -> String {
+    let units = ["B", "KB", "MB", "GB", "TB"];
+    let mut size = bytes;
+    let mut unit = 0;
+    while size >= 1000.0 && unit < units.len() - 1 {
+        size /= 1000.0;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{} {}", size as u64, units[unit]),
+        _ => format!("{:.1} {}", size, units[unit]),
+    }
+}
+
+pub fn human_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match secs {
+        0..=59 => format!("{:.1}s", duration.as_secs_f64()),
+        60..=3599 => format!("{}m{:02}s", secs / 60, secs % 60),
+        _ => format!("{}h{:02}m", secs / 3600, secs / 60 % 60),
+    }
+}
+
+fn eta(done: u64, total: u64, elapsed: Duration) -> Option<Duration> {
+    if total == 0 || done == 0 || done > total {
+        return None;
+    }
+    let rate = done as f64 / elapsed.as_secs_f64().max(1e-9);
+    Some(Duration::from_secs_f64((total - done) as f64 / rate))
+}
+
+pub fn status() -> String {
+    status_line(true)
+}
+
+fn status_line(with_eta: bool) -> String {
+    let elapsed = STARTED.get().map(|s| s.elapsed()).unwrap_or_default();
+    let secs = elapsed.as_secs_f64().max(1e-9);
+    let lines = LINES_READ.load(Ordering::Relaxed);
+    let bytes = BYTES_READ.load(Ordering::Relaxed);
+    let mut status = format!(
+        "read {} lines ({}) in {}, {} lines/s, {}/s",
+        lines,
+        human_bytes(bytes as f64),
+        human_duration(elapsed),
+        (lines as f64 / secs) as u64,
+        human_bytes(bytes as f64 / secs)
+    );
+    let (emitted, dropped) = (EMITTED.load(Ordering::Relaxed), DROPPED.load(Ordering::Relaxed));
+    if emitted + dropped > 0 {
+        status.push_str(&format!("; emitted {}, dropped {}", emitted, dropped));
+    }
+    // the raw count is only kept for files read through --input/stdin; anything else reports the bytes it processed
+    let raw = RAW_BYTES_READ.load(Ordering::Relaxed);
+    let done = if raw > 0 { raw } else { bytes };
+    let total = TOTAL_BYTES.load(Ordering::Relaxed);
+    if let Some(eta) = eta(done, total, elapsed).filter(|_| with_eta) {
+        status.push_str(&format!(
+            "; {:.1}% done, eta {}",
+            done as f64 * 100.0 / total as f64,
+            human_duration(eta)
+        ));
+    }
+    status
+}
+
+pub fn summary() -> String {
+    let mut summary = format!("{}: {}\n", crate::config::binary_name(), status_line(false));
+    let dropped_by = DROPPED_BY.lock().unwrap_or_else(|p| p.into_inner());
+    dropped_by
+        .iter()
+        .for_each(|(filter, count)| summary.push_str(&format!("  dropped by {}: {}\n", filter, count)));
+    summary
+}
+
+/// Stops the reporter and prints the summary. Only the first call does anything.
+pub fn finish() {
+    if !enabled() {
+        return;
+    }
+    let _lock = PRINTING.lock().unwrap_or_else(|p| p.into_inner());
+    if FINISHED.swap(true, Ordering::Relaxed) {
+        return;
+    }
+    if std::io::stderr().is_terminal() {
+        eprint!("\r\x1b[K");
+    }
+    eprint!("{}", summary());
+}
+
+/// Counts the bytes read off disk, so the eta can be measured against file sizes even when the data is compressed.
+pub struct Counted<R: Read> {
+    pub inner: R,
+}
+
+impl<R: Read> Read for Counted<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        read_raw_bytes(n as u64);
+        Ok(n)
+    }
+}
+
+pub fn progress_help() -> String {
+    "--progress reports lines, bytes, throughput and an eta on stderr while running, and a summary at the end.\n".to_string()
+}
+
+#[test]
+fn test_human_units() {
+    assert_eq!(human_bytes(999.0), "999 B");
+    assert_eq!(human_bytes(20_893.0), "20.9 KB");
+    assert_eq!(human_bytes(3.5e9), "3.5 GB");
+    assert_eq!(human_duration(Duration::from_millis(1500)), "1.5s");
+    assert_eq!(human_duration(Duration::from_secs(192)), "3m12s");
+    assert_eq!(human_duration(Duration::from_secs(7380)), "2h03m");
+}
+
+#[test]
+fn test_count_lines() {
+    assert_eq!(count_lines(b""), 0);
+    assert_eq!(count_lines(b"one\n"), 1);
+    assert_eq!(count_lines(b"one\ntwo"), 2);
+}
+
+#[test]
+fn test_eta() {
+    assert_eq!(eta(25, 100, Duration::from_secs(10)), Some(Duration::from_secs(30)));
+    assert_eq!(eta(0, 100, Duration::from_secs(10)), None);
+    assert_eq!(eta(10, 0, Duration::from_secs(10)), None);
+}
@@ -0,0 +1,73 @@
+use crate::parse_args::Args;
+use crate::spec::ArgSpec;
+use miniserde::Deserialize;
+use owo_colors::OwoColorize;
+use std::io::{BufRead, IsTerminal, Write};
+
+/// Only prompt when someone's actually there to answer, so piped and scripted use stays as it was.
+pub fn is_interactive() -> bool {
+    std::io::stdin().is_terminal() && std::io::stderr().is_terminal() && !crate::server::serving()
+}
+
+/// Keeps asking until the answer parses as T. None if the input runs out first.
+pub fn read_answer<T: Deserialize>(
+    spec: &ArgSpec,
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+) -> Option<T> {
+    let mut line = String::new();
+    loop {
+        let _ = write!(output, "{} ({}): ", spec.flag().magenta(), spec.describe().cyan());
+        let _ = output.flush();
+        line.clear();
+        match input.read_line(&mut line) {
+            Ok(0) | Err(_) => return None,
+            Ok(_) => {}
+        }
+        let answer = line.trim();
+        if answer.is_empty() {
+            continue;
+        }
+        match Args::parse_arg::<T>(answer) {
+            Some(value) => return Some(value),
+            None => {
+                let _ = writeln!(
+                    output,
+                    "{}",
+                    format!("couldn't parse {:?} as {}", answer, spec.typ.unwrap_or_else(|| "bool")).red()
+                );
+            }
+        }
+    }
+}
+
+/// Asks for a missing required argument on stderr, or None if this isn't an interactive session.
+pub fn prompt_arg<T: Deserialize>(spec: &ArgSpec) -> Option<T> {
+    if !is_interactive() {
+        return None;
+    }
+    let stdin = std::io::stdin();
+    let mut input = stdin.lock();
+    read_answer(spec, &mut input, &mut std::io::stderr())
+}
+
+#[test]
+fn test_read_answer() {
+    let spec = ArgSpec {
+        name: "order",
+        typ: Some("Vec<usize>"),
+        default: None,
+        cond: None,
+        because: None,
+    };
+    let mut input = std::io::Cursor::new("\n2 0 1\n[2, 0, 1]\n");
+    let mut output = vec![];
+    let answer = read_answer::<Vec<usize>>(&spec, &mut input, &mut output);
+    assert_eq!(answer, Some(vec![2, 0, 1]));
+    let output = String::from_utf8(output).unwrap();
+    assert_eq!(output.matches("type: Vec<usize>, required").count(), 3);
+    assert!(output.contains("couldn't parse \"2 0 1\""));
+
+    let mut input = std::io::Cursor::new("nope\n");
+    assert_eq!(read_answer::<usize>(&spec, &mut input, &mut vec![]), None);
+}
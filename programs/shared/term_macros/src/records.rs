@@ -0,0 +1,626 @@
+use crate::inputs::{sources_or_exit, write_tagged};
+use crate::progress;
+use miniserde::json::{self, Value};
+use parsers::{parse_column_specs, resolve, ColumnSpec, ColumnSpecs, Index, Pick};
+use std::io::prelude::*;
+use std::io::BufReader;
+
+// filter_records!/readin_records! run a closure over the fields of each record rather than over raw lines:
+//   --record_format tsv|csv|jsonl   (default tsv; csv is RFC 4180, so quoted fields can hold commas, quotes and newlines)
+//   --fields "2 ..1 text"           which fields the closure gets, in the ColumnSpec grammar from experimental/parsers
+//   --header                        the first record of every input names the columns (jsonl records always do)
+//   --on_missing drop|empty|error   what happens to a record without one of the fields (default drop)
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Tsv,
+    Csv,
+    Jsonl,
+}
+
+impl Format {
+    pub fn from_name(name: &str) -> Result<Format, String> {
+        match name.to_lowercase().as_str() {
+            "tsv" => Ok(Format::Tsv),
+            "csv" => Ok(Format::Csv),
+            "jsonl" | "ndjson" => Ok(Format::Jsonl),
+            _ => Err(format!("--record_format needs one of tsv, csv, jsonl, not {:?}", name)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OnMissing {
+    Drop,
+    Empty,
+    Error,
+}
+
+impl OnMissing {
+    pub fn from_name(name: &str) -> Result<OnMissing, String> {
+        match name.to_lowercase().as_str() {
+            "drop" => Ok(OnMissing::Drop),
+            "empty" => Ok(OnMissing::Empty),
+            "error" => Ok(OnMissing::Error),
+            _ => Err(format!("--on_missing needs one of drop, empty, error, not {:?}", name)),
+        }
+    }
+}
+
+pub struct RecordOptions {
+    pub format: Format,
+    pub fields: ColumnSpecs,
+    pub header: bool,
+    pub on_missing: OnMissing,
+}
+
+impl RecordOptions {
+    /// Reads the record arguments from any layer. `default_fields` is what the tool picks when --fields isn't given.
+    pub fn from_args(default_fields: &str) -> Result<RecordOptions, String> {
+        let format = crate::config::find_layered_arg::<String>("--record_format")?;
+        let fields = crate::config::find_layered_arg::<String>("--fields")?;
+        let on_missing = crate::config::find_layered_arg::<String>("--on_missing")?;
+        Ok(RecordOptions {
+            format: Format::from_name(format.as_deref().unwrap_or_else(|| "tsv"))?,
+            fields: parse_column_specs(fields.as_deref().unwrap_or_else(|| default_fields))?,
+            header: crate::config::find_layered_flag("--header")?,
+            on_missing: OnMissing::from_name(on_missing.as_deref().unwrap_or_else(|| "drop"))?,
+        })
+    }
+
+    pub fn from_args_or_exit(default_fields: &str) -> RecordOptions {
+        RecordOptions::from_args(default_fields).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1)
+        })
+    }
+}
+
+pub struct Record {
+    /// exactly as it was read, line ending included, so filters can pass it through untouched
+    pub raw: Vec<u8>,
+    pub fields: Vec<String>,
+    /// jsonl keys, in the order they're written; tsv and csv records get their names from the header instead
+    pub names: Option<Vec<String>>,
+    /// jsonl only: which fields were json (numbers, bools, null, arrays, objects) rather than strings
+    pub json: Option<Vec<bool>>,
+}
+
+fn trim_line_ending(text: &str) -> &str {
+    let text = text.strip_suffix('\n').unwrap_or_else(|| text);
+    text.strip_suffix('\r').unwrap_or_else(|| text)
+}
+
+/// Whether a csv record is still inside a quoted field, i.e. continues on the next line. Only a quote at the start of
+/// a field opens one and `""` inside it is a quote, so a stray quote in an unquoted field doesn't run on.
+fn csv_open(text: &[u8]) -> bool {
+    let mut quoted = false;
+    let mut at_start = true;
+    let mut bytes = text.iter().peekable();
+    while let Some(b) = bytes.next() {
+        match (quoted, b) {
+            (false, b'"') if at_start => quoted = true,
+            (false, b',') => {
+                at_start = true;
+                continue;
+            }
+            (true, b'"') if bytes.peek() == Some(&&b'"') => {
+                bytes.next();
+            }
+            (true, b'"') => quoted = false,
+            _ => {}
+        }
+        at_start = false;
+    }
+    quoted
+}
+
+pub fn parse_csv(text: &str) -> Result<Vec<String>, String> {
+    let text = trim_line_ending(text);
+    let mut fields = vec![];
+    let mut field = String::new();
+    let mut chars = text.chars().peekable();
+    let mut quoted = false;
+    let mut at_start = true;
+    while let Some(c) = chars.next() {
+        match (quoted, c) {
+            (false, '"') if at_start => quoted = true,
+            (false, '"') => return Err(format!("stray quote in unquoted csv field {:?}", field)),
+            (true, '"') if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            (true, '"') => {
+                quoted = false;
+                if !matches!(chars.peek(), None | Some(',')) {
+                    return Err(format!("csv field {:?} continues after its closing quote", field));
+                }
+            }
+            (false, ',') => {
+                fields.push(std::mem::take(&mut field));
+                at_start = true;
+                continue;
+            }
+            _ => field.push(c),
+        }
+        at_start = false;
+    }
+    if quoted {
+        return Err("csv record ends inside a quoted field".to_string());
+    }
+    fields.push(field);
+    Ok(fields)
+}
+
+pub fn parse_tsv(text: &str) -> Vec<String> {
+    trim_line_ending(text).split('\t').map(|f| f.to_string()).collect()
+}
+
+/// The keys of a json object in the order they're written, which json::Object (a BTreeMap) doesn't keep.
+fn key_order(text: &str) -> Vec<String> {
+    let bytes = text.as_bytes();
+    let mut keys = vec![];
+    let mut depth = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'{' | b'[' => depth += 1,
+            b'}' | b']' => depth -= 1,
+            b'"' => {
+                let start = i;
+                i += 1;
+                while i < bytes.len() && bytes[i] != b'"' {
+                    i += if bytes[i] == b'\\' { 2 } else { 1 };
+                }
+                let is_key = depth == 1 && text[(i + 1).min(text.len())..].trim_start().starts_with(':');
+                if let (true, Ok(key)) = (is_key, json::from_str::<String>(&text[start..(i + 1).min(text.len())])) {
+                    keys.push(key);
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    keys
+}
+
+/// A jsonl record's keys and values in the order they're written, each with whether it was json rather than a string.
+/// String values are taken as they are, anything else as its json.
+pub fn parse_jsonl(text: &str) -> Result<Vec<(String, String, bool)>, String> {
+    let text = trim_line_ending(text);
+    let obj = match json::from_str::<Value>(text) {
+        Ok(Value::Object(obj)) => obj,
+        Ok(_) => return Err("jsonl record needs to be a json object".to_string()),
+        Err(_) => return Err("couldn't parse jsonl record".to_string()),
+    };
+    let mut names: Vec<String> = vec![];
+    for key in key_order(text) {
+        if obj.contains_key(&key) && !names.contains(&key) {
+            names.push(key);
+        }
+    }
+    // in case the scan missed any, they go at the end rather than getting lost
+    names.extend(obj.keys().filter(|k| !names.contains(k)).cloned().collect::<Vec<_>>());
+    Ok(names
+        .into_iter()
+        .map(|name| {
+            let (value, json) = match &obj[&name] {
+                Value::String(s) => (s.clone(), false),
+                other => (json::to_string(other), true),
+            };
+            (name, value, json)
+        })
+        .collect())
+}
+
+pub fn parse_record(format: Format, raw: Vec<u8>) -> Result<Record, String> {
+    let text = std::str::from_utf8(&raw).map_err(|_| "record isn't valid utf-8".to_string())?;
+    let (fields, names, json) = match format {
+        Format::Tsv => (parse_tsv(text), None, None),
+        Format::Csv => (parse_csv(text)?, None, None),
+        Format::Jsonl => {
+            let entries = parse_jsonl(text)?;
+            let names = entries.iter().map(|(name, _, _)| name.clone()).collect();
+            let json = entries.iter().map(|(_, _, json)| *json).collect();
+            (entries.into_iter().map(|(_, value, _)| value).collect(), Some(names), Some(json))
+        }
+    };
+    Ok(Record { raw, fields, names, json })
+}
+
+/// Reads one record at a time: a line, or for csv, as many lines as a quoted field spans.
+pub struct RecordReader<R: BufRead> {
+    input: R,
+    format: Format,
+    line_number: usize,
+}
+
+impl<R: BufRead> RecordReader<R> {
+    pub fn new(input: R, format: Format) -> RecordReader<R> {
+        RecordReader {
+            input,
+            format,
+            line_number: 0,
+        }
+    }
+
+    /// The record and the line it started on, None at the end of the input.
+    pub fn next_record(&mut self) -> Option<(usize, Result<Record, String>)> {
+        let mut raw = vec![];
+        let first_line = self.line_number + 1;
+        loop {
+            match self.input.read_until(b'\n', &mut raw) {
+                Ok(0) if raw.is_empty() => return None,
+                Ok(0) => break,
+                Ok(_) => self.line_number += 1,
+                Err(e) => return Some((first_line, Err(e.to_string()))),
+            }
+            if self.format != Format::Csv || !csv_open(&raw) {
+                break;
+            }
+        }
+        Some((first_line, parse_record(self.format, raw)))
+    }
+}
+
+pub enum Selected<'a> {
+    Fields(Vec<&'a str>),
+    Missing(Index),
+}
+
+/// A jsonl record's keys, plus any the spec names that this record doesn't have: in jsonl an absent key
+/// is a missing field (which --on_missing deals with), not a mistake in the spec.
+fn record_names(specs: &ColumnSpecs, record: &Record) -> Option<Vec<String>> {
+    record.names.as_ref().map(|names| {
+        let absent = specs.iter().filter_map(|spec| match spec {
+            ColumnSpec::Named(name) if !names.contains(name) => Some(name.clone()),
+            _ => None,
+        });
+        names.iter().cloned().chain(absent).collect()
+    })
+}
+
+/// The key of each field select picks out of a jsonl record, for writing them back out under the same keys, and
+/// whether its value was json rather than a string (a literal or missing field is a string).
+fn selected_keys(specs: &ColumnSpecs, record: &Record) -> Option<Vec<(String, bool)>> {
+    let names = record_names(specs, record)?;
+    let json = record.json.as_deref().unwrap_or_else(|| &[]);
+    let picked = resolve(specs, record.fields.len(), Some(&names)).ok()?;
+    Some(
+        picked
+            .iter()
+            .filter_map(|pick| match pick {
+                Pick::Column(i) => names.get(*i).map(|name| (name.clone(), json.get(*i).copied().unwrap_or_else(|| false))),
+                Pick::Missing(Index::Start(i)) => names.get(*i).map(|name| (name.clone(), false)),
+                Pick::Missing(Index::End(_)) => None,
+                Pick::Literal(literal) => Some((literal.to_string(), false)),
+            })
+            .collect(),
+    )
+}
+
+/// The names of the fields select picks out of a jsonl record, for writing them back out under the same keys.
+pub fn selected_names(specs: &ColumnSpecs, record: &Record) -> Option<Vec<String>> {
+    selected_keys(specs, record).map(|keys| keys.into_iter().map(|(name, _)| name).collect())
+}
+
+/// Picks the --fields out of a record, by position or by name, with any literals in the spec in their places.
+pub fn select<'a>(
+    specs: &'a ColumnSpecs,
+    record: &'a Record,
+    header: Option<&[String]>,
+    on_missing: OnMissing,
+) -> Result<Selected<'a>, String> {
+    let names = record_names(specs, record);
+    let picked = resolve(specs, record.fields.len(), names.as_deref().or(header))?;
+    let mut fields = Vec::with_capacity(picked.len());
+    for pick in picked {
+        match (pick, on_missing) {
+            (Pick::Column(i), _) => fields.push(record.fields[i].as_str()),
+            (Pick::Literal(literal), _) => fields.push(literal),
+            (Pick::Missing(_), OnMissing::Empty) => fields.push(""),
+            (Pick::Missing(index), _) => return Ok(Selected::Missing(index)),
+        }
+    }
+    Ok(Selected::Fields(fields))
+}
+
+/// Writes records back out in the input's format. What readin_records! hands its closure as the writer.
+pub struct RecordWriter<W: Write> {
+    output: W,
+    format: Format,
+    /// keys for jsonl output, when the fields written line up with them
+    pub names: Option<Vec<String>>,
+    /// which of those keys had json values rather than strings; a field written under one goes out as json again if
+    /// it still parses as json, so a number that's passed through stays a number
+    pub json: Option<Vec<bool>>,
+}
+
+pub fn csv_field(field: &str) -> std::borrow::Cow<'_, str> {
+    match field.contains(|c| c == ',' || c == '"' || c == '\n' || c == '\r') {
+        true => format!("\"{}\"", field.replace('"', "\"\"")).into(),
+        false => field.into(),
+    }
+}
+
+impl<W: Write> RecordWriter<W> {
+    pub fn new(output: W, format: Format) -> RecordWriter<W> {
+        RecordWriter {
+            output,
+            format,
+            names: None,
+            json: None,
+        }
+    }
+
+    /// Names the fields after the keys that the --fields picked out of a jsonl record, for writing them back out.
+    pub fn name_fields(&mut self, specs: &ColumnSpecs, record: &Record) {
+        let (names, json) = selected_keys(specs, record).map(|keys| keys.into_iter().unzip()).unzip();
+        self.names = names;
+        self.json = json;
+    }
+
+    pub fn write_record<S: AsRef<str>>(&mut self, fields: &[S]) -> std::io::Result<()> {
+        let fields: Vec<&str> = fields.iter().map(|f| f.as_ref()).collect();
+        match self.format {
+            Format::Tsv => writeln!(self.output, "{}", fields.join("\t")),
+            Format::Csv => {
+                let quoted: Vec<_> = fields.iter().map(|f| csv_field(f)).collect();
+                writeln!(self.output, "{}", quoted.join(","))
+            }
+            Format::Jsonl => match &self.names {
+                Some(names) if names.len() == fields.len() => {
+                    // written by hand, since json::Object would put the keys in alphabetical order
+                    let json = self.json.as_deref().unwrap_or_else(|| &[]);
+                    let mut written: Vec<&str> = vec![];
+                    let mut line = String::from("{");
+                    for (i, (name, field)) in names.iter().zip(fields.iter()).enumerate() {
+                        if written.contains(&name.as_str()) {
+                            continue;
+                        }
+                        if !written.is_empty() {
+                            line.push(',');
+                        }
+                        written.push(name);
+                        line.push_str(&json::to_string(name));
+                        line.push(':');
+                        match json.get(i).copied().unwrap_or_else(|| false) && json::from_str::<Value>(field).is_ok() {
+                            true => line.push_str(field),
+                            false => line.push_str(&json::to_string(field)),
+                        }
+                    }
+                    line.push('}');
+                    writeln!(self.output, "{}", line)
+                }
+                _ => writeln!(self.output, "{}", json::to_string(&fields)),
+            },
+        }
+    }
+}
+
+fn missing_error(name: &str, line: usize, column: Index) -> String {
+    format!("{}:{} has no column {}", name, line, column)
+}
+
+fn exit_with(e: String) -> ! {
+    eprintln!("{}", e);
+    std::process::exit(1)
+}
+
+/// Runs `process` over the selected fields of every record of every input, handing it a writer in the input's format.
+/// When it returns true and wrote nothing, the record is passed through as it was read (which is all filtering is).
+fn run<F>(options: &RecordOptions, filtering: bool, mut process: F)
+where
+    F: FnMut(&mut RecordWriter<Vec<u8>>, &Record, &[&str]) -> bool,
+{
+    let mut writer = RecordWriter::new(vec![], options.format);
+    let with_source = crate::inputs::with_source();
+    let mut output = std::io::BufWriter::new(crate::compression::stdout());
+    let mut wrote_header = false;
+    progress::start();
+    for source in sources_or_exit() {
+        let mut reader = RecordReader::new(BufReader::new(source.open_or_panic()), options.format);
+        let mut header: Option<Vec<String>> = None;
+        while let Some((line, record)) = reader.next_record() {
+            let record = match record {
+                Ok(record) => record,
+                Err(e) if options.on_missing == OnMissing::Error => exit_with(format!("{}:{}: {}", source.name, line, e)),
+                Err(_) => {
+                    progress::line_done(0, 0);
+                    progress::dropped_by("malformed record");
+                    continue;
+                }
+            };
+            if options.header && options.format != Format::Jsonl && header.is_none() {
+                // only the first input's header is written: filtering passes it through, mapping writes the selected names
+                if !wrote_header {
+                    wrote_header = true;
+                    let written = match filtering {
+                        true => output.write_all(&record.raw),
+                        false => match select(&options.fields, &record, Some(&record.fields), OnMissing::Empty) {
+                            Ok(Selected::Fields(names)) => {
+                                let mut header_writer = RecordWriter::new(&mut output, options.format);
+                                header_writer.write_record(&names)
+                            }
+                            _ => Ok(()),
+                        },
+                    };
+                    if written.is_err() {
+                        return;
+                    }
+                }
+                header = Some(record.fields);
+                continue;
+            }
+            let fields = match select(&options.fields, &record, header.as_deref(), options.on_missing) {
+                Ok(Selected::Fields(fields)) => fields,
+                Ok(Selected::Missing(column)) if options.on_missing == OnMissing::Error => {
+                    exit_with(missing_error(&source.name, line, column))
+                }
+                Ok(Selected::Missing(_)) => {
+                    progress::line_done(record.raw.len(), 0);
+                    progress::dropped_by("missing column");
+                    continue;
+                }
+                Err(e) => exit_with(e),
+            };
+            if options.format == Format::Jsonl {
+                writer.name_fields(&options.fields, &record);
+            }
+            if process(&mut writer, &record, &fields) && writer.output.is_empty() {
+                writer.output.extend_from_slice(&record.raw);
+                if !record.raw.ends_with(b"\n") {
+                    writer.output.push(b'\n');
+                }
+            }
+            progress::line_done(record.raw.len(), progress::count_lines(&writer.output));
+            let written = match with_source {
+                true => write_tagged(&mut output, &source.name, line, &writer.output),
+                false => output.write_all(&writer.output),
+            };
+            writer.output.clear();
+            if written.is_err() {
+                return;
+            }
+        }
+    }
+    let _ = output.flush();
+    progress::finish();
+}
+
+/// The json-rpc version of run: every line of a request is one record, and there's no header.
+fn serve<F>(options: &RecordOptions, mut process: F)
+where
+    F: FnMut(&mut RecordWriter<Vec<u8>>, &Record, &[&str]) -> bool,
+{
+    let mut writer = RecordWriter::new(vec![], options.format);
+    crate::server::serve(|lines: &[String]| {
+        let mut out = vec![];
+        for line in lines {
+            let record = match parse_record(options.format, line.clone().into_bytes()) {
+                Ok(record) => record,
+                Err(_) => continue,
+            };
+            let fields = match select(&options.fields, &record, None, options.on_missing) {
+                Ok(Selected::Fields(fields)) => fields,
+                _ => continue,
+            };
+            if options.format == Format::Jsonl {
+                writer.name_fields(&options.fields, &record);
+            }
+            if process(&mut writer, &record, &fields) && writer.output.is_empty() {
+                out.push(line.clone());
+            }
+            let written = String::from_utf8_lossy(&writer.output).to_string();
+            out.extend(written.lines().map(|l| l.to_string()));
+            writer.output.clear();
+        }
+        out
+    });
+}
+
+/// What filter_records! runs: keeps the records whose selected fields `keep` returns true for, exactly as they were.
+pub fn filter_records(options: &RecordOptions, mut keep: impl FnMut(&[&str]) -> bool) {
+    let process = |_: &mut RecordWriter<Vec<u8>>, _: &Record, fields: &[&str]| keep(fields);
+    match crate::server::serving() {
+        true => serve(options, process),
+        false => run(options, true, process),
+    }
+}
+
+/// What readin_records! runs: `process` writes whatever records it likes for each input record's selected fields.
+pub fn map_records(options: &RecordOptions, mut process: impl FnMut(&mut RecordWriter<Vec<u8>>, &[&str])) {
+    let process = |writer: &mut RecordWriter<Vec<u8>>, _: &Record, fields: &[&str]| {
+        process(writer, fields);
+        false
+    };
+    match crate::server::serving() {
+        true => serve(options, process),
+        false => run(options, false, process),
+    }
+}
+
+pub fn records_help() -> String {
+    "filter_records!/readin_records! tools take --record_format tsv|csv|jsonl, --fields (e.g. \"2 ..1 text 4..\"), --header and --on_missing drop|empty|error.\n".to_string()
+}
+
+#[test]
+fn test_parse_csv() {
+    assert_eq!(parse_csv("a,b,c\n").unwrap(), vec!["a", "b", "c"]);
+    assert_eq!(
+        parse_csv("\"x, y\",\"say \"\"hi\"\"\",\"two\nlines\",\r\n").unwrap(),
+        vec!["x, y", "say \"hi\"", "two\nlines", ""]
+    );
+    assert!(parse_csv("\"open").is_err());
+    assert!(parse_csv("a\"b").is_err());
+    assert!(parse_csv("\"a\"b").is_err());
+}
+
+#[test]
+fn test_record_reader() {
+    let input = "id,text\n1,\"spans\ntwo lines\"\n2,plain\n";
+    let mut reader = RecordReader::new(input.as_bytes(), Format::Csv);
+    let mut records = vec![];
+    while let Some((line, record)) = reader.next_record() {
+        records.push((line, record.unwrap().fields));
+    }
+    assert_eq!(records[1], (2, vec!["1".to_string(), "spans\ntwo lines".to_string()]));
+    assert_eq!(records[2], (4, vec!["2".to_string(), "plain".to_string()]));
+}
+
+#[test]
+fn test_select() {
+    // positions in jsonl follow the order the keys are written in, not the alphabet
+    let specs = parse_column_specs("1 0").unwrap();
+    let record = parse_record(Format::Jsonl, br#"{"text": "hello, \"you\"", "id": 3, "meta": {"a": 1}}"#.to_vec()).unwrap();
+    match select(&specs, &record, None, OnMissing::Drop) {
+        Ok(Selected::Fields(fields)) => assert_eq!(fields, vec!["3", "hello, \"you\""]),
+        _ => panic!("expected fields"),
+    }
+    assert_eq!(record.names, Some(vec!["text".to_string(), "id".to_string(), "meta".to_string()]));
+    assert_eq!(record.json, Some(vec![false, true, true]));
+    let specs = parse_column_specs("text lang").unwrap();
+    assert!(matches!(select(&specs, &record, None, OnMissing::Drop), Ok(Selected::Missing(Index::Start(3)))));
+    assert_eq!(selected_names(&specs, &record), Some(vec!["text".to_string(), "lang".to_string()]));
+    let record = parse_record(Format::Tsv, b"a\tb\n".to_vec()).unwrap();
+    let specs = parse_column_specs("1 2").unwrap();
+    assert!(matches!(select(&specs, &record, None, OnMissing::Drop), Ok(Selected::Missing(Index::Start(2)))));
+    match select(&specs, &record, None, OnMissing::Empty) {
+        Ok(Selected::Fields(fields)) => assert_eq!(fields, vec!["b", ""]),
+        _ => panic!("expected fields"),
+    }
+}
+
+#[test]
+fn test_record_writer() {
+    let mut writer = RecordWriter::new(vec![], Format::Csv);
+    writer.write_record(&["plain", "with, comma", "\"quoted\""]).unwrap();
+    assert_eq!(writer.output, b"plain,\"with, comma\",\"\"\"quoted\"\"\"\n");
+    let mut writer = RecordWriter::new(vec![], Format::Jsonl);
+    writer.names = Some(vec!["a".to_string(), "b".to_string()]);
+    writer.write_record(&["1", "2"]).unwrap();
+    writer.write_record(&["only one"]).unwrap();
+    assert_eq!(String::from_utf8(writer.output).unwrap(), "{\"a\":\"1\",\"b\":\"2\"}\n[\"only one\"]\n");
+
+    // keys keep the record's order, and values that were json go back out as json
+    let record = parse_record(Format::Jsonl, br#"{"z": 1.5, "text": "3", "ok": true, "gone": null}"#.to_vec()).unwrap();
+    let mut writer = RecordWriter::new(vec![], Format::Jsonl);
+    writer.name_fields(&parse_column_specs("z text ok gone").unwrap(), &record);
+    writer.write_record(&["1.5", "3", "true", "null"]).unwrap();
+    writer.write_record(&["not json", "3", "true", "null"]).unwrap();
+    assert_eq!(
+        String::from_utf8(writer.output).unwrap(),
+        "{\"z\":1.5,\"text\":\"3\",\"ok\":true,\"gone\":null}\n{\"z\":\"not json\",\"text\":\"3\",\"ok\":true,\"gone\":null}\n"
+    );
+}
+
+#[test]
+fn test_csv_open() {
+    assert!(!csv_open(b"a,b\n"));
+    assert!(csv_open(b"a,\"b\n"));
+    assert!(!csv_open(b"a,\"b\nc\"\n"));
+    // a stray quote in an unquoted field doesn't open anything, and "" inside a quoted one doesn't close it
+    assert!(!csv_open(b"5\" screen,b\n"));
+    assert!(csv_open(b"a,\"say \"\"hi\"\"\n"));
+    assert!(!csv_open(b"a,\"\"\n"));
+}
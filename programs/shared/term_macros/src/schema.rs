@@ -0,0 +1,207 @@
+use crate::completions::META_ARGS;
+use crate::spec::ArgSpec;
+use miniserde::json::{self, Array, Number, Object, Value};
+
+// the argument declarations are rust types and expressions, so this maps the common ones onto json schema
+// and keeps the original under x-rust-type / x-default-expr for anything it doesn't understand.
+// the arguments every tool takes (--input, --threads, the record options...) are listed after the tool's own,
+// so that a request using them still validates.
+
+fn string(s: &str) -> Value {
+    Value::String(s.to_string())
+}
+
+fn object(pairs: Vec<(&str, Value)>) -> Value {
+    Value::Object(pairs.into_iter().map(|(k, v)| (k.to_string(), v)).collect())
+}
+
+fn strip_wrapper<'a>(typ: &'a str, wrapper: &str) -> Option<&'a str> {
+    typ.strip_prefix(wrapper)
+        .and_then(|t| t.strip_prefix('<'))
+        .and_then(|t| t.strip_suffix('>'))
+}
+
+/// JSON Schema for a stringified rust type, e.g. "Vec<usize>" -> {"type": "array", "items": {"type": "integer", "minimum": 0}}
+pub fn type_schema(typ: &str) -> Value {
+    let typ = typ.replace(' ', "");
+    if let Some(inner) = strip_wrapper(&typ, "Option") {
+        return object(vec![(
+            "anyOf",
+            Value::Array(vec![type_schema(inner), object(vec![("type", string("null"))])].into_iter().collect()),
+        )]);
+    }
+    if let Some(inner) = strip_wrapper(&typ, "Vec").or_else(|| strip_wrapper(&typ, "HashSet")) {
+        return object(vec![("type", string("array")), ("items", type_schema(inner))]);
+    }
+    match typ.as_str() {
+        "u8" | "u16" | "u32" | "u64" | "u128" | "usize" => object(vec![
+            ("type", string("integer")),
+            ("minimum", Value::Number(Number::U64(0))),
+        ]),
+        "i8" | "i16" | "i32" | "i64" | "i128" | "isize" => object(vec![("type", string("integer"))]),
+        "f32" | "f64" => object(vec![("type", string("number"))]),
+        "bool" => object(vec![("type", string("boolean"))]),
+        "char" => object(vec![
+            ("type", string("string")),
+            ("minLength", Value::Number(Number::U64(1))),
+            ("maxLength", Value::Number(Number::U64(1))),
+        ]),
+        "String" | "&str" | "Filename" | "PathBuf" => object(vec![("type", string("string"))]),
+        _ => object(vec![]),
+    }
+}
+
+/// Tries to turn a stringified default expression into the json value it evaluates to.
+pub fn default_value(expr: &str) -> Option<Value> {
+    let expr = expr.trim();
+    let expr = [".to_string()", ".to_owned()", ".into()"]
+        .iter()
+        .fold(expr, |e, suffix| e.strip_suffix(suffix).unwrap_or_else(|| e));
+    match expr {
+        "None" => Some(Value::Null),
+        "String::new()" => Some(string("")),
+        "vec![]" | "Vec::new()" => Some(Value::Array(Array::new())),
+        _ => json::from_str::<Value>(expr).ok(),
+    }
+}
+
+pub fn arg_schema(spec: &ArgSpec) -> Value {
+    let mut schema = match spec.typ {
+        Some(typ) => match type_schema(typ) {
+            Value::Object(obj) => obj,
+            _ => Object::new(),
+        },
+        None => match type_schema("bool") {
+            Value::Object(mut obj) => {
+                obj.insert("default".to_string(), Value::Bool(false));
+                obj.insert("x-flag".to_string(), Value::Bool(true));
+                obj
+            }
+            _ => Object::new(),
+        },
+    };
+    if let Some(typ) = spec.typ {
+        schema.insert("x-rust-type".to_string(), string(typ));
+    }
+    let mut shown_default = spec.default.map(str::to_string);
+    if let Some(default) = spec.default {
+        match default_value(default) {
+            Some(value) => {
+                shown_default = Some(json::to_string(&value));
+                schema.insert("default".to_string(), value)
+            }
+            None => schema.insert("x-default-expr".to_string(), string(default)),
+        };
+    }
+    if let Some(cond) = spec.cond {
+        let mut failure = Object::new();
+        failure.insert("cond".to_string(), string(cond));
+        if let Some(because) = spec.because {
+            failure.insert("because".to_string(), string(because));
+        }
+        schema.insert("x-fails-if".to_string(), Value::Array(vec![Value::Object(failure)].into_iter().collect()));
+    }
+    schema.insert("description".to_string(), string(&spec.describe_default(shown_default.as_deref())));
+    Value::Object(schema)
+}
+
+pub fn json_schema(tool: &str, specs: &[ArgSpec]) -> Value {
+    object(vec![
+        ("$schema", string("https://json-schema.org/draft/2020-12/schema")),
+        ("title", string(tool)),
+        ("type", string("object")),
+        (
+            "properties",
+            Value::Object(
+                specs
+                    .iter()
+                    .chain(META_ARGS.iter().filter(|meta| !specs.iter().any(|s| s.name == meta.name)))
+                    .map(|s| (s.name.to_string(), arg_schema(s)))
+                    .collect(),
+            ),
+        ),
+        (
+            "required",
+            Value::Array(specs.iter().filter(|s| s.is_required()).map(|s| string(s.name)).collect()),
+        ),
+        ("additionalProperties", Value::Bool(false)),
+    ])
+}
+
+pub fn schema_string(tool: &str, specs: &[ArgSpec]) -> String {
+    json::to_string(&json_schema(tool, specs))
+}
+
+#[cfg(test)]
+const TEST_SPECS: &[ArgSpec] = &[
+    ArgSpec {
+        name: "order",
+        typ: Some("Vec<usize>"),
+        default: None,
+        cond: None,
+        because: None,
+    },
+    ArgSpec {
+        name: "sep",
+        typ: Some("String"),
+        default: Some("\"\\t\".to_string()"),
+        cond: None,
+        because: None,
+    },
+    ArgSpec {
+        name: "max_words",
+        typ: Some("usize"),
+        default: Some("1000000"),
+        cond: Some("max_words == 0"),
+        because: Some("max_words can't be zero"),
+    },
+    ArgSpec {
+        name: "max_chars",
+        typ: Some("usize"),
+        default: Some("max_words * 15"),
+        cond: None,
+        because: None,
+    },
+    ArgSpec {
+        name: "desired_lang",
+        typ: Some("Option<String>"),
+        default: Some("None"),
+        cond: None,
+        because: None,
+    },
+    ArgSpec {
+        name: "interactive",
+        typ: None,
+        default: None,
+        cond: None,
+        because: None,
+    },
+];
+
+#[test]
+fn test_schema() {
+    let schema = json_schema("dicer", TEST_SPECS);
+    let obj = match &schema {
+        Value::Object(obj) => obj,
+        _ => panic!("schema should be an object"),
+    };
+    let props = match obj.get("properties") {
+        Some(Value::Object(props)) => props,
+        _ => panic!("schema should have properties"),
+    };
+    let field = |name: &str, key: &str| match props.get(name) {
+        Some(Value::Object(p)) => p.get(key).map(json::to_string),
+        _ => panic!("missing property {}", name),
+    };
+    assert_eq!(field("order", "type").unwrap(), r#""array""#);
+    assert_eq!(field("sep", "default").unwrap(), r#""\t""#);
+    assert!(field("sep", "description").unwrap().contains(r#"default: \"\\t\""#));
+    assert_eq!(field("input", "x-rust-type").unwrap(), r#""Option<Vec<Filename>>""#);
+    assert_eq!(field("with_source", "type").unwrap(), r#""boolean""#);
+    assert_eq!(field("max_words", "default").unwrap(), "1000000");
+    assert_eq!(field("max_chars", "x-default-expr").unwrap(), r#""max_words * 15""#);
+    assert_eq!(field("desired_lang", "default").unwrap(), "null");
+    assert_eq!(field("interactive", "type").unwrap(), r#""boolean""#);
+    assert_eq!(obj.get("required").map(json::to_string).unwrap(), r#"["order"]"#);
+    assert!(schema_string("dicer", TEST_SPECS).contains(r#""because":"max_words can't be zero""#));
+}
@@ -0,0 +1,270 @@
+use crate::find_flag;
+use miniserde::json::{self, Value};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::io::prelude::*;
+use std::rc::Rc;
+use std::sync::Mutex;
+
+// --serve keeps a filter_in!/readin! tool resident, reading one json-rpc request per line from stdin:
+//   {"jsonrpc": "2.0", "id": 1, "method": "process", "params": {"args": {"max_words": 10}, "lines": ["..."]}}
+// and answering each with one line on stdout:
+//   {"jsonrpc": "2.0", "id": 1, "result": {"lines": ["..."]}}
+// whatever setup happens before filter_in!/readin! is only redone when a request's args differ from the
+// last ones, in which case serve() hands back to tool!, which rebinds the args and runs the body again.
+
+pub type RequestArgs = BTreeMap<String, String>;
+
+pub struct Request {
+    id: String,
+    args: RequestArgs,
+    lines: Vec<String>,
+}
+
+enum Parsed {
+    Process(Request),
+    Exit(String),
+}
+
+struct ServerState {
+    serving: bool,
+    restartable: bool,
+    rerun: bool,
+    args: RequestArgs,
+    previous_args: RequestArgs,
+    pending: Option<Request>,
+}
+
+static STATE: Mutex<ServerState> = Mutex::new(ServerState {
+    serving: false,
+    restartable: false,
+    rerun: false,
+    args: BTreeMap::new(),
+    previous_args: BTreeMap::new(),
+    pending: None,
+});
+
+fn with_state<T>(f: impl FnOnce(&mut ServerState) -> T) -> T {
+    let mut state = STATE.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    f(&mut state)
+}
+
+/// Called by tool!, which can set the body up again when a request's args change.
+pub fn start_if_requested() -> bool {
+    let serving = find_flag("--serve");
+    with_state(|state| {
+        state.serving = serving;
+        state.restartable = serving;
+    });
+    serving
+}
+
+pub fn serving() -> bool {
+    with_state(|state| state.serving) || find_flag("--serve")
+}
+
+/// The value the current request gave for an argument, which wins over every other layer.
+pub fn request_arg(argname: &str) -> Option<String> {
+    with_state(|state| state.args.get(argname.trim_start_matches('-')).cloned())
+}
+
+pub fn take_rerun() -> bool {
+    with_state(|state| std::mem::replace(&mut state.rerun, false))
+}
+
+/// If the args of a pending request were rejected, answers it with the error and goes back to the previous args.
+pub fn fail_pending(err: &str) -> bool {
+    let pending = with_state(|state| {
+        let pending = state.pending.take();
+        if pending.is_some() {
+            state.args = std::mem::take(&mut state.previous_args);
+            state.rerun = false;
+        }
+        pending
+    });
+    match pending {
+        Some(request) => {
+            respond_error(&request.id, -32602, err);
+            true
+        }
+        None => false,
+    }
+}
+
+pub fn server_help() -> String {
+    "--serve stays resident and answers json-rpc requests on stdin, one per line, e.g. {\"jsonrpc\": \"2.0\", \"id\": 1, \"method\": \"process\", \"params\": {\"args\": {}, \"lines\": [\"...\"]}}\n".to_string()
+}
+
+fn respond(id: &str, lines: &[String]) {
+    let stdout = std::io::stdout();
+    let mut lock = stdout.lock();
+    let _ = writeln!(
+        lock,
+        "{{\"jsonrpc\":\"2.0\",\"id\":{},\"result\":{{\"lines\":{}}}}}",
+        id,
+        json::to_string(lines)
+    );
+    let _ = lock.flush();
+}
+
+fn respond_error(id: &str, code: i64, message: &str) {
+    let stdout = std::io::stdout();
+    let mut lock = stdout.lock();
+    let _ = writeln!(
+        lock,
+        "{{\"jsonrpc\":\"2.0\",\"id\":{},\"error\":{{\"code\":{},\"message\":{}}}}}",
+        id,
+        code,
+        json::to_string(message)
+    );
+    let _ = lock.flush();
+}
+
+fn parse_request(line: &str) -> Result<Parsed, (String, i64, String)> {
+    let null = || "null".to_string();
+    let obj = match json::from_str::<Value>(line) {
+        Ok(Value::Object(obj)) => obj,
+        Ok(_) => return Err((null(), -32600, "request needs to be a json object".to_string())),
+        Err(_) => return Err((null(), -32700, "couldn't parse json".to_string())),
+    };
+    let id = obj.get("id").map(json::to_string).unwrap_or_else(null);
+    let method = match obj.get("method") {
+        Some(Value::String(method)) => method.as_str(),
+        _ => return Err((id, -32600, "request needs a method".to_string())),
+    };
+    match method {
+        "process" => {}
+        "exit" => return Ok(Parsed::Exit(id)),
+        _ => return Err((id, -32601, format!("unknown method {:?}, expected process or exit", method))),
+    }
+    let params = match obj.get("params") {
+        Some(Value::Object(params)) => params,
+        _ => return Err((id, -32602, "process needs params with lines".to_string())),
+    };
+    let args = match params.get("args") {
+        None | Some(Value::Null) => RequestArgs::new(),
+        Some(Value::Object(args)) => args
+            .iter()
+            .map(|(k, v)| (k.trim_start_matches('-').to_string(), json::to_string(v)))
+            .collect(),
+        Some(_) => return Err((id, -32602, "args needs to be an object".to_string())),
+    };
+    let lines = match params.get("lines") {
+        Some(Value::Array(lines)) => lines
+            .iter()
+            .map(|l| match l {
+                Value::String(s) => Some(s.clone()),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>(),
+        _ => None,
+    };
+    match lines {
+        Some(lines) => Ok(Parsed::Process(Request { id, args, lines })),
+        None => Err((id, -32602, "lines needs to be an array of strings".to_string())),
+    }
+}
+
+/// Answers requests until stdin closes, or until a request needs the tool to be set up again with different args.
+pub fn serve(mut process: impl FnMut(&[String]) -> Vec<String>) {
+    let pending = with_state(|state| {
+        state.serving = true;
+        state.pending.take()
+    });
+    if let Some(request) = pending {
+        respond(&request.id, &process(&request.lines));
+    }
+    let stdin = std::io::stdin();
+    let mut line = String::new();
+    loop {
+        line.clear();
+        match stdin.lock().read_line(&mut line) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {}
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+        let request = match parse_request(line.trim()) {
+            Ok(Parsed::Process(request)) => request,
+            Ok(Parsed::Exit(id)) => {
+                respond(&id, &[]);
+                break;
+            }
+            Err((id, code, message)) => {
+                respond_error(&id, code, &message);
+                continue;
+            }
+        };
+        let needs_setup = with_state(|state| state.args != request.args);
+        if !needs_setup {
+            respond(&request.id, &process(&request.lines));
+            continue;
+        }
+        let restartable = with_state(|state| state.restartable);
+        if !restartable {
+            respond_error(&request.id, -32602, "this tool doesn't take arguments per request");
+            continue;
+        }
+        with_state(|state| {
+            state.previous_args = std::mem::replace(&mut state.args, request.args.clone());
+            state.pending = Some(request);
+            state.rerun = true;
+        });
+        return;
+    }
+}
+
+/// What readin! hands to its closure as the writer while serving, so each request's output can be collected.
+#[derive(Clone, Default)]
+pub struct SharedBuf(Rc<RefCell<Vec<u8>>>);
+
+impl SharedBuf {
+    pub fn take_lines(&self) -> Vec<String> {
+        let bytes = std::mem::take(&mut *self.0.borrow_mut());
+        let text = String::from_utf8_lossy(&bytes);
+        let mut lines: Vec<String> = text.split('\n').map(|l| l.to_string()).collect();
+        if lines.last().map(|l| l.is_empty()).unwrap_or_else(|| false) {
+            lines.pop();
+        }
+        lines
+    }
+}
+
+impl Write for SharedBuf {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_parse_request() {
+    let parsed = parse_request(r#"{"jsonrpc": "2.0", "id": 7, "method": "process", "params": {"args": {"--max_words": 3, "sep": "|"}, "lines": ["a b", "c"]}}"#);
+    match parsed {
+        Ok(Parsed::Process(request)) => {
+            assert_eq!(request.id, "7");
+            assert_eq!(request.args.get("max_words").map(|s| s.as_str()), Some("3"));
+            assert_eq!(request.args.get("sep").map(|s| s.as_str()), Some("\"|\""));
+            assert_eq!(request.lines, vec!["a b".to_string(), "c".to_string()]);
+        }
+        _ => panic!("expected a process request"),
+    }
+    assert!(matches!(parse_request(r#"{"id": "x", "method": "exit"}"#), Ok(Parsed::Exit(id)) if id == "\"x\""));
+    assert!(matches!(parse_request("{nope"), Err((_, -32700, _))));
+    assert!(matches!(parse_request(r#"{"id": 1, "method": "frobnicate"}"#), Err((_, -32601, _))));
+    assert!(matches!(parse_request(r#"{"id": 1, "method": "process", "params": {"lines": [1]}}"#), Err((_, -32602, _))));
+}
+
+#[test]
+fn test_shared_buf() {
+    let buf = SharedBuf::default();
+    let mut writer = buf.clone();
+    writer.write_all(b"one\ntwo\n").unwrap();
+    assert_eq!(buf.take_lines(), vec!["one".to_string(), "two".to_string()]);
+    assert!(buf.take_lines().is_empty());
+}
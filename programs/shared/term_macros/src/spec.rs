@@ -0,0 +1,62 @@
+/// Everything tool! knows about one of its arguments, as written in the macro invocation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ArgSpec {
+    pub name: &'static str,
+    pub typ: Option<&'static str>,
+    pub default: Option<&'static str>,
+    pub cond: Option<&'static str>,
+    pub because: Option<&'static str>,
+}
+
+impl ArgSpec {
+    pub fn flag(&self) -> String {
+        format!("--{}", self.name)
+    }
+
+    pub fn is_flag(&self) -> bool {
+        self.typ.is_none()
+    }
+
+    pub fn is_required(&self) -> bool {
+        self.typ.is_some() && self.default.is_none()
+    }
+
+    pub fn is_bool(&self) -> bool {
+        self.typ.map(|t| t.replace(' ', "") == "bool").unwrap_or_else(|| false)
+    }
+
+    /// Guesses whether the argument is a file or directory, so completions can offer paths.
+    pub fn takes_path(&self) -> bool {
+        let typ = self.typ.unwrap_or_else(|| "");
+        typ.contains("Filename")
+            || typ.contains("Path")
+            || ["file", "dir", "path"].iter().any(|w| self.name.contains(w))
+    }
+
+    /// One-line summary of the type, default, failure condition and its reason, as shown in the help block.
+    pub fn describe(&self) -> String {
+        self.describe_default(self.default)
+    }
+
+    /// The same summary with the default shown as given, e.g. the json value it parses to rather than the rust.
+    pub fn describe_default(&self, default: Option<&str>) -> String {
+        let mut parts = vec![];
+        match self.typ {
+            Some(typ) => parts.push(format!("type: {}", typ)),
+            None => parts.push("flag".to_string()),
+        }
+        if let Some(default) = default {
+            parts.push(format!("default: {}", default));
+        }
+        if self.is_required() {
+            parts.push("required".to_string());
+        }
+        if let Some(cond) = self.cond {
+            parts.push(format!("fails if: {}", cond));
+        }
+        if let Some(because) = self.because {
+            parts.push(format!("because: {}", because));
+        }
+        parts.join(", ")
+    }
+}